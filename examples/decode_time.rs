@@ -0,0 +1,53 @@
+// Time decoding an elementary stream from the ring buffer and from a slice
+// usage: cargo run --release --example decode_time <file.m1v> [runs]
+
+use std::env;
+use std::fs;
+use std::time::{Duration, Instant};
+
+use ez_mpeg1::bitbuf::BitReader;
+use ez_mpeg1::video::{DecodeResult, Mpeg1Video};
+
+fn drain<B: BitReader>(vcodec: &mut Mpeg1Video<B>) -> usize {
+    let mut frames = 0;
+    while let DecodeResult::GotOneFrame = vcodec.decode() {
+        frames += 1;
+    }
+    frames
+}
+
+// pushed in 4 KiB pieces like a network or file reader would
+fn decode_ring(data: &[u8]) -> usize {
+    let mut vcodec = Mpeg1Video::new();
+    let mut frames = 0;
+    for piece in data.chunks(4096) {
+        vcodec.push(piece).expect("ring buffer full");
+        frames += drain(&mut vcodec);
+    }
+    vcodec.end_of_stream().expect("ring buffer full");
+    frames += drain(&mut vcodec);
+    frames + matches!(vcodec.flush(), DecodeResult::GotOneFrame) as usize
+}
+
+fn decode_slice(data: &[u8]) -> usize {
+    let mut vcodec = Mpeg1Video::from_slice(data);
+    let frames = drain(&mut vcodec);
+    frames + matches!(vcodec.flush(), DecodeResult::GotOneFrame) as usize
+}
+
+fn time(runs: usize, decode: impl Fn() -> usize) -> (Duration, usize) {
+    let start = Instant::now();
+    let frames = (0..runs).map(|_| decode()).sum();
+    (start.elapsed(), frames)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let data = fs::read(args.get(1).expect("no input file")).expect("Can't read input");
+    let runs = args.get(2).map_or(100, |runs| runs.parse().expect("runs must be a number"));
+
+    for (name, decode) in [("ring", decode_ring as fn(&[u8]) -> usize), ("slice", decode_slice)].iter() {
+        let (elapsed, frames) = time(runs, || decode(&data));
+        println!("{:>5}: {} frames in {} ms", name, frames, elapsed.as_millis());
+    }
+}
@@ -1,147 +1,312 @@
+// Bit level access used by the syntax parsers, over buffered or borrowed input
+pub trait BitReader {
+    // bit position of the next read from the first input byte on, it never wraps
+    fn pos(&self) -> usize;
+    fn bits_left(&self) -> usize;
+    fn skip(&mut self, count: usize) -> usize;
+    fn back(&mut self, count: usize);
+    // move to the next byte boundary
+    fn align(&mut self);
+    // the next `count` bits, at most 32, without consuming them,
+    // None if fewer are left
+    fn peek_checked(&mut self, count: usize) -> Option<u32>;
+    fn next_is_start(&mut self) -> bool;
+    fn include_code(&self, code:u32, count: usize) -> bool;
+    fn find_start(&mut self) -> bool;
+    fn find_start_code(&mut self, code: u32) -> bool;
+
+    // bits read since `from`, an earlier pos()
+    fn distance(&self, from: usize) -> usize {
+        self.pos() - from
+    }
+
+    fn has(&self, count: usize) -> bool {
+        count <= self.bits_left()
+    }
+
+    // None without consuming anything if fewer than `count` bits are left
+    fn read_checked(&mut self, count: usize) -> Option<u32> {
+        let value = self.peek_checked(count)?;
+        self.skip(count);
+        Some(value)
+    }
+
+    // 0 past the end of the data
+    fn peek(&mut self, count: usize) -> u32 {
+        self.peek_checked(count).unwrap_or(0)
+    }
+
+    fn read(&mut self, count: usize) -> u32 {
+        self.read_checked(count).unwrap_or(0)
+    }
+
+    // Walk the code table over up to 32 peeked bits and skip the whole code
+    // at once. None without consuming anything if the data ends inside the code.
+    fn read_vlc_checked(&mut self, vlc_table: &[(i16, i16)]) -> Option<i16> {
+        let (window, available) = vlc_window(self)?;
+        let mut state: (i16, i16) = (0, 0);
+        for length in 1..=available {
+            let bit = (window >> (32 - length)) & 1;
+            state = vlc_table[(state.0 + bit as i16) as usize];
+            if state.0 <= 0 {
+                self.skip(length);
+                return Some(state.1);
+            }
+        }
+        None
+    }
+
+    fn read_vlc_u16_checked(&mut self, vlc_table: &[(i16, u16)]) -> Option<u16> {
+        let (window, available) = vlc_window(self)?;
+        let mut state: (i16, u16) = (0, 0);
+        for length in 1..=available {
+            let bit = (window >> (32 - length)) & 1;
+            state = vlc_table[state.0 as usize + bit as usize];
+            if state.0 <= 0 {
+                self.skip(length);
+                return Some(state.1);
+            }
+        }
+        None
+    }
+
+    fn read_vlc(&mut self, vlc_table: &[(i16, i16)]) -> i16 {
+        self.read_vlc_checked(vlc_table).unwrap_or(0)
+    }
+
+    fn read_vlc_u16(&mut self, vlc_table: &[(i16, u16)]) -> u16 {
+        self.read_vlc_u16_checked(vlc_table).unwrap_or(0)
+    }
+}
+
+// The next bits left aligned in a word, with how many of them are valid
+fn vlc_window<R: BitReader + ?Sized>(reader: &mut R) -> Option<(u32, usize)> {
+    if let Some(window) = reader.peek_checked(32) {
+        return Some((window, 32));
+    }
+    let available = reader.bits_left().min(32);
+    if available == 0 {
+        return None;
+    }
+    Some((reader.peek_checked(available)? << (32 - available), available))
+}
+
+// Up to eight input bytes from byte `start_` on, first byte in the top bits,
+// so a peek is a single shift instead of a loop over bytes
+#[derive(Debug, Default)]
+struct BitCache {
+    bits_:  u64,
+    start_: usize,
+    len_:   usize,
+}
+
+impl BitCache {
+    // `count` bits from `offset` bits past the first cached byte, None if not all of them are cached
+    fn get(&self, offset: usize, count: usize) -> Option<u32> {
+        if offset + count > self.len_ * 8 {
+            return None;
+        }
+        if count == 0 {
+            return Some(0);
+        }
+        Some(((self.bits_ << offset) >> (64 - count)) as u32)
+    }
+
+    fn fill<I: Iterator<Item = u8>>(&mut self, start: usize, bytes: I) {
+        self.bits_ = 0;
+        self.start_ = start;
+        self.len_ = 0;
+        for byte in bytes.take(8) {
+            self.bits_ |= (byte as u64) << (56 - self.len_ * 8);
+            self.len_ += 1;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.len_ = 0;
+    }
+}
+
 #[derive(Debug)]
 pub struct RingBitBuffer {
     buffer_: Vec<u8>,
     rbi_:    usize,
     wi_:     usize,
     cap_:    usize,
+    limit_:  usize,
+    // bytes pushed since the start, including the ones read already
+    pushed_: usize,
+    cache_:  BitCache,
 }
 
 impl RingBitBuffer {
+    const DEFAULT_CAPACITY: usize = 1024 * 1024 * 4;
+    const DEFAULT_LIMIT: usize = 1024 * 1024 * 64;
+
+    // wrap a byte position below twice the capacity, without a division
     fn round(&self, p: usize) -> usize {
-        (p + self.cap_) % self.cap_
+        if p >= self.cap_ {
+            p - self.cap_
+        } else {
+            p
+        }
+    }
+
+    // the same for a bit position
+    fn round_bits(&self, p: usize) -> usize {
+        if p >= self.cap_ * 8 {
+            p - self.cap_ * 8
+        } else {
+            p
+        }
     }
 
     pub fn len(&self) -> usize {
-        if self.empty() {
+        if self.is_empty() {
             return 0;
         }
-        let has_bytes:usize = (self.wi_ + self.cap_ - (self.rbi_ >> 3) ) % self.cap_;
+        let has_bytes:usize = self.round(self.wi_ + self.cap_ - (self.rbi_ >> 3));
         let has_bits = has_bytes * 8 - (self.rbi_ & 0x07);
         has_bits
     }
 
-    pub fn has(&self, count: usize) -> bool {
-        if self.len() >= count {
-            return true;
-        }
-        return false;
-    }
-
     pub fn new() -> Self {
-        let mut buffer:Vec<u8> = Vec::new();
-        buffer.resize(1024*1024*4, 0);
+        Self::with_capacity(Self::DEFAULT_CAPACITY, Self::DEFAULT_LIMIT)
+    }
 
+    // Start with `capacity` bytes and grow on demand up to `limit` bytes
+    pub fn with_capacity(capacity: usize, limit: usize) -> Self {
+        let capacity = capacity.max(2);
         RingBitBuffer {
-            cap_:       buffer.len(),
-            buffer_:    buffer,
+            cap_:       capacity,
+            buffer_:    vec![0; capacity],
             rbi_:       0,
             wi_:        0,
+            limit_:     limit.max(capacity),
+            pushed_:    0,
+            cache_:     BitCache::default(),
         }
     }
 
-    pub fn empty(&self) -> bool {
+    pub fn is_empty(&self) -> bool {
         if (self.rbi_ >> 3) == self.wi_ {
             return true;
         }
         return false;
     }
 
-    pub fn push(&mut self, data: &[u8]) -> Option<usize> {
-        let mut byte_space:usize = ((self.rbi_>>3) + self.cap_ - self.wi_ ) % self.cap_;
-        if byte_space == 0 {
-            byte_space = self.cap_;
-        }
-        if byte_space < 1 + data.len() {
-            return None
+    // Bytes a push can take without growing, one byte stays free to tell full from empty
+    pub fn space(&self) -> usize {
+        let used = (self.wi_ + self.cap_ - (self.rbi_ >> 3)) % self.cap_;
+        self.cap_ - 1 - used
+    }
+
+    // Move the unread bytes to the start of a larger ring
+    fn grow(&mut self, needed: usize) {
+        let used = (self.wi_ + self.cap_ - (self.rbi_ >> 3)) % self.cap_;
+        let capacity = (self.cap_ * 2).max(used + needed + 1).min(self.limit_);
+        if capacity <= self.cap_ {
+            return;
         }
 
-        let mut wlen = 0;
-        while wlen < data.len() {
-            self.buffer_[self.wi_] = data[wlen];
-            self.wi_ = self.round(self.wi_ + 1);
-            wlen += 1;
+        let mut buffer = vec![0; capacity];
+        let start = self.rbi_ >> 3;
+        for (i, byte) in buffer.iter_mut().take(used).enumerate() {
+            *byte = self.buffer_[(start + i) % self.cap_];
         }
-        Some(wlen)
+        self.buffer_ = buffer;
+        self.cap_ = capacity;
+        self.rbi_ &= 0x07;
+        self.wi_ = used;
+        self.cache_.clear();
     }
 
-    pub fn back(&mut self, count: usize) {
-        self.rbi_ = (self.rbi_ + self.cap_ * 8 - count) % (self.cap_ * 8);
-    }
-    pub fn skip(&mut self, count: usize) -> usize {
-        if self.has(count) {
-            self.rbi_ = (self.rbi_ + count) % (self.cap_ * 8);
-            return count;
+    // Make room for `count` more bytes if the limit allows
+    pub fn reserve(&mut self, count: usize) -> bool {
+        if self.space() < count {
+            self.grow(count);
         }
-        0
+        self.space() >= count
     }
 
-    pub fn read(&mut self, mut count: usize) -> u32 {
-        if !self.has(count) {
-            return 0x00;
+    // Take as much of `data` as fits, growing the ring up to its limit first.
+    // None if the ring is full, a short count asks the caller to push the rest
+    // once more data has been decoded.
+    pub fn push(&mut self, data: &[u8]) -> Option<usize> {
+        self.reserve(data.len());
+        let wlen = self.space().min(data.len());
+        if wlen == 0 && !data.is_empty() {
+            return None;
         }
 
-        let mut value:u32 = 0x00;
-        while count > 0 {
-            let current_byte = self.buffer_[self.rbi_ >> 3] as u32;
-
-            let remaining = 8 - (self.rbi_ & 7);  // Remaining bits in byte
-            let read = if remaining < count {    // Bits in self run
-                remaining
-            } else {
-                count
-            };
-
-            let shift = remaining - read;
-            let mask = 0xff >> (8 - read);
+        for &byte in &data[..wlen] {
+            self.buffer_[self.wi_] = byte;
+            self.wi_ = self.round(self.wi_ + 1);
+        }
+        self.pushed_ += wlen;
+        // the new bytes may land where cached bytes were read long ago
+        self.cache_.clear();
+        Some(wlen)
+    }
+}
 
-            value = (value << read) | ((current_byte & (mask << shift)) >> shift);
+impl BitReader for RingBitBuffer {
+    // everything pushed minus what is left to read
+    fn pos(&self) -> usize {
+        self.pushed_ * 8 - self.len()
+    }
 
-            self.rbi_ = (self.rbi_ + read)  % (self.cap_ * 8);
+    fn bits_left(&self) -> usize {
+        self.len()
+    }
 
-            count = count - read;
-        }
-        value
+    fn back(&mut self, count: usize) {
+        self.rbi_ = self.round_bits(self.rbi_ + self.cap_ * 8 - count);
     }
 
-    pub fn read_vlc(&mut self, vlc_table: &[(i16, i16)]) -> i16 {
-        let mut state: (i16, i16) = (0, 0);
-        loop {
-            state = vlc_table[(state.0 + self.read(1) as i16) as usize];
-            if state.0 <= 0 {
-                break;
-            }
+    fn align(&mut self) {
+        self.rbi_ = self.round_bits(((self.rbi_ + 7) >> 3) << 3);
+    }
+    fn skip(&mut self, count: usize) -> usize {
+        if self.has(count) {
+            self.rbi_ = self.round_bits(self.rbi_ + count);
+            return count;
         }
-        return state.1
+        0
     }
 
-    pub fn read_vlc_u16(&mut self, vlc_table: &[(i16, u16)]) -> u16 {
-        let mut state: (i16, u16) = (0, 0);
-        loop {
-            state = vlc_table[state.0 as usize + self.read(1) as usize];
-            if state.0 <= 0 {
-                break;
-            }
+    fn peek_checked(&mut self, count: usize) -> Option<u32> {
+        // cached bytes were unread when they were cached, so they need no check
+        let byte = self.rbi_ >> 3;
+        let offset = self.round(byte + self.cap_ - self.cache_.start_) * 8 + (self.rbi_ & 7);
+        if let Some(value) = self.cache_.get(offset, count) {
+            return Some(value);
         }
-        return state.1
+        if !self.has(count) {
+            return None;
+        }
+
+        let buffer = &self.buffer_;
+        let cap = self.cap_;
+        let unread = self.round(self.wi_ + cap - byte);
+        self.cache_.fill(byte, (0..unread).map(|i| buffer[(byte + i) % cap]));
+        self.cache_.get(self.rbi_ & 7, count)
     }
 
-    pub fn next_is_start(&mut self) -> bool {
+    fn next_is_start(&mut self) -> bool {
         if !self.has(40) {
             return false;
         }
 
         let oldbi = self.rbi_;
-
-        self.rbi_ = ((self.rbi_ + 7) >> 3) << 3;
-        self.rbi_ = self.rbi_ % (self.cap_ * 8);
-
-        let ret = self.read(24) == 0x000001;
+        self.align();
+        let ret = self.peek(24) == 0x000001;
         self.rbi_ = oldbi;
 
         return ret;
     }
 
-    pub fn include_two_code(&self, code:u32 ) -> bool {
+    fn include_code(&self, code:u32, count: usize) -> bool {
         let mut pos = (self.rbi_ + 7) >> 3;
         let mut pattern:u32 = 0xFFFFFFFF;
         let mut times = 0;
@@ -151,7 +316,7 @@ impl RingBitBuffer {
 
             if pattern == code {
                 times += 1;
-                if times == 2 {
+                if times == count {
                     return true;
                 }
             }
@@ -159,17 +324,14 @@ impl RingBitBuffer {
         return false;
     }
 
-    pub fn find_start(&mut self) -> bool {
-        // aligen to byte
-        self.rbi_ = ((self.rbi_ + 7) >> 3) << 3;
-        self.rbi_ = self.rbi_ % (self.cap_ * 8);
+    fn find_start(&mut self) -> bool {
+        self.align();
 
         let mut pattern:u32 = 0xFFFFFF00;
         while (self.rbi_ >> 3) != self.wi_ {
             pattern = (pattern << 8) | (self.buffer_[self.rbi_>>3] as u32);
 
-            self.rbi_ += 8;
-            self.rbi_ = self.rbi_ % (self.cap_ * 8);
+            self.rbi_ = self.round_bits(self.rbi_ + 8);
 
             if (pattern & 0x00FFFFFF) == 0x000001 {
                 return true;
@@ -178,17 +340,14 @@ impl RingBitBuffer {
         return false;
     }
 
-    pub fn find_start_code(&mut self, code: u32) -> bool {
-        // aligen to byte
-        self.rbi_ = ((self.rbi_ + 7) >> 3) << 3;
-        self.rbi_ = self.rbi_ % (self.cap_ * 8);
+    fn find_start_code(&mut self, code: u32) -> bool {
+        self.align();
 
         let mut pattern:u32 = 0xFFFFFFFF;
         while (self.rbi_ >> 3) != self.wi_ {
             pattern = (pattern << 8) | (self.buffer_[self.rbi_>>3] as u32);
 
-            self.rbi_ += 8;
-            self.rbi_ = self.rbi_ % (self.cap_ * 8);
+            self.rbi_ = self.round_bits(self.rbi_ + 8);
 
             if pattern == code {
                 return true;
@@ -199,70 +358,238 @@ impl RingBitBuffer {
     }
 }
 
+impl Default for RingBitBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Reads straight from a borrowed slice, followed by an optional tail such as
+// an end code, so input already in memory is never copied
 pub struct BitBuffer<'a> {
-    _buf:  &'a [u8],
-    _bi:   usize,
+    buffer_: &'a [u8],
+    tail_:   &'a [u8],
+    rbi_:    usize,
+    len_:    usize,
+    cache_:  BitCache,
 }
 
 impl<'a> BitBuffer<'a> {
-    pub fn len(&self) -> usize {
-        self._buf.len()
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self::with_tail(buf, &[])
     }
-    pub fn new(buf: &'a[u8]) -> Self {
+
+    pub fn with_tail(buf: &'a [u8], tail: &'a [u8]) -> Self {
         BitBuffer::<'a> {
-            _buf: buf,
-            _bi:  0
+            buffer_: buf,
+            tail_:   tail,
+            rbi_:    0,
+            len_:    buf.len() + tail.len(),
+            cache_:  BitCache::default(),
         }
     }
-    pub fn pos(&self) -> usize {
-        self._bi
+
+    // bytes of input, read or not
+    pub fn len(&self) -> usize {
+        self.len_
     }
-    pub fn has(&self, count: usize) -> bool {
-        if count <= self._buf.len() * 8 - self._bi {
-            return true;
+
+    pub fn is_empty(&self) -> bool {
+        self.len_ == 0
+    }
+
+    fn byte(&self, pos: usize) -> u32 {
+        if pos < self.buffer_.len() {
+            self.buffer_[pos] as u32
+        } else {
+            self.tail_[pos - self.buffer_.len()] as u32
         }
-        return false;
     }
-    pub fn skip(&mut self, count: usize) -> usize {
+}
+
+impl<'a> BitReader for BitBuffer<'a> {
+    fn pos(&self) -> usize {
+        self.rbi_
+    }
+
+    fn bits_left(&self) -> usize {
+        (self.len_ * 8).saturating_sub(self.rbi_)
+    }
+
+    fn skip(&mut self, count: usize) -> usize {
         if self.has(count) {
-            self._bi = self._bi + count;
+            self.rbi_ += count;
             return count;
         }
         0
     }
-    pub fn back(&mut self, count: usize) {
-        if self._bi >= count {
-            self._bi = self._bi - count;
+
+    fn back(&mut self, count: usize) {
+        if self.rbi_ >= count {
+            self.rbi_ -= count;
         }
     }
-    pub fn read(&mut self, mut count: usize) -> Option<u32> {
+
+    fn align(&mut self) {
+        self.rbi_ = ((self.rbi_ + 7) >> 3) << 3;
+    }
+
+    fn peek_checked(&mut self, count: usize) -> Option<u32> {
+        let byte = self.rbi_ >> 3;
+        if byte >= self.cache_.start_ {
+            let offset = (byte - self.cache_.start_) * 8 + (self.rbi_ & 7);
+            if let Some(value) = self.cache_.get(offset, count) {
+                return Some(value);
+            }
+        }
         if !self.has(count) {
             return None;
         }
-        let mut value:u32 = 0x00;
 
-        while count > 0 {
-            let current_byte = self._buf[self._bi >> 3] as u32;
+        let (buffer, tail) = (self.buffer_, self.tail_);
+        let rest = buffer.iter().chain(tail.iter()).skip(byte).copied();
+        self.cache_.fill(byte, rest);
+        self.cache_.get(self.rbi_ & 7, count)
+    }
 
-            let remaining = 8 - (self._bi & 7);  // Remaining bits in byte
-            let read = if remaining < count {    // Bits in self run
-                remaining
-            } else {
-                count
-            };
+    fn next_is_start(&mut self) -> bool {
+        if !self.has(40) {
+            return false;
+        }
+
+        let oldbi = self.rbi_;
+        self.align();
+        let ret = self.peek(24) == 0x000001;
+        self.rbi_ = oldbi;
+
+        ret
+    }
+
+    fn include_code(&self, code:u32, count: usize) -> bool {
+        let mut pattern:u32 = 0xFFFFFFFF;
+        let mut times = 0;
+        for pos in ((self.rbi_ + 7) >> 3)..self.len_ {
+            pattern = (pattern << 8) | self.byte(pos);
+
+            if pattern == code {
+                times += 1;
+                if times == count {
+                    return true;
+                }
+            }
+        }
+        false
+    }
 
-            let shift = remaining - read;
-            let mask = 0xff >> (8 - read);
+    fn find_start(&mut self) -> bool {
+        self.align();
 
-            value = (value << read) | ((current_byte & (mask << shift)) >> shift);
+        let mut pattern:u32 = 0xFFFFFF00;
+        while (self.rbi_ >> 3) < self.len_ {
+            pattern = (pattern << 8) | self.byte(self.rbi_ >> 3);
+            self.rbi_ += 8;
 
-            self._bi = self._bi + read;
-            count = count - read;
+            if (pattern & 0x00FFFFFF) == 0x000001 {
+                return true;
+            }
         }
+        false
+    }
 
-        Some(value)
+    fn find_start_code(&mut self, code: u32) -> bool {
+        self.align();
+
+        let mut pattern:u32 = 0xFFFFFFFF;
+        while (self.rbi_ >> 3) < self.len_ {
+            pattern = (pattern << 8) | self.byte(self.rbi_ >> 3);
+            self.rbi_ += 8;
+
+            if pattern == code {
+                return true;
+            }
+        }
+        false
     }
 }
 
+// Builds a bitstream most significant bit first, the counterpart of the readers
+#[derive(Debug, Default)]
+pub struct BitWriter {
+    buffer_: Vec<u8>,
+    // bits not yet in a whole byte, in the low `cached_` bits
+    cache_:  u64,
+    cached_: usize,
+}
 
+impl BitWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
+    // bits written so far
+    pub fn pos(&self) -> usize {
+        self.buffer_.len() * 8 + self.cached_
+    }
+
+    pub fn is_aligned(&self) -> bool {
+        self.cached_ == 0
+    }
+
+    // the low `count` bits of `value`, at most 32
+    pub fn write(&mut self, value: u32, count: usize) {
+        if count == 0 {
+            return;
+        }
+        let value = value as u64 & ((1u64 << count) - 1);
+        self.cache_ = (self.cache_ << count) | value;
+        self.cached_ += count;
+        while self.cached_ >= 8 {
+            self.cached_ -= 8;
+            self.buffer_.push((self.cache_ >> self.cached_) as u8);
+        }
+    }
+
+    // The code of `value` in a table from vlc::encode_table(), false if the
+    // table has no code for it
+    pub fn write_vlc<T: Copy + Ord>(&mut self, codes: &[(T, u32, usize)], value: T) -> bool {
+        match codes.binary_search_by_key(&value, |&(value, _, _)| value) {
+            Ok(index) => {
+                let (_, code, length) = codes[index];
+                self.write(code, length);
+                true
+            },
+            Err(_) => false,
+        }
+    }
+
+    // pad with zero bits to the next byte boundary
+    pub fn align(&mut self) {
+        if self.cached_ > 0 {
+            self.write(0, 8 - self.cached_);
+        }
+    }
+
+    // `count` bytes of `byte` from the next byte boundary, zero bytes before a
+    // start code or 0xFF in a PES header
+    pub fn stuff(&mut self, byte: u8, count: usize) {
+        self.align();
+        self.buffer_.resize(self.buffer_.len() + count, byte);
+    }
+
+    // byte aligned 0x000001 and the start code value
+    pub fn write_start_code(&mut self, code: u8) {
+        self.align();
+        self.buffer_.extend_from_slice(&[0x00, 0x00, 0x01, code]);
+    }
+
+    // the whole bytes written so far
+    pub fn data(&self) -> &[u8] {
+        &self.buffer_
+    }
+
+    // all bytes, the last one padded with zero bits
+    pub fn finish(mut self) -> Vec<u8> {
+        self.align();
+        self.buffer_
+    }
+}
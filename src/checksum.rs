@@ -0,0 +1,183 @@
+// Checksums of output data, computed in the crate to stay free of dependencies
+
+use crate::video::{Plane, VideoFrame};
+
+// CRC-32 of ISO 3309 as used by PNG and zlib, reflected polynomial 0xEDB88320
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+// continue a crc32() over more data
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+// CRC-32 of ISO/IEC 13818-1 Annex A as used by PSI sections, MSB first
+// without reflection or final inversion. Over a whole section with its
+// CRC_32 field it gives 0.
+pub fn crc32_mpeg(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04C1_1DB7 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+// Adler-32 of RFC 1950
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes keep the sums below 2^32 between reductions
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+// MD5 of RFC 1321, fed in pieces
+pub struct Md5 {
+    state_:  [u32; 4],
+    block_:  [u8; 64],
+    filled_: usize,
+    length_: u64,
+}
+
+static MD5_SHIFT: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+// floor(abs(sin(i + 1)) * 2^32)
+static MD5_K: [u32; 64] = [
+    0xD76A_A478, 0xE8C7_B756, 0x2420_70DB, 0xC1BD_CEEE,
+    0xF57C_0FAF, 0x4787_C62A, 0xA830_4613, 0xFD46_9501,
+    0x6980_98D8, 0x8B44_F7AF, 0xFFFF_5BB1, 0x895C_D7BE,
+    0x6B90_1122, 0xFD98_7193, 0xA679_438E, 0x49B4_0821,
+    0xF61E_2562, 0xC040_B340, 0x265E_5A51, 0xE9B6_C7AA,
+    0xD62F_105D, 0x0244_1453, 0xD8A1_E681, 0xE7D3_FBC8,
+    0x21E1_CDE6, 0xC337_07D6, 0xF4D5_0D87, 0x455A_14ED,
+    0xA9E3_E905, 0xFCEF_A3F8, 0x676F_02D9, 0x8D2A_4C8A,
+    0xFFFA_3942, 0x8771_F681, 0x6D9D_6122, 0xFDE5_380C,
+    0xA4BE_EA44, 0x4BDE_CFA9, 0xF6BB_4B60, 0xBEBF_BC70,
+    0x289B_7EC6, 0xEAA1_27FA, 0xD4EF_3085, 0x0488_1D05,
+    0xD9D4_D039, 0xE6DB_99E5, 0x1FA2_7CF8, 0xC4AC_5665,
+    0xF429_2244, 0x432A_FF97, 0xAB94_23A7, 0xFC93_A039,
+    0x655B_59C3, 0x8F0C_CC92, 0xFFEF_F47D, 0x8584_5DD1,
+    0x6FA8_7E4F, 0xFE2C_E6E0, 0xA301_4314, 0x4E08_11A1,
+    0xF753_7E82, 0xBD3A_F235, 0x2AD7_D2BB, 0xEB86_D391
+];
+
+impl Md5 {
+    pub fn new() -> Self {
+        Md5 {
+            state_:  [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476],
+            block_:  [0; 64],
+            filled_: 0,
+            length_: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length_ += data.len() as u64;
+        while !data.is_empty() {
+            let take = (64 - self.filled_).min(data.len());
+            self.block_[self.filled_..self.filled_ + take].copy_from_slice(&data[..take]);
+            self.filled_ += take;
+            data = &data[take..];
+            if self.filled_ == 64 {
+                let block = self.block_;
+                self.compress(&block);
+                self.filled_ = 0;
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> [u8; 16] {
+        let bits = self.length_.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.filled_ != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_le_bytes());
+
+        let mut digest = [0u8; 16];
+        for (out, word) in digest.chunks_mut(4).zip(self.state_.iter()) {
+            out.copy_from_slice(&word.to_le_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut m = [0u32; 16];
+        for (word, bytes) in m.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
+        let [mut a, mut b, mut c, mut d] = self.state_;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let sum = a.wrapping_add(f).wrapping_add(MD5_K[i]).wrapping_add(m[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(sum.rotate_left(MD5_SHIFT[(i / 16) * 4 + i % 4]));
+        }
+        self.state_[0] = self.state_[0].wrapping_add(a);
+        self.state_[1] = self.state_[1].wrapping_add(b);
+        self.state_[2] = self.state_[2].wrapping_add(c);
+        self.state_[3] = self.state_[3].wrapping_add(d);
+    }
+}
+
+impl Default for Md5 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn md5(data: &[u8]) -> [u8; 16] {
+    let mut md5 = Md5::new();
+    md5.update(data);
+    md5.finish()
+}
+
+pub fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Over the visible Y, Cb and Cr samples of a frame, like ffmpeg's framemd5
+pub fn frame_md5(frame: &VideoFrame) -> [u8; 16] {
+    let mut md5 = Md5::new();
+    for &plane in Plane::ALL.iter() {
+        for row in frame.visible(plane) {
+            md5.update(row);
+        }
+    }
+    md5.finish()
+}
+
+pub fn frame_crc32(frame: &VideoFrame) -> u32 {
+    let mut crc = 0;
+    for &plane in Plane::ALL.iter() {
+        for row in frame.visible(plane) {
+            crc = crc32_update(crc, row);
+        }
+    }
+    crc
+}
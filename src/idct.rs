@@ -0,0 +1,253 @@
+// Inverse DCT of dequantized 8x8 blocks, coefficients and samples in raster order
+// IEEE Std 1180-1990: http://standards.ieee.org/standard/1180-1990.html
+
+static MP1V_PREMULTIPLIER_MATRIX: [u8; 64] = [
+    32, 44, 42, 38, 32, 25, 17,  9,
+    44, 62, 58, 52, 44, 35, 24, 12,
+    42, 58, 55, 49, 42, 33, 23, 12,
+    38, 52, 49, 44, 38, 30, 20, 10,
+    32, 44, 42, 38, 32, 25, 17,  9,
+    25, 35, 33, 30, 25, 20, 14,  7,
+    17, 24, 23, 20, 17, 14,  9,  5,
+     9, 12, 12, 10,  9,  7,  5,  2
+];
+
+// c(u)/2 * cos((2x + 1) * u * PI / 16), indexed [u][x]
+static IDCT_COSINE: [[f64; 8]; 8] = [
+    [  0.3535533905932738,   0.3535533905932738,   0.3535533905932738,   0.3535533905932738,   0.3535533905932738,   0.3535533905932738,   0.3535533905932738,   0.3535533905932738],
+    [  0.4903926402016152,   0.4157348061512726,  0.27778511650980114,  0.09754516100806417,  -0.0975451610080641,   -0.277785116509801,  -0.4157348061512727,  -0.4903926402016152],
+    [ 0.46193976625564337,  0.19134171618254492, -0.19134171618254486, -0.46193976625564337,  -0.4619397662556434, -0.19134171618254517,    0.191341716182545,  0.46193976625564326],
+    [  0.4157348061512726,  -0.0975451610080641,  -0.4903926402016152,  -0.2777851165098011,   0.2777851165098009,   0.4903926402016152,  0.09754516100806439, -0.41573480615127256],
+    [  0.3535533905932738, -0.35355339059327373, -0.35355339059327384,   0.3535533905932737,  0.35355339059327384, -0.35355339059327334, -0.35355339059327356,   0.3535533905932733],
+    [ 0.27778511650980114,  -0.4903926402016152,  0.09754516100806415,  0.41573480615127273, -0.41573480615127256, -0.09754516100806401,   0.4903926402016153, -0.27778511650980076],
+    [ 0.19134171618254492,  -0.4619397662556434,  0.46193976625564326, -0.19134171618254495, -0.19134171618254528,  0.46193976625564337,  -0.4619397662556432,  0.19134171618254478],
+    [ 0.09754516100806417,  -0.2777851165098011,  0.41573480615127273,  -0.4903926402016153,   0.4903926402016152,  -0.4157348061512725,  0.27778511650980076, -0.09754516100806429],
+];
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum IdctMode {
+    // AAN integer transform on premultiplied coefficients
+    #[default]
+    Fast,
+    // Separable transform in double precision, rounded to nearest
+    Reference,
+}
+
+pub fn idct(mode: IdctMode, block: &mut [i32; 64]) {
+    match mode {
+        IdctMode::Fast => fast(block),
+        IdctMode::Reference => reference(block),
+    }
+}
+
+pub fn fast(block: &mut [i32; 64]) {
+    let mut b1:i32;
+    let mut b3:i32;
+    let mut b4:i32;
+    let mut b6:i32;
+    let mut b7:i32;
+    let mut tmp1:i32;
+    let mut tmp2:i32;
+    let mut m0:i32;
+    let mut x0:i32;
+    let mut x1:i32;
+    let mut x2:i32;
+    let mut x3:i32;
+    let mut x4:i32;
+    let mut y3:i32;
+    let mut y4:i32;
+    let mut y5:i32;
+    let mut y6:i32;
+    let mut y7:i32;
+
+    for (coeff, premultiplier) in block.iter_mut().zip(MP1V_PREMULTIPLIER_MATRIX.iter()) {
+        *coeff *= *premultiplier as i32;
+    }
+
+    // Transform columns
+    for i in 0..8 {
+        b1 = block[4 * 8 + i];
+        b3 = block[2 * 8 + i] + block[6 * 8 + i];
+        b4 = block[5 * 8 + i] - block[3 * 8 + i];
+        tmp1 = block[8 + i] + block[7 * 8 + i];
+        tmp2 = block[3 * 8 + i] + block[5 * 8 + i];
+        b6 = block[8 + i] - block[7 * 8 + i];
+        b7 = tmp1 + tmp2;
+        m0 = block[i];
+        x4 = ((b6 * 473 - b4 * 196 + 128) >> 8) - b7;
+        x0 = x4 - (((tmp1 - tmp2) * 362 + 128) >> 8);
+        x1 = m0 - b1;
+        x2 = (((block[2 * 8 + i] - block[6 * 8 + i]) * 362 + 128) >> 8) - b3;
+        x3 = m0 + b1;
+        y3 = x1 + x2;
+        y4 = x3 + b3;
+        y5 = x1 - x2;
+        y6 = x3 - b3;
+        y7 = -x0 - ((b4 * 473 + b6 * 196 + 128) >> 8);
+        block[i] = b7 + y4;
+        block[8 + i] = x4 + y3;
+        block[2 * 8 + i] = y5 - x0;
+        block[3 * 8 + i] = y6 - y7;
+        block[4 * 8 + i] = y6 + y7;
+        block[5 * 8 + i] = x0 + y5;
+        block[6 * 8 + i] = y3 - x4;
+        block[7 * 8 + i] = y4 - b7;
+    }
+
+    // Transform rows
+    for ii in 0..8 {
+        let i = ii * 8;
+        b1 = block[4 + i];
+        b3 = block[2 + i] + block[6 + i];
+        b4 = block[5 + i] - block[3 + i];
+        tmp1 = block[1 + i] + block[7 + i];
+        tmp2 = block[3 + i] + block[5 + i];
+        b6 = block[1 + i] - block[7 + i];
+        b7 = tmp1 + tmp2;
+        m0 = block[i];
+        x4 = ((b6 * 473 - b4 * 196 + 128) >> 8) - b7;
+        x0 = x4 - (((tmp1 - tmp2) * 362 + 128) >> 8);
+        x1 = m0 - b1;
+        x2 = (((block[2 + i] - block[6 + i]) * 362 + 128) >> 8) - b3;
+        x3 = m0 + b1;
+        y3 = x1 + x2;
+        y4 = x3 + b3;
+        y5 = x1 - x2;
+        y6 = x3 - b3;
+        y7 = -x0 - ((b4 * 473 + b6 * 196 + 128) >> 8);
+        block[i] = (b7 + y4 + 128) >> 8;
+        block[1 + i] = (x4 + y3 + 128) >> 8;
+        block[2 + i] = (y5 - x0 + 128) >> 8;
+        block[3 + i] = (y6 - y7 + 128) >> 8;
+        block[4 + i] = (y6 + y7 + 128) >> 8;
+        block[5 + i] = (x0 + y5 + 128) >> 8;
+        block[6 + i] = (y3 - x4 + 128) >> 8;
+        block[7 + i] = (y4 - b7 + 128) >> 8;
+    }
+}
+
+pub fn reference(block: &mut [i32; 64]) {
+    let table = &IDCT_COSINE;
+    let mut tmp = [0.0f64; 64];
+
+    // Transform columns
+    for v in 0..8 {
+        for x in 0..8 {
+            let mut sum = 0.0;
+            for u in 0..8 {
+                sum += table[u][x] * block[v * 8 + u] as f64;
+            }
+            tmp[v * 8 + x] = sum;
+        }
+    }
+
+    // Transform rows
+    for y in 0..8 {
+        for x in 0..8 {
+            let mut sum = 0.0;
+            for v in 0..8 {
+                sum += table[v][y] * tmp[v * 8 + x];
+            }
+            block[y * 8 + x] = (sum + 0.5).floor() as i32;
+        }
+    }
+}
+
+// Peak and mean errors of an IDCT against the double precision
+// IDCT, measured as described in IEEE 1180 section 3
+#[derive(Debug, Default)]
+pub struct Ieee1180Result {
+    pub peak_error: i32,
+    pub peak_mse: f64,
+    pub overall_mse: f64,
+    pub peak_mean_error: f64,
+    pub overall_mean_error: f64,
+}
+
+impl Ieee1180Result {
+    pub fn is_compliant(&self) -> bool {
+        self.peak_error <= 1
+            && self.peak_mse <= 0.06
+            && self.overall_mse <= 0.02
+            && self.peak_mean_error <= 0.015
+            && self.overall_mean_error <= 0.0015
+    }
+}
+
+// random number generator of IEEE 1180 section 3.2, values in [-low, high]
+struct Ieee1180Random {
+    seed: u32,
+}
+
+impl Ieee1180Random {
+    fn next(&mut self, low: i32, high: i32) -> i32 {
+        self.seed = self.seed.wrapping_mul(1103515245).wrapping_add(12345);
+        let x = (self.seed & 0x7ffffffe) as f64 / 0x7fffffff as f64;
+        (x * (low + high + 1) as f64) as i32 - low
+    }
+}
+
+// Run `count` random blocks with samples in [-low, high], multiplied by `sign`,
+// through the IDCT selected by `mode`
+pub fn ieee1180(mode: IdctMode, low: i32, high: i32, sign: i32, count: usize) -> Ieee1180Result {
+    let table = &IDCT_COSINE;
+    let mut random = Ieee1180Random { seed: 1 };
+
+    let mut sum_error = [0i64; 64];
+    let mut sum_square_error = [0i64; 64];
+    let mut result: Ieee1180Result = Default::default();
+
+    for _ in 0..count {
+        let mut samples = [0.0f64; 64];
+        for sample in samples.iter_mut() {
+            *sample = (random.next(low, high) * sign) as f64;
+        }
+
+        // forward DCT, rounded and clipped to 12 bits
+        let mut rows = [0.0f64; 64];
+        for y in 0..8 {
+            for u in 0..8 {
+                rows[y * 8 + u] = (0..8).map(|x| table[u][x] * samples[y * 8 + x]).sum();
+            }
+        }
+        let mut coeffs = [0i32; 64];
+        for v in 0..8 {
+            for u in 0..8 {
+                let sum: f64 = (0..8).map(|y| table[v][y] * rows[y * 8 + u]).sum();
+                coeffs[v * 8 + u] = ((sum + 0.5).floor() as i32).clamp(-2048, 2047);
+            }
+        }
+
+        // double precision IDCT computed straight from the definition
+        let mut expected = [0i32; 64];
+        for y in 0..8 {
+            for x in 0..8 {
+                let mut sum = 0.0;
+                for v in 0..8 {
+                    for u in 0..8 {
+                        sum += table[v][y] * table[u][x] * coeffs[v * 8 + u] as f64;
+                    }
+                }
+                expected[y * 8 + x] = ((sum + 0.5).floor() as i32).clamp(-256, 255);
+            }
+        }
+
+        let mut block = coeffs;
+        idct(mode, &mut block);
+        for i in 0..64 {
+            let error = block[i].clamp(-256, 255) - expected[i];
+            result.peak_error = result.peak_error.max(error.abs());
+            sum_error[i] += error as i64;
+            sum_square_error[i] += (error * error) as i64;
+        }
+    }
+
+    let count = count as f64;
+    for i in 0..64 {
+        result.peak_mse = result.peak_mse.max(sum_square_error[i] as f64 / count);
+        result.peak_mean_error = result.peak_mean_error.max((sum_error[i] as f64 / count).abs());
+    }
+    result.overall_mse = sum_square_error.iter().sum::<i64>() as f64 / (64.0 * count);
+    result.overall_mean_error = (sum_error.iter().sum::<i64>() as f64 / (64.0 * count)).abs();
+    result
+}
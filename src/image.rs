@@ -0,0 +1,184 @@
+// Still images of decoded frames as PPM, BMP or PNG with stored deflate blocks
+
+use std::io;
+use std::io::Write;
+
+use crate::checksum;
+use crate::video::VideoFrame;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ImageFormat {
+    Ppm,
+    Bmp,
+    Png,
+}
+
+impl ImageFormat {
+    // by file name extension
+    pub fn from_path(path: &str) -> Option<ImageFormat> {
+        let extension = path.rsplit('.').next()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "ppm" => Some(ImageFormat::Ppm),
+            "bmp" => Some(ImageFormat::Bmp),
+            "png" => Some(ImageFormat::Png),
+            _ => None,
+        }
+    }
+}
+
+// 8-bit RGB triplets, rows top to bottom without padding
+pub struct RgbImage {
+    pub width:  u32,
+    pub height: u32,
+    pub data:   Vec<u8>,
+}
+
+fn clip(value: i32) -> u8 {
+    value.clamp(0, 255) as u8
+}
+
+// ITU-R BT.601 studio swing to full range RGB
+fn yuv_to_rgb(y: u8, cb: u8, cr: u8) -> [u8; 3] {
+    let c = 298 * (y as i32 - 16);
+    let d = cb as i32 - 128;
+    let e = cr as i32 - 128;
+    [
+        clip((c + 409 * e + 128) >> 8),
+        clip((c - 100 * d - 208 * e + 128) >> 8),
+        clip((c + 516 * d + 128) >> 8),
+    ]
+}
+
+impl RgbImage {
+    // The visible picture of a frame. With a sample aspect ratio the image is
+    // stretched to square pixels, widened or heightened so no line is lost.
+    pub fn from_frame(frame: &VideoFrame, aspect: Option<(u32, u32)>) -> RgbImage {
+        let (mut width, mut height) = (frame.width, frame.height);
+        if let Some((num, den)) = aspect {
+            if num > den {
+                width = ((width as u64 * num as u64 + den as u64 / 2) / den as u64) as u32;
+            } else if num < den {
+                height = ((height as u64 * den as u64 + num as u64 / 2) / num as u64) as u32;
+            }
+        }
+
+        let luma = frame.plane(&frame.y);
+        let cb = frame.plane(&frame.cb);
+        let cr = frame.plane(&frame.cr);
+        let mut data = Vec::with_capacity((width * height * 3) as usize);
+        for row in 0..height {
+            // nearest sample of the coded picture
+            let sy = (row as u64 * frame.height as u64 / height as u64) as usize;
+            for col in 0..width {
+                let sx = (col as u64 * frame.width as u64 / width as u64) as usize;
+                let chroma = (sy >> 1) * frame.cb.width as usize + (sx >> 1);
+                let y = luma[sy * frame.y.width as usize + sx];
+                data.extend_from_slice(&yuv_to_rgb(y, cb[chroma], cr[chroma]));
+            }
+        }
+        RgbImage { width, height, data }
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W, format: ImageFormat) -> io::Result<()> {
+        match format {
+            ImageFormat::Ppm => self.write_ppm(out),
+            ImageFormat::Bmp => self.write_bmp(out),
+            ImageFormat::Png => self.write_png(out),
+        }
+    }
+
+    fn rows(&self) -> std::slice::Chunks<'_, u8> {
+        self.data.chunks((self.width * 3) as usize)
+    }
+
+    fn write_ppm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+        out.write_all(&self.data)
+    }
+
+    // 24 bit BGR, bottom-up rows padded to 4 bytes
+    fn write_bmp<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let stride = (self.width * 3 + 3) & !3;
+        let image_size = stride * self.height;
+        let header_size = 14 + 40;
+
+        let mut header = Vec::with_capacity(header_size as usize);
+        header.extend_from_slice(b"BM");
+        header.extend_from_slice(&(header_size + image_size).to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&header_size.to_le_bytes());
+        // BITMAPINFOHEADER
+        header.extend_from_slice(&40u32.to_le_bytes());
+        header.extend_from_slice(&(self.width as i32).to_le_bytes());
+        header.extend_from_slice(&(self.height as i32).to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&24u16.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&image_size.to_le_bytes());
+        // 72 dpi
+        header.extend_from_slice(&2835i32.to_le_bytes());
+        header.extend_from_slice(&2835i32.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        out.write_all(&header)?;
+
+        let mut line = vec![0u8; stride as usize];
+        for row in self.rows().rev() {
+            for (dst, src) in line.chunks_mut(3).zip(row.chunks(3)) {
+                dst.copy_from_slice(&[src[2], src[1], src[0]]);
+            }
+            out.write_all(&line)?;
+        }
+        Ok(())
+    }
+
+    fn write_png_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+        out.write_all(&(data.len() as u32).to_be_bytes())?;
+        out.write_all(kind)?;
+        out.write_all(data)?;
+        let crc = checksum::crc32_update(checksum::crc32(kind), data);
+        out.write_all(&crc.to_be_bytes())
+    }
+
+    // Truecolour without interlacing, every scanline with filter type None
+    // in a zlib stream of stored blocks.
+    fn write_png<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        const MAX_STORED: usize = 65535;
+
+        out.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+        let mut ihdr = Vec::with_capacity(13);
+        ihdr.extend_from_slice(&self.width.to_be_bytes());
+        ihdr.extend_from_slice(&self.height.to_be_bytes());
+        // bit depth 8, colour type 2, deflate, adaptive filtering, no interlace
+        ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+        Self::write_png_chunk(out, b"IHDR", &ihdr)?;
+
+        let mut raw = Vec::with_capacity(self.data.len() + self.height as usize);
+        for row in self.rows() {
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+
+        let block_count = raw.len().div_ceil(MAX_STORED).max(1);
+        let mut zlib = Vec::with_capacity(raw.len() + block_count * 5 + 6);
+        // deflate with a 32K window, no preset dictionary, fastest level
+        zlib.extend_from_slice(&[0x78, 0x01]);
+        let mut blocks = raw.chunks(MAX_STORED).peekable();
+        if blocks.peek().is_none() {
+            zlib.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+        }
+        while let Some(block) = blocks.next() {
+            let last = blocks.peek().is_none();
+            let len = block.len() as u16;
+            zlib.push(last as u8);
+            zlib.extend_from_slice(&len.to_le_bytes());
+            zlib.extend_from_slice(&(!len).to_le_bytes());
+            zlib.extend_from_slice(block);
+        }
+        zlib.extend_from_slice(&checksum::adler32(&raw).to_be_bytes());
+        Self::write_png_chunk(out, b"IDAT", &zlib)?;
+
+        Self::write_png_chunk(out, b"IEND", &[])
+    }
+}
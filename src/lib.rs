@@ -0,0 +1,9 @@
+pub mod bitbuf;
+pub mod checksum;
+pub mod idct;
+pub mod image;
+pub mod pkt;
+pub mod vbv;
+pub mod video;
+pub mod vlc;
+pub mod y4m;
@@ -1,65 +1,329 @@
 use std::env;
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, BufWriter, Read, Write};
 
-mod bitbuf;
-mod pkt;
-mod video;
-mod vlc;
+use ez_mpeg1::bitbuf::BitReader;
+use ez_mpeg1::checksum;
+use ez_mpeg1::image;
+use ez_mpeg1::pkt;
+use ez_mpeg1::vbv;
+use ez_mpeg1::video;
+use ez_mpeg1::y4m;
 
-fn main() {
-    // file to [u8]
-    let args: Vec<String> = env::args().collect();
-    let mut file = File::open(&args[1]).unwrap();
-    let mut data:Vec<u8> = Vec::new();
-    let _result = file.read_to_end(&mut data);
+// Everything but the frame checksums goes to stderr while they are printed,
+// so stdout diffs like ffmpeg's framemd5 output
+macro_rules! report {
+    ($to_stderr:expr, $($arg:tt)*) => {
+        if $to_stderr {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
 
-    let mut vcodec = video::Mpeg1Video::new();
+// what to do with decoded frames and the statistics of their pictures
+#[derive(Default)]
+struct Analysis {
+    print_stats: bool,
+    check_vbv:   bool,
+    pictures:    Vec<video::PictureStats>,
+    y4m:         Option<y4m::Y4mWriter<BufWriter<File>>>,
+    // frame number in display order and where to save it
+    image:       Option<(usize, String)>,
+    aspect:      bool,
+    framemd5:    bool,
+    framecrc:    bool,
+    frames:      usize,
+}
+
+impl Analysis {
+    fn to_stderr(&self) -> bool {
+        self.framemd5 || self.framecrc
+    }
+}
+
+// one line per frame: number in display order, visible size and checksum
+fn print_checksum(frame: &video::VideoFrame, number: usize, md5: bool) {
+    let size = frame.width * frame.height + 2 * ((frame.width + 1) >> 1) * ((frame.height + 1) >> 1);
+    let sum = if md5 {
+        checksum::hex(&checksum::frame_md5(frame))
+    } else {
+        format!("{:08x}", checksum::frame_crc32(frame))
+    };
+    println!("{:>5}, {:>8}, {}", number, size, sum);
+}
+
+fn save_image<B: BitReader>(vcodec: &video::Mpeg1Video<B>, path: &str, aspect: bool, to_stderr: bool) {
+    let format = image::ImageFormat::from_path(path).expect("Image name must end with .ppm, .bmp or .png");
+    let (frame, info) = match (vcodec.frame(), vcodec.info()) {
+        (Some(frame), Some(info)) => (frame, info),
+        _ => return,
+    };
+    let aspect = if aspect { info.sample_aspect_ratio() } else { None };
+    let rgb = image::RgbImage::from_frame(&frame, aspect);
+    let mut out = BufWriter::new(File::create(path).expect("Can't create image"));
+    rgb.write(&mut out, format).expect("Can't write image");
+    report!(to_stderr, "Frame saved as {}", path);
+}
+
+fn got_frame<B: BitReader>(vcodec: &video::Mpeg1Video<B>, analysis: &mut Analysis) {
+    report!(analysis.to_stderr(), "One frame is OK");
+    if analysis.framemd5 || analysis.framecrc {
+        if let Some(frame) = vcodec.frame() {
+            print_checksum(&frame, analysis.frames, analysis.framemd5);
+        }
+    }
+    if let Some(writer) = analysis.y4m.as_mut() {
+        writer.write_frame(vcodec).expect("Can't write Y4M frame");
+    }
+    if let Some((number, path)) = &analysis.image {
+        if *number == analysis.frames {
+            save_image(vcodec, path, analysis.aspect, analysis.to_stderr());
+        }
+    }
+    analysis.frames += 1;
+}
+
+fn decode_frames<B: BitReader>(vcodec: &mut video::Mpeg1Video<B>, analysis: &mut Analysis) {
+    // one push may complete several pictures
+    loop {
+        let result = vcodec.decode();
+        for violation in vcodec.take_violations() {
+            report!(analysis.to_stderr(), "{:?} at video byte {}: {}", violation.severity, violation.es_offset, violation.message);
+        }
+        for stats in vcodec.take_stats() {
+            if analysis.print_stats {
+                print_stats(&stats, analysis.to_stderr());
+            }
+            if analysis.check_vbv {
+                analysis.pictures.push(stats);
+            }
+        }
+        match result {
+            video::DecodeResult::GotOneFrame =>{
+                got_frame(vcodec, analysis);
+            },
+            video::DecodeResult::InternalError =>{
+                panic!("Internal error happen");
+            },
+            video::DecodeResult::NeedMoreData =>{
+                break;
+            }
+        };
+    }
+}
+
+fn print_stats(stats: &video::PictureStats, to_stderr: bool) {
+    let picture_type = match stats.picture_type {
+        1 => 'I',
+        2 => 'P',
+        3 => 'B',
+        _ => 'D',
+    };
+    if !stats.decoded {
+        report!(to_stderr, "picture {} es_offset {} temporal_reference {} structure {} bits {} vbv_delay {} not decoded",
+                 picture_type, stats.es_offset, stats.temporal_reference, stats.picture_structure, stats.bits, stats.vbv_delay);
+        return;
+    }
+    report!(to_stderr, "picture {} es_offset {} temporal_reference {} structure {} bits {} vbv_delay {} \
+              q {}/{:.2}/{} intra {} inter {} skipped {}",
+             picture_type, stats.es_offset, stats.temporal_reference, stats.picture_structure, stats.bits, stats.vbv_delay,
+             stats.quantizer_min, stats.quantizer_avg(), stats.quantizer_max,
+             stats.intra_macroblocks, stats.inter_macroblocks, stats.skipped_macroblocks);
+}
+
+fn push_video(vcodec: &mut video::Mpeg1Video, mut data: &[u8], analysis: &mut Analysis) {
+    // the buffer takes what fits, decoding makes room for the rest
+    let mut stalled = false;
+    while !data.is_empty() {
+        match vcodec.push(data) {
+            Some(taken) => {
+                data = &data[taken..];
+                stalled = false;
+            },
+            None if stalled => panic!("A picture doesn't fit into the decoder's buffer"),
+            None => stalled = true,
+        }
+        decode_frames(vcodec, analysis);
+    }
+}
+
+// the last reference picture is held back for reordering
+fn finish<B: BitReader>(vcodec: &mut video::Mpeg1Video<B>, analysis: &mut Analysis) {
+    if let video::DecodeResult::GotOneFrame = vcodec.flush() {
+        got_frame(vcodec, analysis);
+    }
+    if let Some(writer) = analysis.y4m.as_mut() {
+        writer.flush().expect("Can't write Y4M frame");
+    }
+    check_vbv(vcodec, analysis);
+}
 
-    let mut index:usize = 1400;
+fn check_vbv<B: BitReader>(vcodec: &video::Mpeg1Video<B>, analysis: &Analysis) {
+    if !analysis.check_vbv {
+        return;
+    }
+    let params = match vcodec.vbv_parameters() {
+        Some(params) => params,
+        None => return,
+    };
+    let events = vbv::verify(&params, &analysis.pictures);
+    for event in &events {
+        report!(analysis.to_stderr(), "VBV {:?} at picture {} time {:.4} occupancy {}",
+                 event.error, event.picture, event.time, event.occupancy);
+    }
+    report!(analysis.to_stderr(), "VBV bit_rate {} buffer_size {}: {} pictures, {} violations",
+             params.bit_rate, params.buffer_size, analysis.pictures.len(), events.len());
+}
+
+fn trace_level(args: &[String]) -> video::TraceLevel {
+    let level = args.iter().find_map(|arg| arg.strip_prefix("--trace="));
+    match level {
+        None => video::TraceLevel::Off,
+        Some("headers") => video::TraceLevel::Headers,
+        Some("slices") => video::TraceLevel::Slices,
+        Some("macroblocks") => video::TraceLevel::Macroblocks,
+        Some("blocks") => video::TraceLevel::Blocks,
+        Some(other) => panic!("Unknown trace level {}, use headers, slices, macroblocks or blocks", other),
+    }
+}
+
+fn configure<B: BitReader>(vcodec: &mut video::Mpeg1Video<B>, args: &[String]) -> Analysis {
+    let y4m = args.iter().find_map(|arg| arg.strip_prefix("--y4m=")).map(|path| {
+        let file = File::create(path).expect("Can't create Y4M output");
+        y4m::Y4mWriter::new(BufWriter::new(file))
+    });
+    let analysis = Analysis {
+        print_stats: args.iter().any(|arg| arg == "--stats"),
+        check_vbv:   args.iter().any(|arg| arg == "--vbv"),
+        y4m,
+        image: args.iter().find_map(|arg| arg.strip_prefix("--image=")).map(|path| {
+            let number = args.iter().find_map(|arg| arg.strip_prefix("--frame="))
+                .map_or(0, |number| number.parse().expect("--frame needs a frame number"));
+            (number, path.to_string())
+        }),
+        aspect:      args.iter().any(|arg| arg == "--aspect"),
+        framemd5:    args.iter().any(|arg| arg == "--framemd5"),
+        framecrc:    args.iter().any(|arg| arg == "--framecrc"),
+        ..Default::default()
+    };
+    vcodec.set_strict(args.iter().any(|arg| arg == "--strict"));
+    vcodec.set_stats(analysis.print_stats || analysis.check_vbv);
+    let level = trace_level(args);
+    if level != video::TraceLevel::Off {
+        let out: Box<dyn Write> = if analysis.to_stderr() { Box::new(io::stderr()) } else { Box::new(io::stdout()) };
+        vcodec.set_trace(level, out);
+    }
+    analysis
+}
+
+// decode a video elementary stream in place, the whole file is already in memory
+fn decode_es(data: &[u8], args: &[String]) {
+    let mut vcodec = video::Mpeg1Video::from_slice(data);
+    let mut analysis = configure(&mut vcodec, args);
+    decode_frames(&mut vcodec, &mut analysis);
+    finish(&mut vcodec, &mut analysis);
+}
+
+// demux a program or transport stream and feed its video packets to the decoder
+fn decode_pes(data: &[u8], vcodec: &mut video::Mpeg1Video, is_ts: bool, analysis: &mut Analysis, args: &[String]) {
     let mut ps = pkt::MpegPS::new();
-    ps.push_ts(&data[0..index]);
+    if let Some(number) = args.iter().find_map(|arg| arg.strip_prefix("--program=")) {
+        ps.select_program(number.parse().expect("--program needs a program number"));
+    }
+    let push = |ps: &mut pkt::MpegPS, data: &[u8]| -> usize {
+        if is_ts {
+            ps.push_ts(data)
+        } else {
+            ps.push(data)
+        }
+    };
 
-    while index < data.len() {
+    let mut index:usize = push(&mut ps, &data[0..data.len().min(1400)]);
+
+    loop {
         let pkt_result = ps.get();
         if let Ok(ref pkt) = pkt_result {
-            println!("===={:?}", pkt);
+            report!(analysis.to_stderr(), "===={:?}", pkt);
             if pkt.pes_type == pkt::PacketType::PES_VIDEO {
-                let payload = ps.payload(pkt);
-                if vcodec.push(payload).is_none() {
-                    panic!("Decoder's buffer is full,can't do any decoding");
+                if pkt.damaged {
+                    report!(analysis.to_stderr(), "Video PES packet misses lost TS packets");
                 }
-                match vcodec.decode() {
-                    video::DecodeResult::GotOneFrame =>{
-                        println!("One frame is OK");
-                    },
-                    video::DecodeResult::InternalError =>{
-                        panic!("Internal error happen");
-                    },
-                    video::DecodeResult::NeedMoreData =>{
-
-                    }
-                };
+                push_video(vcodec, ps.payload(pkt), analysis);
             }
         }
 
         if let Err(e) = pkt_result {
+            if index == data.len() {
+                // the last PES packets of a transport stream end with the input
+                if is_ts && ps.end_of_stream() {
+                    continue;
+                }
+                break;
+            }
             if let pkt::PacketError::OUT_LENGTH(more) = e {
                 let mut pushed = 1280 + more;
                 if pushed > data.len() - index {
                     pushed = data.len() - index;
                 }
-                index = index + ps.push_ts(&data[index..(index+pushed)]);
+                index += push(&mut ps, &data[index..(index+pushed)]);
             } else if let pkt::PacketError::NO_START_CODE = e {
                 let mut pushed = 1280;
                 if pushed > data.len() - index {
                     pushed = data.len() - index;
                 }
-                index = index + ps.push_ts(&data[index..(index+pushed)]);
+                index += push(&mut ps, &data[index..(index+pushed)]);
             } else {
-                println!("Can't handle Error: {:?}", e);
+                report!(analysis.to_stderr(), "Can't handle Error: {:?}", e);
                 break;
             }
         }
     }
+    if ps.dropped_packets > 0 || ps.skipped_bytes > 0 {
+        report!(analysis.to_stderr(), "TS dropped {} broken packets, skipped {} bytes out of sync",
+                ps.dropped_packets, ps.skipped_bytes);
+    }
+    if ps.lost_packets > 0 || ps.duplicate_packets > 0 {
+        report!(analysis.to_stderr(), "TS lost {} packets, {} came twice", ps.lost_packets, ps.duplicate_packets);
+    }
+}
+
+fn main() {
+    // file to [u8]
+    // usage: ez-mpeg1 [--strict] [--stats] [--vbv] [--y4m=out.y4m]
+    //                 [--image=out.ppm|bmp|png [--frame=N] [--aspect]] [--framemd5|--framecrc]
+    //                 [--trace=headers|slices|macroblocks|blocks] [--program=N] <file>
+    let args: Vec<String> = env::args().collect();
+    let path = args.iter().skip(1).find(|arg| !arg.starts_with("--")).expect("no input file");
+    let mut file = File::open(path).unwrap();
+    let mut data:Vec<u8> = Vec::new();
+    let _result = file.read_to_end(&mut data);
+
+    let stream_type = pkt::probe(&data);
+    let to_stderr = args.iter().any(|arg| arg == "--framemd5" || arg == "--framecrc");
+    report!(to_stderr, "Input is {:?}", stream_type);
+    let is_ts = match stream_type {
+        pkt::StreamType::VIDEO_ES => {
+            decode_es(&data, &args);
+            return;
+        },
+        pkt::StreamType::PROGRAM_STREAM => false,
+        pkt::StreamType::TRANSPORT_STREAM => true,
+        pkt::StreamType::UNKNOWN => {
+            report!(to_stderr, "Unknown input format");
+            return;
+        }
+    };
+
+    let mut vcodec = video::Mpeg1Video::new();
+    let mut analysis = configure(&mut vcodec, &args);
+    decode_pes(&data, &mut vcodec, is_ts, &mut analysis, &args);
+
+    // the last picture is terminated by the end of the input
+    if vcodec.end_of_stream().is_none() {
+        panic!("Decoder's buffer is full,can't do any decoding");
+    }
+    decode_frames(&mut vcodec, &mut analysis);
+    finish(&mut vcodec, &mut analysis);
 }
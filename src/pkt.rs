@@ -3,6 +3,8 @@
 
 use std::ptr;
 use crate::bitbuf;
+use crate::checksum;
+use crate::bitbuf::BitReader;
 
 #[allow(non_camel_case_types)]
 #[derive(Debug)]
@@ -23,6 +25,15 @@ pub enum PacketType {
     PES_UNKNOW,
 }
 
+#[allow(non_camel_case_types)]
+#[derive(Debug, PartialEq)]
+pub enum StreamType {
+    VIDEO_ES,
+    PROGRAM_STREAM,
+    TRANSPORT_STREAM,
+    UNKNOWN,
+}
+
 #[derive(Debug)]
 pub struct PESPacketInfo {
     pub pes_type: PacketType,
@@ -32,6 +43,145 @@ pub struct PESPacketInfo {
     pub offset: usize,
     pub len: usize,
     pub payload: usize,
+
+    // TS only: packets of it were lost, it follows a discontinuity_indicator
+    pub damaged: bool,
+    pub discontinuity: bool,
+    // TS only: the adaptation field of the packet it starts in, if it says anything
+    pub adaptation: Option<TsAdaptationField>,
+}
+
+// Tell the input format from the first bytes: a video sequence header,
+// a pack header, or TS sync bytes 188 bytes apart
+pub fn probe(data: &[u8]) -> StreamType {
+    if data.len() >= 4 && data[0..3] == [0x00, 0x00, 0x01] {
+        return match data[3] {
+            0xB3 => StreamType::VIDEO_ES,
+            0xBA => StreamType::PROGRAM_STREAM,
+            _ => StreamType::UNKNOWN,
+        };
+    }
+
+    // captured files may start in the middle of a packet
+    let window = MpegPS::TS_PACKET_SIZE * (MpegPS::TS_SYNC_RUN + 1);
+    let head = &data[..data.len().min(window)];
+    if let Some(start) = ts_sync(head, head.len() == data.len()) {
+        if start < MpegPS::TS_PACKET_SIZE {
+            return StreamType::TRANSPORT_STREAM;
+        }
+    }
+    StreamType::UNKNOWN
+}
+
+// Offset of the first TS_SYNC_RUN sync bytes 188 apart. At the end of the
+// input a shorter run that reaches the end is taken as well.
+fn ts_sync(data: &[u8], end: bool) -> Option<usize> {
+    (0..data.len()).find(|&start| {
+        let mut run = 0;
+        for pos in (start..data.len()).step_by(MpegPS::TS_PACKET_SIZE).take(MpegPS::TS_SYNC_RUN) {
+            if data[pos] != MpegPS::TS_SYNC_BYTE {
+                return false;
+            }
+            run += 1;
+        }
+        run == MpegPS::TS_SYNC_RUN || end
+    })
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TsStreamKind {
+    VIDEO,
+    AUDIO,
+    PRIVATE,
+    UNKNOWN,
+}
+
+// An elementary stream of a transport stream program, from the PMT
+#[derive(Clone, Debug)]
+pub struct TsStream {
+    pub pid:         u16,
+    pub stream_type: u8,
+    pub kind:        TsStreamKind,
+}
+
+// A program of the PAT, the streams are known once its PMT has been seen
+#[derive(Clone, Debug, Default)]
+pub struct TsProgram {
+    pub number:  u16,
+    pub pmt_pid: u16,
+    pub pcr_pid: u16,
+    pub streams: Vec<TsStream>,
+}
+
+// stream_type of ISO/IEC 13818-1 table 2-34
+pub fn ts_stream_kind(stream_type: u8) -> TsStreamKind {
+    match stream_type {
+        0x01 | 0x02 => TsStreamKind::VIDEO,
+        0x03 | 0x04 => TsStreamKind::AUDIO,
+        0x05 | 0x06 => TsStreamKind::PRIVATE,
+        _ => TsStreamKind::UNKNOWN,
+    }
+}
+
+// The flags and optional fields of a TS adaptation field, clock references
+// in 27 MHz units as base * 300 + extension
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TsAdaptationField {
+    pub discontinuity:      bool,
+    pub random_access:      bool,
+    pub pcr:                Option<u64>,
+    pub opcr:               Option<u64>,
+    pub splice_countdown:   Option<i8>,
+}
+
+impl TsAdaptationField {
+    // stuffing only
+    pub fn is_empty(&self) -> bool {
+        *self == TsAdaptationField::default()
+    }
+}
+
+// The fields of a TS packet header the demuxer needs
+struct TsHeader {
+    payload_start:      bool,
+    pid:                u16,
+    // offset of the payload in the packet
+    payload:            usize,
+    has_payload:        bool,
+    continuity_counter: u8,
+    adaptation:         TsAdaptationField,
+}
+
+// A PES packet being collected from the TS packets of one PID
+struct TsPes {
+    pid:            u16,
+    data:           Vec<u8>,
+    // continuity_counter of the last packet with payload
+    continuity:     Option<u8>,
+    // packets of data were lost
+    damaged:        bool,
+    // data starts behind a discontinuity_indicator
+    discontinuity:  bool,
+    // a discontinuity_indicator for the PES packet to start next
+    pending_discontinuity: bool,
+    // of the packet data starts in
+    adaptation:     Option<TsAdaptationField>,
+}
+
+// What get() tells about the PES packet at a buffer offset
+struct TsMark {
+    offset:         usize,
+    damaged:        bool,
+    discontinuity:  bool,
+    adaptation:     Option<TsAdaptationField>,
+}
+
+enum Continuity {
+    Next,
+    Duplicate,
+    // packets lost in between
+    Lost(u8),
 }
 
 pub struct MpegPS {
@@ -43,13 +193,36 @@ pub struct MpegPS {
     pub has_pack_header: bool,
     pub has_system_header: bool,
 
+    // The last PCR of the selected TS program in 27 MHz units, its base
+    // goes to system_clock_ref like the SCR of a pack header. Taken when the
+    // TS packet is pushed, the adaptation field of a PES packet has the PCR
+    // that came with it.
+    pub program_clock_ref: u64,
+    pub has_program_clock_ref: bool,
+
+    // TS packets dropped as broken, bytes skipped to find the packet sync
+    pub dropped_packets: u64,
+    pub skipped_bytes:   u64,
+    // TS packets missing or sent twice by continuity_counter
+    pub lost_packets:      u64,
+    pub duplicate_packets: u64,
+
     buffer_: Vec<u8>,
     offset_: usize,
     len_: usize,
 
-    ts_buffer_:     [u8; 188],
-    ts_offset_:     usize,
-    ts_pid_:        i32,
+    // TS input not routed yet, from a packet start while in sync
+    ts_carry_:      Vec<u8>,
+    ts_synced_:     bool,
+    ts_programs_:   Vec<TsProgram>,
+    // chosen by the caller, the first program and its first video stream otherwise
+    ts_program_:    Option<u16>,
+    ts_streams_:    Vec<u16>,
+    // PSI sections and PES packets still being collected, by PID
+    ts_sections_:   Vec<(u16, Vec<u8>)>,
+    ts_pes_:        Vec<TsPes>,
+    // PES packets in the buffer with something to tell
+    ts_marks_:      Vec<TsMark>,
 }
 
 impl MpegPS {
@@ -62,6 +235,13 @@ impl MpegPS {
     const PES_PRIVATE_CODE: u8 = 0xBD;
     const PES_PADDING_CODE: u8 = 0xBE;
 
+    const TS_PACKET_SIZE: usize = 188;
+    const TS_SYNC_BYTE: u8 = 0x47;
+    // sync bytes in a row before the packets are trusted
+    const TS_SYNC_RUN: usize = 3;
+    // TS input held back while the packet buffer is full
+    const TS_CARRY_LIMIT: usize = 188 * 64;
+
     fn code2type(code:u8) -> PacketType {
         if code >= MpegPS::PES_AUDIO_BEGIN &&
            code <= MpegPS::PES_AUDIO_END {
@@ -94,6 +274,11 @@ impl MpegPS {
             ptr::copy(src, dst, self.len_ - self.offset_);
         }
         self.len_ = self.len_ - self.offset_;
+        let offset = self.offset_;
+        self.ts_marks_.retain(|mark| mark.offset >= offset);
+        for mark in self.ts_marks_.iter_mut() {
+            mark.offset -= offset;
+        }
         self.offset_ = 0;
     }
 
@@ -103,18 +288,29 @@ impl MpegPS {
         MpegPS {
             has_pack_header:    false,
             has_system_header:  false,
+            program_clock_ref:  0,
+            has_program_clock_ref: false,
             system_clock_ref:   0x00,
             bit_rate:           0,
             num_audio_streams:  -1,
             num_video_streams:  -1,
+            dropped_packets:    0,
+            skipped_bytes:      0,
+            lost_packets:       0,
+            duplicate_packets:  0,
 
             buffer_:    buffer,
             offset_:    0,
             len_:       0,
 
-            ts_buffer_: [0;188],
-            ts_offset_: 0,
-            ts_pid_:    -1,
+            ts_carry_:      Vec::new(),
+            ts_synced_:     false,
+            ts_programs_:   Vec::new(),
+            ts_program_:    None,
+            ts_streams_:    Vec::new(),
+            ts_sections_:   Vec::new(),
+            ts_pes_:        Vec::new(),
+            ts_marks_:      Vec::new(),
         }
     }
 
@@ -145,6 +341,12 @@ impl MpegPS {
         if let Ok(ref mut pkt) = pkt_result {
             self.offset_ = self.offset_ + pkt.len + pkt.offset;
             pkt.offset = self.offset_ - pkt.len;
+            if let Some(index) = self.ts_marks_.iter().position(|mark| mark.offset == pkt.offset) {
+                let mark = self.ts_marks_.remove(index);
+                pkt.damaged = mark.damaged;
+                pkt.discontinuity = mark.discontinuity;
+                pkt.adaptation = mark.adaptation;
+            }
         }
         if let Err(ref e) = pkt_result {
             if let PacketError::OUT_LENGTH(more) = e {
@@ -192,10 +394,10 @@ impl MpegPS {
         }
 
         // check code
-        let code:u8 = (buffer.read(32).unwrap() & 0xFF) as u8;
+        let code:u8 = (buffer.read(32) & 0xFF) as u8;
 
         // get length
-        let mut pes_length = buffer.read(16).unwrap() as usize;
+        let mut pes_length = buffer.read(16) as usize;
         if buffer.len() < 6 + pes_length {
             return Err(PacketError::OUT_LENGTH(6 + pes_length - buffer.len()));
         }
@@ -207,6 +409,9 @@ impl MpegPS {
                 len: pes_length + 6,
                 pts: 0,
                 payload: 0,
+                damaged: false,
+                discontinuity: false,
+                adaptation: None,
             });
         }
 
@@ -219,13 +424,13 @@ impl MpegPS {
             }
         }
 
-        if buffer.read(2).unwrap() == 0x01 {
+        if buffer.read(2) == 0x01 {
             buffer.skip(16);
             payload += 2;
         }
 
         // 11 = both present, 01 is forbidden, 10 = only PTS, 00 = no PTS or DTS
-        let indicator = buffer.read(2).unwrap();
+        let indicator = buffer.read(2);
         if indicator == 0x00 {
             buffer.skip(4);
             payload += 1;
@@ -236,17 +441,20 @@ impl MpegPS {
                 len: pes_length + 6,
                 pts: 0,
                 payload: payload,
+                damaged: false,
+                discontinuity: false,
+                adaptation: None,
             });
         } else if indicator == 0x01 {
             return Err(PacketError::FORMAT_ERROR);
         }
 
         let mut ts:u64 = 0;
-        ts = ts | (buffer.read(3).unwrap() as u64) << 30;
+        ts |= (buffer.read(3) as u64) << 30;
         buffer.skip(1);
-        ts = ts | (buffer.read(15).unwrap() as u64) << 15;
+        ts |= (buffer.read(15) as u64) << 15;
         buffer.skip(1);
-        ts = ts | (buffer.read(15).unwrap() as u64);
+        ts |= buffer.read(15) as u64;
         buffer.skip(1);
         payload += 5;
 
@@ -263,6 +471,9 @@ impl MpegPS {
             len: pes_length + 6,
             pts: ts,
             payload: payload,
+            damaged: false,
+            discontinuity: false,
+            adaptation: None,
         });
     }
 
@@ -275,19 +486,19 @@ impl MpegPS {
         }
 
         // check code
-        let code:u8 = (buffer.read(32).unwrap() & 0xFF) as u8;
+        let code:u8 = (buffer.read(32) & 0xFF) as u8;
 
         // get length
-        let pes_length = buffer.read(16).unwrap() as usize;
+        let pes_length = buffer.read(16) as usize;
         if buffer.len() < 6 + pes_length {
             return Err(PacketError::OUT_LENGTH(6 + pes_length - buffer.len()));
         }
 
         // get audio&video number
         buffer.skip(24);    //rate bound and marker bits
-        let num_audio_streams = buffer.read(6).unwrap() as i32;
+        let num_audio_streams = buffer.read(6) as i32;
         buffer.skip(5);
-        let num_video_streams = buffer.read(5).unwrap() as i32;
+        let num_video_streams = buffer.read(5) as i32;
 
         // skip to end of packet
         buffer.skip( (pes_length - 5) * 8);
@@ -299,6 +510,9 @@ impl MpegPS {
             len: buffer.pos() >> 3,
             pts: 0,
             payload: 0,
+            damaged: false,
+            discontinuity: false,
+            adaptation: None,
         };
 
         self.num_audio_streams = num_audio_streams;
@@ -316,26 +530,26 @@ impl MpegPS {
         }
 
         // check code
-        let code:u8 = (buffer.read(32).unwrap() & 0xFF) as u8;
+        let code:u8 = (buffer.read(32) & 0xFF) as u8;
 
         // check marker
-        let marker_bits = buffer.read(4).unwrap();
+        let marker_bits = buffer.read(4);
         if marker_bits != 0x02 {
             return Err(PacketError::FORMAT_ERROR);
         }
 
         // get clock
         let mut clock:u64 = 0;
-        clock = clock | (buffer.read(3).unwrap() as u64) << 30;
+        clock |= (buffer.read(3) as u64) << 30;
         buffer.skip(1);
-        clock = clock | (buffer.read(15).unwrap() as u64) << 15;
+        clock |= (buffer.read(15) as u64) << 15;
         buffer.skip(1);
-        clock = clock | (buffer.read(15).unwrap() as u64);
+        clock |= buffer.read(15) as u64;
         buffer.skip(1);
 
         // skip bitrate and stuff
         buffer.skip(1);
-        let bit_rate = buffer.read(22).unwrap() as u64;
+        let bit_rate = buffer.read(22) as u64;
         buffer.skip(1);
 
         let pkt = PESPacketInfo {
@@ -345,6 +559,9 @@ impl MpegPS {
             len: buffer.pos() >> 3,
             pts: 0,
             payload: 0,
+            damaged: false,
+            discontinuity: false,
+            adaptation: None,
         };
 
         self.system_clock_ref = clock;
@@ -371,123 +588,473 @@ impl MpegPS {
         None
     }
 
-    fn pid2pes(&mut self, ts:(u32, u32, u32, u32, usize)) -> bool {
-        if ts.0 == 0x01 && self.ts_pid_ == -1 {
-            if ts.3 >= 0x000001E0 && ts.3 <= 0x000001EF {
-                self.ts_pid_ = ts.1 as i32;
-                return true;
+    // added implementation for MPEG-TS support
+    fn remind(&self) -> usize {
+        self.buffer_.len() - self.len_
+    }
+
+    // None for a packet that can't be used: lost sync, transport_error_indicator
+    // set, the reserved adaptation_field_control or a too long adaptation field
+    fn parse_ts(data: &[u8]) -> Option<TsHeader> {
+        let mut buffer = bitbuf::BitBuffer::new(data);
+        if buffer.read(8) != MpegPS::TS_SYNC_BYTE as u32 {
+            return None;
+        }
+
+        let tei = buffer.read(1);
+        let payload_start = buffer.read(1);
+        let _transport_priority = buffer.read(1);
+        let pid = buffer.read(13);
+        let _tsc = buffer.read(2);
+        let adaptation_field_control = buffer.read(2);
+        let continuity_counter = buffer.read(4);
+
+        if tei != 0 || adaptation_field_control == 0x00 {
+            return None;
+        }
+        let mut payload = 4;
+        let mut adaptation = TsAdaptationField::default();
+        if (adaptation_field_control & 0x02) != 0 {
+            let adaptation_field_length = buffer.read(8) as usize;
+            payload += 1 + adaptation_field_length;
+            // 183 bytes without payload, 182 at most with it
+            let limit = if (adaptation_field_control & 0x01) != 0 { 187 } else { 188 };
+            if payload > limit {
+                return None;
             }
+            if adaptation_field_length > 0 {
+                adaptation = MpegPS::parse_adaptation_field(&mut buffer, adaptation_field_length)?;
+            }
+        }
+        if (adaptation_field_control & 0x01) == 0 {
+            payload = MpegPS::TS_PACKET_SIZE;
         }
 
-        if self.ts_pid_ == -1 {
-            return false;
+        Some(TsHeader {
+            payload_start:      payload_start == 0x01,
+            pid:                pid as u16,
+            payload,
+            has_payload:        (adaptation_field_control & 0x01) != 0,
+            continuity_counter: continuity_counter as u8,
+            adaptation,
+        })
+    }
+
+    // program_clock_reference_base, 6 reserved bits and the extension
+    fn read_clock_ref(buffer: &mut bitbuf::BitBuffer) -> u64 {
+        let base = ((buffer.read(32) as u64) << 1) | buffer.read(1) as u64;
+        buffer.skip(6);
+        base * 300 + buffer.read(9) as u64
+    }
+
+    // Up to splice_countdown, None if the fields don't fit in its length
+    fn parse_adaptation_field(buffer: &mut bitbuf::BitBuffer, length: usize) -> Option<TsAdaptationField> {
+        let discontinuity = buffer.read(1) == 1;
+        let random_access = buffer.read(1) == 1;
+        let _elementary_stream_priority = buffer.read(1);
+        let pcr_flag = buffer.read(1) == 1;
+        let opcr_flag = buffer.read(1) == 1;
+        let splicing_point_flag = buffer.read(1) == 1;
+        buffer.skip(2);
+
+        if 1 + 6 * (pcr_flag as usize + opcr_flag as usize) + splicing_point_flag as usize > length {
+            return None;
         }
+        let pcr = if pcr_flag { Some(MpegPS::read_clock_ref(buffer)) } else { None };
+        let opcr = if opcr_flag { Some(MpegPS::read_clock_ref(buffer)) } else { None };
+        let splice_countdown = if splicing_point_flag { Some(buffer.read(8) as u8 as i8) } else { None };
+        Some(TsAdaptationField {
+            discontinuity,
+            random_access,
+            pcr,
+            opcr,
+            splice_countdown,
+        })
+    }
 
-        if ts.1 as i32 == self.ts_pid_ {
-            return true;
+    // Programs of the last PAT with the streams of their PMTs
+    pub fn programs(&self) -> &[TsProgram] {
+        &self.ts_programs_
+    }
+
+    // Demux the video stream of this program instead of the first one
+    pub fn select_program(&mut self, number: u16) {
+        self.ts_program_ = Some(number);
+        self.ts_streams_.clear();
+    }
+
+    // Demux exactly these elementary PIDs, of any program
+    pub fn select_streams(&mut self, pids: &[u16]) {
+        self.ts_streams_ = pids.to_vec();
+    }
+
+    fn program(&self) -> Option<&TsProgram> {
+        let number = self.ts_program_;
+        self.ts_programs_.iter().find(|program| number.map_or(true, |number| program.number == number))
+    }
+
+    fn is_selected(&self, pid: u16) -> bool {
+        if !self.ts_streams_.is_empty() {
+            return self.ts_streams_.contains(&pid);
         }
+        self.program()
+            .and_then(|program| program.streams.iter().find(|stream| stream.kind == TsStreamKind::VIDEO))
+            .is_some_and(|stream| stream.pid == pid)
+    }
 
-        return false;
+    fn is_pmt(&self, pid: u16) -> bool {
+        self.ts_programs_.iter().any(|program| program.pmt_pid == pid)
     }
 
-    // added implementation for MPEG-TS support
-    fn remind(&self) -> usize {
-        self.buffer_.len() - self.len_
+    // Collect PSI sections, a section may start anywhere after the pointer_field
+    // and run over several packets.
+    fn ts_section(&mut self, pid: u16, payload_start: bool, mut payload: &[u8]) {
+        let index = match self.ts_sections_.iter().position(|(section_pid, _)| *section_pid == pid) {
+            Some(index) => index,
+            None => {
+                self.ts_sections_.push((pid, Vec::new()));
+                self.ts_sections_.len() - 1
+            }
+        };
+
+        let mut pending = std::mem::take(&mut self.ts_sections_[index].1);
+        if payload_start {
+            if payload.is_empty() {
+                return;
+            }
+            let pointer = (payload[0] as usize).min(payload.len() - 1);
+            if !pending.is_empty() {
+                pending.extend_from_slice(&payload[1..1 + pointer]);
+                self.ts_complete_sections(pid, &mut pending);
+            }
+            pending.clear();
+            payload = &payload[1 + pointer..];
+        } else if pending.is_empty() {
+            // joined in the middle of a section
+            return;
+        }
+        pending.extend_from_slice(payload);
+        self.ts_complete_sections(pid, &mut pending);
+        self.ts_sections_[index].1 = pending;
     }
 
-    fn parse_ts(data: &[u8]) -> (u32, u32, u32, u32, usize) {
-        // check ts packet itself
-        let mut buffer = bitbuf::BitBuffer::new(data);
-        if buffer.read(8).unwrap() != 0x47 {
-            panic!("Can't find 0x47 in TS package");
+    fn ts_complete_sections(&mut self, pid: u16, pending: &mut Vec<u8>) {
+        while pending.len() >= 3 {
+            // stuffing up to the end of the packet
+            if pending[0] == 0xFF {
+                pending.clear();
+                return;
+            }
+            let len = 3 + ((((pending[1] as usize) << 8) | pending[2] as usize) & 0x0FFF);
+            if pending.len() < len {
+                return;
+            }
+            let section: Vec<u8> = pending.drain(..len).collect();
+            self.parse_section(pid, &section);
         }
+    }
 
-        let _tei = buffer.read(1);
-        let payload_start = buffer.read(1).unwrap();
-        let _transport_priority = buffer.read(1);
-        let pid = buffer.read(13).unwrap();
-        let _tsc = buffer.read(2);
-        let adaptation_field_control = buffer.read(2).unwrap();
-        let continuity_counter = buffer.read(4).unwrap();
+    fn parse_section(&mut self, pid: u16, section: &[u8]) {
+        // long form sections only, table_id to last_section_number and the CRC_32
+        if section.len() < 12 || checksum::crc32_mpeg(section) != 0 {
+            return;
+        }
+        let mut buffer = bitbuf::BitBuffer::new(&section[..section.len() - 4]);
+        let table_id = buffer.read(8);
+        buffer.skip(1 + 1 + 2 + 12);   // syntax indicator, '0', reserved, section_length
+        let table_id_extension = buffer.read(16) as u16;
+        buffer.skip(2 + 5);           // reserved, version_number
+        let current_next_indicator = buffer.read(1);
+        buffer.skip(8 + 8);           // section_number, last_section_number
+        if current_next_indicator == 0x00 {
+            return;
+        }
 
-        if adaptation_field_control == 0x00 {
-            panic!("Can't support adaptation_field_control = 0x00");
+        if pid == 0x0000 && table_id == 0x00 {
+            self.parse_pat(&mut buffer);
+        } else if table_id == 0x02 {
+            self.parse_pmt(&mut buffer, table_id_extension);
         }
-        if (adaptation_field_control & 0x02) != 0 {
-            let filed_length = buffer.read(8).unwrap();
-            buffer.skip( (filed_length << 3) as usize);
+    }
+
+    fn parse_pat(&mut self, buffer: &mut bitbuf::BitBuffer) {
+        let mut programs = Vec::new();
+        while buffer.has(32) {
+            let number = buffer.read(16) as u16;
+            buffer.skip(3);
+            let pid = buffer.read(13) as u16;
+            // program 0 points at the network information table
+            if number == 0 {
+                continue;
+            }
+            // a program whose PMT moved has to wait for the new one
+            let known = self.ts_programs_.iter().find(|program| program.number == number && program.pmt_pid == pid);
+            programs.push(known.cloned().unwrap_or(TsProgram { number, pmt_pid: pid, ..Default::default() }));
         }
-        let code:u32 = if buffer.has(32) {
-            let ret = buffer.read(32).unwrap();
-            buffer.back(32);
-            ret
-        } else {
-            0xFFFFFFFF
-        };
+        self.ts_programs_ = programs;
+    }
 
-        return (payload_start, pid, continuity_counter, code, buffer.pos() / 8);
+    fn parse_pmt(&mut self, buffer: &mut bitbuf::BitBuffer, number: u16) {
+        buffer.skip(3);
+        let pcr_pid = buffer.read(13) as u16;
+        buffer.skip(4);
+        let program_info_length = buffer.read(12) as usize;
+        buffer.skip(program_info_length * 8);
+
+        let mut streams = Vec::new();
+        while buffer.has(40) {
+            let stream_type = buffer.read(8) as u8;
+            buffer.skip(3);
+            let pid = buffer.read(13) as u16;
+            buffer.skip(4);
+            let es_info_length = buffer.read(12) as usize;
+            buffer.skip(es_info_length * 8);
+            streams.push(TsStream { pid, stream_type, kind: ts_stream_kind(stream_type) });
+        }
+
+        if let Some(program) = self.ts_programs_.iter_mut().find(|program| program.number == number) {
+            program.pcr_pid = pcr_pid;
+            program.streams = streams;
+        }
     }
 
-    pub fn push_ts(&mut self, data: &[u8]) -> usize {
-        if data.len() < 188 {
-            return 0;
+    // Move a collected PES packet into the packet buffer, false if it doesn't fit yet
+    fn flush_pes(&mut self, index: usize) -> bool {
+        let mut pes = std::mem::take(&mut self.ts_pes_[index].data);
+        if pes.len() > self.remind() {
+            self.rewind();
+        }
+        if pes.len() > self.remind() {
+            self.ts_pes_[index].data = pes;
+            return false;
+        }
+        // video PES packets of unbounded length get their real one, so get()
+        // doesn't have to look for the next start code. So do damaged ones
+        // that came out shorter, while data of a lost PES start behind the
+        // end of a damaged one is cut off.
+        if pes.len() >= 6 {
+            let declared = ((pes[4] as usize) << 8) | pes[5] as usize;
+            if declared != 0 && pes.len() > 6 + declared {
+                pes.truncate(6 + declared);
+            }
+            let len = pes.len() - 6;
+            if (declared == 0 || declared > len) && len <= 0xFFFF {
+                pes[4..6].copy_from_slice(&(len as u16).to_be_bytes());
+            }
         }
+        let entry = &mut self.ts_pes_[index];
+        if !pes.is_empty() && (entry.damaged || entry.discontinuity || entry.adaptation.is_some()) {
+            self.ts_marks_.push(TsMark {
+                offset:         self.len_,
+                damaged:        entry.damaged,
+                discontinuity:  entry.discontinuity,
+                adaptation:     entry.adaptation,
+            });
+        }
+        entry.damaged = false;
+        entry.discontinuity = false;
+        entry.adaptation = None;
+        self.push(&pes);
+        pes.clear();
+        self.ts_pes_[index].data = pes;
+        true
+    }
 
-        let mut offset:usize = 0;
+    // Tell a packet with payload from the one before on its PID. Packets
+    // without payload and discontinuities don't count.
+    fn continuity(entry: &TsPes, header: &TsHeader) -> Continuity {
+        if !header.has_payload || header.adaptation.discontinuity {
+            return Continuity::Next;
+        }
+        match entry.continuity {
+            Some(last) if last == header.continuity_counter => Continuity::Duplicate,
+            Some(last) => match header.continuity_counter.wrapping_sub(last).wrapping_sub(1) & 0x0F {
+                0 => Continuity::Next,
+                lost => Continuity::Lost(lost),
+            },
+            None => Continuity::Next,
+        }
+    }
 
-        if self.ts_offset_ > 0 {
-            offset = 188 - self.ts_offset_;
-            unsafe {
-                let dst: *mut u8 = self.ts_buffer_.as_ptr().add( self.ts_offset_ ) as *mut u8;
-                let src: *const u8 = data.as_ptr();
-                ptr::copy(src, dst, offset);
+    // Route one TS packet, false if the packet buffer has no room for it yet
+    fn ts_packet(&mut self, packet: &[u8; 188]) -> bool {
+        let header = match MpegPS::parse_ts(packet) {
+            Some(header) => header,
+            None => {
+                self.dropped_packets += 1;
+                return true;
             }
-            self.ts_offset_ = 0;
-
-            let ret = MpegPS::parse_ts(&self.ts_buffer_);
-            println!("============{:?}", ret);
-
-            let payload_len = 188 - ret.4;
-            if payload_len < self.remind() {
-                if self.pid2pes(ret) {
-                    unsafe {
-                        let dst: *mut u8 = self.buffer_.as_ptr().add(self.len_) as *mut u8;
-                        let src: *const u8 = self.ts_buffer_.as_ptr().add(ret.4);
-                        ptr::copy(src, dst, payload_len);
-                    }
-                    self.len_ = self.len_ + payload_len;
-                }
-            } else {
-                return 0;
+        };
+        let payload = &packet[header.payload..];
+
+        if let Some(pcr) = header.adaptation.pcr {
+            if self.program().is_some_and(|program| program.pcr_pid == header.pid) {
+                self.program_clock_ref = pcr;
+                self.system_clock_ref = pcr / 300;
+                self.has_program_clock_ref = true;
             }
         }
 
-        while data.len() >= 188 + offset {
-            let ret = MpegPS::parse_ts(&data[offset..offset+188]);
-            println!("============{:?}", ret);
+        if header.pid == 0x0000 || self.is_pmt(header.pid) {
+            self.ts_section(header.pid, header.payload_start, payload);
+            return true;
+        }
+        if !self.is_selected(header.pid) {
+            return true;
+        }
 
-            let payload_len = 188 - ret.4;
-            if payload_len < self.remind() {
-                if self.pid2pes(ret) {
-                    self.push( &data[ret.4 + offset .. offset+188] );
+        let index = match self.ts_pes_.iter().position(|entry| entry.pid == header.pid) {
+            Some(index) => index,
+            None => {
+                self.ts_pes_.push(TsPes {
+                    pid:            header.pid,
+                    data:           Vec::new(),
+                    continuity:     None,
+                    damaged:        false,
+                    discontinuity:  false,
+                    pending_discontinuity: false,
+                    adaptation:     None,
+                });
+                self.ts_pes_.len() - 1
+            }
+        };
+        let continuity = MpegPS::continuity(&self.ts_pes_[index], &header);
+        match continuity {
+            Continuity::Duplicate => {
+                self.duplicate_packets += 1;
+                return true;
+            }
+            Continuity::Lost(_) => {
+                // the PES packet being collected misses some of its data
+                let entry = &mut self.ts_pes_[index];
+                if !entry.data.is_empty() {
+                    entry.damaged = true;
                 }
-                offset += 188;
-            } else {
-                return offset;
+            }
+            Continuity::Next => {}
+        }
+        if header.payload_start {
+            // the previous PES packet of this PID ends here
+            if !self.flush_pes(index) {
+                return false;
             }
         }
 
-        if offset < data.len() {
-            unsafe {
-                let dst: *mut u8 = self.ts_buffer_.as_ptr() as *mut u8;
-                let src: *const u8 = data.as_ptr().add(offset);
-                ptr::copy(src, dst, data.len() - offset);
+        // the state changes once the packet can't come back with a full buffer
+        let entry = &mut self.ts_pes_[index];
+        if let Continuity::Lost(lost) = continuity {
+            self.lost_packets += lost as u64;
+        }
+        if header.has_payload {
+            entry.continuity = Some(header.continuity_counter);
+        }
+        entry.pending_discontinuity |= header.adaptation.discontinuity;
+        if header.payload_start {
+            entry.discontinuity = entry.pending_discontinuity;
+            entry.pending_discontinuity = false;
+            if !header.adaptation.is_empty() {
+                entry.adaptation = Some(header.adaptation);
             }
-            self.ts_offset_ = data.len() - offset;
+        } else if entry.data.is_empty() {
+            // joined in the middle of a PES packet
+            return true;
         }
 
-        return  data.len();
+        let pes = &mut entry.data;
+        pes.extend_from_slice(payload);
+        let complete = pes.len() >= 6 && {
+            let len = ((pes[4] as usize) << 8) | pes[5] as usize;
+            len != 0 && pes.len() >= 6 + len
+        };
+        if complete {
+            // may wait for the next packet of this PID if the buffer is full
+            self.flush_pes(index);
+        }
+        true
+    }
+
+    // Route the whole packets of the carried input, looking for the sync
+    // first where it was lost. True if any input was used up.
+    fn ts_process(&mut self, end: bool) -> bool {
+        let mut carry = std::mem::take(&mut self.ts_carry_);
+        let mut pos = 0;
+        while pos < carry.len() {
+            if !self.ts_synced_ {
+                match ts_sync(&carry[pos..], end) {
+                    Some(start) => {
+                        self.skipped_bytes += start as u64;
+                        pos += start;
+                        self.ts_synced_ = true;
+                    }
+                    None => {
+                        // keep the bytes that may still start a run
+                        let rest = carry.len() - pos;
+                        let skip = if end {
+                            rest
+                        } else {
+                            rest.saturating_sub((MpegPS::TS_SYNC_RUN - 1) * MpegPS::TS_PACKET_SIZE)
+                        };
+                        self.skipped_bytes += skip as u64;
+                        pos += skip;
+                        break;
+                    }
+                }
+            }
+            let next = pos + MpegPS::TS_PACKET_SIZE;
+            if carry.len() < next {
+                if end {
+                    // a cut off last packet
+                    self.skipped_bytes += (carry.len() - pos) as u64;
+                    pos = carry.len();
+                }
+                break;
+            }
+            // junk in between packets may start with a sync byte too, the
+            // next packet has to follow where there is more input
+            if carry[pos] != MpegPS::TS_SYNC_BYTE ||
+               carry.get(next).is_some_and(|&sync| sync != MpegPS::TS_SYNC_BYTE) {
+                self.ts_synced_ = false;
+                continue;
+            }
+
+            let mut packet = [0u8; 188];
+            packet.copy_from_slice(&carry[pos..pos + MpegPS::TS_PACKET_SIZE]);
+            if !self.ts_packet(&packet) {
+                break;
+            }
+            pos += MpegPS::TS_PACKET_SIZE;
+        }
+        carry.drain(..pos);
+        self.ts_carry_ = carry;
+        pos > 0
+    }
+
+    // Takes what fits behind the input still waiting for room in the packet
+    // buffer, returns how much of data that is
+    pub fn push_ts(&mut self, data: &[u8]) -> usize {
+        let take = MpegPS::TS_CARRY_LIMIT.saturating_sub(self.ts_carry_.len()).min(data.len());
+        self.ts_carry_.extend_from_slice(&data[..take]);
+        self.ts_process(false);
+        take
+    }
+
+    // At the end of a transport stream route the last packets and hand out
+    // the PES packets that were waiting for the next one of their PID.
+    // True if there was anything to hand out.
+    pub fn end_of_stream(&mut self) -> bool {
+        let mut flushed = self.ts_process(true);
+        for index in 0..self.ts_pes_.len() {
+            if !self.ts_pes_[index].data.is_empty() && self.flush_pes(index) {
+                flushed = true;
+            }
+        }
+        flushed
     }
 
 }
 
+impl Default for MpegPS {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,102 @@
+// Video Buffering Verifier, ISO/IEC 11172-2 and 13818-2 Annex C
+// Replays the picture sizes of a decoded stream through the decoder buffer
+// the stream was encoded for.
+
+use crate::video::PictureStats;
+
+const PICTURE_TYPE_B: u32 = 0x03;
+const PICTURE_TYPE_D: u32 = 0x04;
+// vbv_delay of a variable bit rate stream
+const VBV_DELAY_VARIABLE: u32 = 0xFFFF;
+const VBV_DELAY_CLOCK: f64 = 90000.0;
+
+// Rates and sizes from the sequence header
+#[derive(Clone, Copy, Debug)]
+pub struct VbvParameters {
+    // bits per second
+    pub bit_rate:    u64,
+    // bits
+    pub buffer_size: u64,
+    pub frame_rate:  f64,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum VbvError {
+    // a picture is not completely in the buffer when it is due
+    Underflow,
+    // more data arrives than the buffer holds
+    Overflow,
+}
+
+#[derive(Clone, Debug)]
+pub struct VbvEvent {
+    pub error:     VbvError,
+    // index in coding order
+    pub picture:   usize,
+    // seconds since the first bit entered the buffer
+    pub time:      f64,
+    // bits in the buffer just before the picture is removed
+    pub occupancy: u64,
+}
+
+// Constant bit rate streams start decoding after the vbv_delay of the first
+// picture and fill the buffer all the time. Variable bit rate streams start
+// with a full buffer and stop filling it while it is full, so only underflow
+// can happen there.
+pub fn verify(params: &VbvParameters, pictures: &[PictureStats]) -> Vec<VbvEvent> {
+    let mut events = Vec::new();
+    if pictures.is_empty() || params.bit_rate == 0 || params.frame_rate <= 0.0 {
+        return events;
+    }
+
+    let rate = params.bit_rate as f64;
+    let size = params.buffer_size as f64;
+    let total: f64 = pictures.iter().map(|picture| picture.bits as f64).sum();
+    let variable = pictures[0].vbv_delay == VBV_DELAY_VARIABLE;
+    let field_period = 1.0 / (2.0 * params.frame_rate);
+
+    let mut time = if variable {
+        size.min(total) / rate
+    } else {
+        pictures[0].vbv_delay as f64 / VBV_DELAY_CLOCK
+    };
+    let mut arrived = 0.0;
+    let mut removed = 0.0;
+    let mut last_time = 0.0;
+    let mut anchor_fields: Option<u32> = None;
+
+    for (index, picture) in pictures.iter().enumerate() {
+        arrived += rate * (time - last_time);
+        if variable {
+            arrived = arrived.min(removed + size);
+        }
+        arrived = arrived.min(total);
+        last_time = time;
+
+        let occupancy = arrived - removed;
+        if !variable && occupancy > size {
+            events.push(VbvEvent { error: VbvError::Overflow, picture: index, time, occupancy: occupancy as u64 });
+            // whatever did not fit is lost
+            arrived = removed + size;
+        }
+        removed += picture.bits as f64;
+        if arrived < removed {
+            events.push(VbvEvent { error: VbvError::Underflow, picture: index, time, occupancy: occupancy as u64 });
+            // the decoder waits for the rest of the picture
+            time += (removed - arrived) / rate;
+            last_time = time;
+            arrived = removed;
+        }
+
+        // reference frames are decoded while the previous reference frame is shown
+        let fields = if picture.picture_type == PICTURE_TYPE_B
+            || picture.picture_type == PICTURE_TYPE_D
+            || picture.fields == 1 {
+            picture.fields
+        } else {
+            anchor_fields.replace(picture.fields).unwrap_or(picture.fields)
+        };
+        time += fields as f64 * field_period;
+    }
+    events
+}
@@ -1,6 +1,12 @@
 // http://dvd.sourceforge.net/dvdinfo/mpeghdrs.html
 
+use std::io::Write;
+use std::rc::Rc;
+
 use crate::bitbuf;
+use crate::bitbuf::BitReader;
+use crate::idct;
+use crate::vbv;
 use crate::vlc;
 
 static MP1V_FRAME_RATE: [f32; 16] = [
@@ -8,6 +14,18 @@ static MP1V_FRAME_RATE: [f32; 16] = [
     60.000, 0.000, 0.000, 0.000, 0.000, 0.000, 0.000, 0.000
 ];
 
+// MPEG-1 pel aspect ratio, height / width of a sample times 10000
+static MP1V_PEL_ASPECT_RATIO: [u32; 16] = [
+    0, 10000, 6735, 7031, 7615, 8055, 8437, 8935,
+    9157, 9815, 10255, 10695, 10950, 11575, 12015, 0
+];
+
+// MPEG-2 display aspect ratio, width : height, 1 stands for square samples
+static MP2V_DISPLAY_ASPECT_RATIO: [(u32, u32); 16] = [
+    (0, 0), (1, 1), (4, 3), (16, 9), (221, 100), (0, 0), (0, 0), (0, 0),
+    (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0)
+];
+
 const MP1V_ZIG_ZAG: [i32; 64] = [
      0,  1,  8, 16,  9,  2,  3, 10,
     17, 24, 32, 25, 18, 11,  4,  5,
@@ -19,6 +37,24 @@ const MP1V_ZIG_ZAG: [i32; 64] = [
     53, 60, 61, 54, 47, 55, 62, 63
 ];
 
+const MP2V_ALTERNATE_SCAN: [i32; 64] = [
+     0,  8, 16, 24,  1,  9,  2, 10,
+    17, 25, 32, 40, 48, 56, 57, 49,
+    41, 33, 26, 18,  3, 11,  4, 12,
+    19, 27, 34, 42, 50, 58, 35, 43,
+    51, 59, 20, 28,  5, 13,  6, 14,
+    21, 29, 36, 44, 52, 60, 37, 45,
+    53, 61, 22, 30,  7, 15, 23, 31,
+    38, 46, 54, 62, 39, 47, 55, 63
+];
+
+static MP2V_NON_LINEAR_QUANTIZER_SCALE: [u32; 32] = [
+     0,  1,  2,  3,  4,  5,  6,  7,
+     8, 10, 12, 14, 16, 18, 20, 22,
+    24, 28, 32, 36, 40, 44, 48, 52,
+    56, 64, 72, 80, 88, 96, 104, 112
+];
+
 static MP1V_INTRA_QUANT_MATRIX: [u8; 64] = [
      8, 16, 19, 22, 26, 27, 29, 34,
     16, 16, 22, 24, 27, 29, 34, 37,
@@ -41,29 +77,93 @@ static MP1V_NON_INTRA_QUANT_MATRIX: [u8; 64] = [
     16, 16, 16, 16, 16, 16, 16, 16
 ];
 
-static MP1V_PREMULTIPLIER_MATRIX: [u8; 64] = [
-    32, 44, 42, 38, 32, 25, 17,  9,
-    44, 62, 58, 52, 44, 35, 24, 12,
-    42, 58, 55, 49, 42, 33, 23, 12,
-    38, 52, 49, 44, 38, 30, 20, 10,
-    32, 44, 42, 38, 32, 25, 17,  9,
-    25, 35, 33, 30, 25, 20, 14,  7,
-    17, 24, 23, 20, 17, 14,  9,  5,
-     9, 12, 12, 10,  9,  7,  5,  2
-];
-
 pub enum DecodeResult {
     GotOneFrame,
     NeedMoreData,
     InternalError,
 }
 
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Severity {
+    // reserved or unusual values a decoder can live with
+    Warning,
+    // forbidden values, missing marker bits and broken limits
+    Error,
+}
+
+// How much of the bitstream syntax the trace shows, each level includes the ones before
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug, Default)]
+pub enum TraceLevel {
+    #[default]
+    Off,
+    // sequence, GOP and picture headers with their extensions
+    Headers,
+    Slices,
+    Macroblocks,
+    // DC differentials and run/level pairs
+    Blocks,
+}
+
+// A conformance problem found in strict mode, decoding goes on regardless
+#[derive(Clone, Debug)]
+pub struct Violation {
+    pub severity: Severity,
+    pub message:  String,
+    // byte of the video elementary stream where the problem was found, for
+    // program and transport streams that is not the container byte
+    pub es_offset: usize,
+}
+
+// Coding statistics of one picture, each field of a frame counts on its own
+#[derive(Clone, Debug, Default)]
+pub struct PictureStats {
+    pub picture_type:        u32,
+    pub picture_structure:   u32,
+    pub temporal_reference:  u32,
+    pub vbv_delay:           u32,
+    // display duration in field periods, repeat_first_field included
+    pub fields:              u32,
+    // elementary stream byte of the first start code after the previous picture,
+    // where the sequence or GOP header in front of the picture starts if there is one
+    pub es_offset:           usize,
+    // from there to the end of the last slice
+    pub bits:                usize,
+    pub quantizer_min:       u32,
+    pub quantizer_max:       u32,
+    pub quantizer_sum:       u64,
+    pub intra_macroblocks:   u32,
+    pub inter_macroblocks:   u32,
+    pub skipped_macroblocks: u32,
+    // false for a picture skipped because its reference pictures are missing,
+    // only its header fields and size are known then
+    pub decoded:             bool,
+}
+
+impl PictureStats {
+    // over the coded macroblocks, skipped ones have no quantiser
+    pub fn quantizer_avg(&self) -> f32 {
+        let coded = self.intra_macroblocks + self.inter_macroblocks;
+        if coded == 0 {
+            return 0.0;
+        }
+        self.quantizer_sum as f32 / coded as f32
+    }
+}
+
+// Sequence parameters, sizes in samples and the rest as coded
 #[derive(Default)]
-struct CodecInfo {
+pub struct CodecInfo {
     pub pic_width: u32,
     pub pic_height: u32,
     pub aspect_ratio: u32,
     pub frame_rate: f32,
+    pub frame_rate_code: u32,
+    // MPEG-2 frame_rate_extension_n + 1 and frame_rate_extension_d + 1
+    pub frame_rate_n: u32,
+    pub frame_rate_d: u32,
+    pub bit_rate: u32,
+    pub vbv_buffer_size: u32,
+    pub constrained_parameters: bool,
 
     pub mb_width: u32,
     pub mb_height: u32,
@@ -75,6 +175,10 @@ struct CodecInfo {
     pub chroma_width: u32,
     pub chroma_height: u32,
 
+    pub is_mpeg2: bool,
+    pub progressive_sequence: bool,
+    pub chroma_format: u32,
+
     pub _parsed_:    bool,
 }
 
@@ -87,22 +191,38 @@ struct QuantMatrix {
 pub struct VideoMotion {
     pub full_px: i32,
     pub is_set: i32,
-    pub r_size: i32,
-    pub h:  i32,
-    pub v:  i32,
+    pub r_size: [i32; 2],
+    pub pmv: [[i32; 2]; 2],
+    pub field_select: [i32; 2],
 }
 
 #[derive(Default)]
 pub struct VideoRuntime {
     pub frame_current:     i32,
     pub frame_forward:     i32,
+    pub frame_backward:    i32,
+    pub frame_output:      i32,
+    pub anchor_pending:    bool,
 
     pub quantizer_scale:   u32,
     pub picture_type:      u32,
 
+    pub picture_structure: u32,
+    pub second_field:      bool,
+    pub top_field_first:   bool,
+    pub repeat_first_field: bool,
+    pub frame_pred_frame_dct: bool,
+    pub concealment_motion_vectors: bool,
+    pub q_scale_type:      bool,
+    pub intra_vlc_format:  bool,
+    pub alternate_scan:    bool,
+    pub intra_dc_precision: u32,
+    pub progressive_frame: bool,
+
     pub dc_predictor:      [i32;3],
 
     pub motion_forward:    VideoMotion,
+    pub motion_backward:   VideoMotion,
 
     pub mb_row : u32,
     pub mb_col : u32,
@@ -110,6 +230,11 @@ pub struct VideoRuntime {
     pub macroblock_intra:   i32,
     pub macroblock_type:    i32,
     pub macroblock_address: i32,
+    pub motion_type:        u32,
+    pub dct_type:           bool,
+    pub dmvector:           [i32; 2],
+
+    pub stats:              PictureStats,
 }
 
 #[derive(Default)]
@@ -119,38 +244,130 @@ pub struct VideoPlane {
     pub height: u32,
 }
 
+// The planes of a frame in output order
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Plane {
+    Y,
+    Cb,
+    Cr,
+}
+
+impl Plane {
+    pub const ALL: [Plane; 3] = [Plane::Y, Plane::Cb, Plane::Cr];
+}
+
 #[derive(Default)]
 pub struct VideoFrame {
     pub time: f64,
+    // elementary stream byte where the picture starts, as PictureStats::es_offset,
+    // the first field for field pictures
+    pub es_offset: usize,
     pub width: u32,
     pub height: u32,
+    pub progressive: bool,
+    pub top_field_first: bool,
     pub y:      VideoPlane,
     pub cb:     VideoPlane,
     pub cr:     VideoPlane,
+    data_:      Box<[u8]>,
+}
+
+impl CodecInfo {
+    // Width : height of one sample, None for forbidden or reserved aspect_ratio codes
+    pub fn sample_aspect_ratio(&self) -> Option<(u32, u32)> {
+        let code = (self.aspect_ratio & 0x0F) as usize;
+        let (width, height) = if !self.is_mpeg2 {
+            (10000, MP1V_PEL_ASPECT_RATIO[code])
+        } else if code == 1 {
+            (1, 1)
+        } else {
+            // the display aspect ratio spread over the picture size
+            let (width, height) = MP2V_DISPLAY_ASPECT_RATIO[code];
+            (width * self.pic_height, height * self.pic_width)
+        };
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        let (mut a, mut b) = (width, height);
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        Some((width / a, height / a))
+    }
 }
 
-pub struct Mpeg1Video {
-    buffer_:      bitbuf::RingBitBuffer,
+impl VideoFrame {
+    pub fn plane(&self, plane: &VideoPlane) -> &[u8] {
+        let size = (plane.width * plane.height) as usize;
+        &self.data_[plane.base .. plane.base + size]
+    }
 
-    frame_base_:  Box<[u8]>,
-    frames_:      Vec<VideoFrame>,
+    // Rows of the picture without the macroblock padding
+    pub fn visible(&self, plane: Plane) -> impl Iterator<Item = &[u8]> + '_ {
+        let chroma_size = ((self.width + 1) >> 1, (self.height + 1) >> 1);
+        let (plane, (width, height)) = match plane {
+            Plane::Y => (&self.y, (self.width, self.height)),
+            Plane::Cb => (&self.cb, chroma_size),
+            Plane::Cr => (&self.cr, chroma_size),
+        };
+        self.plane(plane)
+            .chunks(plane.width as usize)
+            .take(height as usize)
+            .map(move |row| &row[..width as usize])
+    }
+}
+
+pub struct Mpeg1Video<B = bitbuf::RingBitBuffer> {
+    buffer_:      B,
+
+    frames_:      Vec<Rc<VideoFrame>>,
     block_data_:  [i32; 64],
+    idct_mode_:   idct::IdctMode,
+    end_of_stream_: bool,
+    // a read ran past the buffered data
+    underrun_:    bool,
+    strict_:      bool,
+    violations_:  Vec<Violation>,
+    trace_level_: TraceLevel,
+    trace_out_:   Option<Box<dyn Write>>,
+    collect_stats_: bool,
+    stats_:       Vec<PictureStats>,
 
     info_:      CodecInfo,
     qmatrix_:   QuantMatrix,
     runtime_:   VideoRuntime,
 }
 
-impl Mpeg1Video {
+impl<B: BitReader> Mpeg1Video<B> {
     const PICTURE_START_CODE: u32  = 0x00000100;
     const SEQUENCE_START_CODE: u32 = 0x000001B3;
+    const SEQUENCE_END: [u8; 4] = [0x00, 0x00, 0x01, 0xB7];
     const START_EXTENSION_CODE: u32 = 0xB5;
     const USER_DATA_CODE: u32 = 0xB2;
+    const GROUP_START_CODE: u32 = 0xB8;
     const SLICE_START: u32 = 0x01;
     const SLICE_LAST: u32 = 0xAF;
 
+    const SEQUENCE_EXTENSION_ID: u32 = 0x01;
+    const QUANT_MATRIX_EXTENSION_ID: u32 = 0x03;
+    const PICTURE_CODING_EXTENSION_ID: u32 = 0x08;
+
     const PICTURE_TYPE_I: u32 = 0x01;
     const PICTURE_TYPE_P: u32 = 0x02;
+    const PICTURE_TYPE_B: u32 = 0x03;
+    const PICTURE_TYPE_D: u32 = 0x04;
+
+    const PICTURE_STRUCTURE_TOP: u32 = 0x01;
+    const PICTURE_STRUCTURE_BOTTOM: u32 = 0x02;
+    const PICTURE_STRUCTURE_FRAME: u32 = 0x03;
+
+    const MOTION_TYPE_FIELD: u32 = 0x01;
+    const MOTION_TYPE_FRAME: u32 = 0x02;
+    const MOTION_TYPE_16X8: u32 = 0x02;
+    const MOTION_TYPE_DUAL_PRIME: u32 = 0x03;
+
+    const CHROMA_FORMAT_420: u32 = 0x01;
 
     const MAX_PICTURE_WIDTH: usize = 2048;
     const MAX_PICTURE_HEIGHT: usize = 2048;
@@ -158,8 +375,11 @@ impl Mpeg1Video {
     const DCT_SIZE_TABLE: [&'static [(i16, i16)];3]  = [&vlc::MP1V_DCT_SIZE_LUMINANCE,
                                                 &vlc::MP1V_DCT_SIZE_CHROMINANCE,
                                                 &vlc::MP1V_DCT_SIZE_CHROMINANCE];
+    const MP2V_DCT_SIZE_TABLE: [&'static [(i16, i16)];3] = [&vlc::MP2V_DCT_SIZE_LUMINANCE,
+                                                    &vlc::MP2V_DCT_SIZE_CHROMINANCE,
+                                                    &vlc::MP2V_DCT_SIZE_CHROMINANCE];
 
-    pub fn new() -> Self {
+    fn with_buffer(buffer: B) -> Self {
         let mut info:    CodecInfo = Default::default();
         info._parsed_ = false;
 
@@ -167,226 +387,884 @@ impl Mpeg1Video {
         let non_intra_quant_matrix:[u8; 64] = [0; 64];
         let qm = QuantMatrix{ intra_quant_matrix, non_intra_quant_matrix};
 
-        let runtime: VideoRuntime = Default::default();
-
-        let buffer = bitbuf::RingBitBuffer::new();
-        let mut fbase: Vec<u8> = Vec::new();
-        let fbase_size = Mpeg1Video::MAX_PICTURE_WIDTH * Mpeg1Video::MAX_PICTURE_HEIGHT * 6;
-        fbase.resize(fbase_size , 0);
-        fbase.reserve_exact(0);
-
-        let frame_current: VideoFrame = Default::default();
-        let frame_forward: VideoFrame = Default::default();
+        let runtime = VideoRuntime {
+            frame_current:  -1,
+            frame_forward:  -1,
+            frame_backward: -1,
+            frame_output:   -1,
+            ..Default::default()
+        };
 
         Mpeg1Video {
             info_:          info,
             qmatrix_:       qm,
             runtime_:       runtime,
             buffer_:        buffer,
-            frame_base_:    fbase.into_boxed_slice(),
-            frames_:        vec![frame_current, frame_forward],
-            block_data_:    [0; 64]
+            frames_:        Vec::new(),
+            block_data_:    [0; 64],
+            idct_mode_:     Default::default(),
+            end_of_stream_: false,
+            underrun_:      false,
+            strict_:        false,
+            violations_:    Vec::new(),
+            trace_level_:   TraceLevel::Off,
+            trace_out_:     None,
+            collect_stats_: false,
+            stats_:         Vec::new(),
         }
     }
 
-    pub fn push(&mut self, data: &[u8]) -> Option<usize> {
-        self.buffer_.push(data)
+    pub fn set_idct(&mut self, mode: idct::IdctMode) {
+        self.idct_mode_ = mode;
     }
 
-    pub fn decode(&mut self) -> DecodeResult {
-        if self.buffer_.include_two_code(Mpeg1Video::PICTURE_START_CODE) == false {
-            return DecodeResult::NeedMoreData;
+    // Check sequence and picture headers against the standard while decoding
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict_ = strict;
+    }
+
+    // Violations found since the last call
+    pub fn take_violations(&mut self) -> Vec<Violation> {
+        std::mem::take(&mut self.violations_)
+    }
+
+    // Write every syntax element up to `level` with its bit offset to `out`
+    pub fn set_trace(&mut self, level: TraceLevel, out: Box<dyn Write>) {
+        self.trace_level_ = level;
+        self.trace_out_ = Some(out);
+    }
+
+    // Parameters of the current sequence, once its header is parsed
+    pub fn info(&self) -> Option<&CodecInfo> {
+        if !self.info_._parsed_ {
+            return None;
         }
-        if self.info_._parsed_ == false {
-            if self.buffer_.find_start_code(Mpeg1Video::SEQUENCE_START_CODE) == false {
-                println!("Can't find SEQ start code");
-                return DecodeResult::InternalError;
+        Some(&self.info_)
+    }
+
+    // Rates and buffer size the stream was encoded for, once the sequence header is parsed
+    pub fn vbv_parameters(&self) -> Option<vbv::VbvParameters> {
+        if !self.info_._parsed_ {
+            return None;
+        }
+        Some(vbv::VbvParameters {
+            bit_rate:    self.info_.bit_rate as u64 * 400,
+            buffer_size: self.info_.vbv_buffer_size as u64 * 16 * 1024,
+            frame_rate:  self.info_.frame_rate as f64,
+        })
+    }
+
+    // Keep the coding statistics of every decoded picture
+    pub fn set_stats(&mut self, collect: bool) {
+        self.collect_stats_ = collect;
+    }
+
+    // Statistics of the pictures decoded since the last call, in coding order
+    pub fn take_stats(&mut self) -> Vec<PictureStats> {
+        std::mem::take(&mut self.stats_)
+    }
+
+    pub fn decode(&mut self) -> DecodeResult {
+        loop {
+            // a picture is complete once the next one starts
+            let pictures = if self.end_of_stream_ { 1 } else { 2 };
+            if !self.buffer_.include_code(Self::PICTURE_START_CODE, pictures) {
+                return DecodeResult::NeedMoreData;
             }
-            self.decode_sequence_header();
+            let start = self.buffer_.pos();
+            self.underrun_ = false;
             if self.info_._parsed_ == false {
-                println!("Parsing sequence header error!");
-                return DecodeResult::InternalError;
+                if self.buffer_.find_start_code(Self::SEQUENCE_START_CODE) == false {
+                    println!("Can't find SEQ start code");
+                    return DecodeResult::InternalError;
+                }
+                if let Some(msg) = self.decode_sequence_header() {
+                    println!("Parsing sequence header error: {}", msg);
+                    return DecodeResult::InternalError;
+                }
+            }
+
+            // Pictures held back for reordering, first fields and undecodable
+            // B-pictures don't produce a frame, so go on with the next one.
+            match self.decode_picture(start) {
+                DecodeResult::NeedMoreData => continue,
+                result => return result,
             }
         }
-        return self.decode_picture();
     }
 
-    fn decode_sequence_header(&mut self) {
-        if self.buffer_.has(8 * 8) == false {
-            return;
+    // Hand out the last reference picture still held back for reordering,
+    // once no more data will arrive.
+    pub fn flush(&mut self) -> DecodeResult {
+        if !self.runtime_.anchor_pending {
+            return DecodeResult::NeedMoreData;
         }
-        self.info_.pic_width = self.buffer_.read(12);
-        self.info_.pic_height = self.buffer_.read(12);
-        self.info_.aspect_ratio = self.buffer_.read(4);
-        self.info_.frame_rate = MP1V_FRAME_RATE[self.buffer_.read(4) as usize];
+        self.runtime_.anchor_pending = false;
+        self.runtime_.frame_output = self.runtime_.frame_backward;
+        DecodeResult::GotOneFrame
+    }
 
-        //skip bitRate, marker, bufferSize and constrained bit
-        self.buffer_.skip(18 + 1 + 10 + 1);
+    // The frame returned by the last GotOneFrame. The decoder never writes into
+    // a frame while it is held, so callers may keep it as long as they like.
+    pub fn frame(&self) -> Option<Rc<VideoFrame>> {
+        if self.runtime_.frame_output < 0 {
+            return None;
+        }
+        Some(self.frames_[self.runtime_.frame_output as usize].clone())
+    }
+
+    fn decode_sequence_header(&mut self) -> Option<String> {
+        let was_parsed = self.info_._parsed_;
+        let old_size = (self.info_.pic_width, self.info_.pic_height);
+        self.info_._parsed_ = false;
+
+        if self.buffer_.has(8 * 8) == false {
+            return Some("sequence header is truncated".to_string());
+        }
+        self.trace_start_code(TraceLevel::Headers, "sequence_header");
+        self.info_.pic_width = self.read_traced(TraceLevel::Headers, "horizontal_size_value", 12);
+        self.info_.pic_height = self.read_traced(TraceLevel::Headers, "vertical_size_value", 12);
+        self.info_.aspect_ratio = self.read_traced(TraceLevel::Headers, "aspect_ratio_information", 4);
+        self.info_.frame_rate_code = self.read_traced(TraceLevel::Headers, "frame_rate_code", 4);
+        self.info_.frame_rate = MP1V_FRAME_RATE[self.info_.frame_rate_code as usize];
+
+        self.info_.bit_rate = self.read_traced(TraceLevel::Headers, "bit_rate_value", 18);
+        let marker_bit = self.read_traced(TraceLevel::Headers, "marker_bit", 1);
+        self.info_.vbv_buffer_size = self.read_traced(TraceLevel::Headers, "vbv_buffer_size_value", 10);
+        self.info_.constrained_parameters =
+            self.read_traced(TraceLevel::Headers, "constrained_parameters_flag", 1) == 0x01;
 
         //load intra quantiser matrix
-        if self.buffer_.read(1) == 0x01 {
+        if self.read_traced(TraceLevel::Headers, "load_intra_quantiser_matrix", 1) == 0x01 {
             if self.buffer_.has(64 * 8) == false {
-                return;
-            }
-            for i in 0..64 {
-                self.qmatrix_.intra_quant_matrix[i] = self.buffer_.read(8) as u8;
+                return Some("quantiser matrix is truncated".to_string());
             }
+            Self::read_quant_matrix(&mut self.buffer_, &mut self.qmatrix_.intra_quant_matrix);
         } else {
-            for i in 0..64 {
-                self.qmatrix_.intra_quant_matrix[i] = MP1V_INTRA_QUANT_MATRIX[i];
-            }
+            self.qmatrix_.intra_quant_matrix = MP1V_INTRA_QUANT_MATRIX;
         }
 
         //(load non-intra quantiser matrix)
-        if self.buffer_.read(1) == 0x01 {
+        if self.read_traced(TraceLevel::Headers, "load_non_intra_quantiser_matrix", 1) == 0x01 {
             if self.buffer_.has(64 * 8) == false {
-                return;
-            }
-            for i in 0..64 {
-                self.qmatrix_.non_intra_quant_matrix[i] = self.buffer_.read(8) as u8;
+                return Some("quantiser matrix is truncated".to_string());
             }
+            Self::read_quant_matrix(&mut self.buffer_, &mut self.qmatrix_.non_intra_quant_matrix);
         } else {
-            for i in 0..64 {
-                self.qmatrix_.non_intra_quant_matrix[i] = MP1V_NON_INTRA_QUANT_MATRIX[i];
-            }
+            self.qmatrix_.non_intra_quant_matrix = MP1V_NON_INTRA_QUANT_MATRIX;
+        }
+
+        // An MPEG-2 sequence header is followed by its sequence_extension
+        self.info_.is_mpeg2 = false;
+        self.info_.progressive_sequence = true;
+        self.info_.frame_rate_n = 1;
+        self.info_.frame_rate_d = 1;
+        self.info_.chroma_format = Self::CHROMA_FORMAT_420;
+        self.decode_extensions();
+
+        if self.info_.chroma_format != Self::CHROMA_FORMAT_420 {
+            return Some("only 4:2:0 chroma format is supported".to_string());
         }
 
         self.info_.mb_width = (self.info_.pic_width + 15) >> 4;
-        self.info_.mb_height = (self.info_.pic_height + 15) >> 4;
+        self.info_.mb_height = if self.info_.progressive_sequence {
+            (self.info_.pic_height + 15) >> 4
+        } else {
+            // interlaced frames are a whole number of field macroblock rows
+            ((self.info_.pic_height + 31) >> 5) << 1
+        };
         self.info_.luma_width = self.info_.mb_width << 4;
         self.info_.luma_height = self.info_.mb_height << 4;
         self.info_.chroma_width = self.info_.mb_width << 3;
         self.info_.chroma_height = self.info_.mb_height << 3;
         self.info_.mb_size = self.info_.mb_width *  self.info_.mb_height;
 
-        self.init_frames();
-        self.runtime_.frame_current = 0;
-        self.runtime_.frame_forward = 0;
+        if self.info_.luma_width as usize > Self::MAX_PICTURE_WIDTH
+            || self.info_.luma_height as usize > Self::MAX_PICTURE_HEIGHT {
+            return Some(format!("picture size {}x{} is too large", self.info_.pic_width, self.info_.pic_height));
+        }
+
+        if self.strict_ {
+            self.check_sequence_header(marker_bit);
+        }
+
+        // a repeated sequence header keeps the reference pictures
+        if !was_parsed || old_size != (self.info_.pic_width, self.info_.pic_height) {
+            self.init_frames();
+            self.runtime_.frame_current = -1;
+            self.runtime_.frame_forward = -1;
+            self.runtime_.frame_backward = -1;
+            self.runtime_.frame_output = -1;
+            self.runtime_.anchor_pending = false;
+            self.runtime_.second_field = false;
+        }
 
         self.info_._parsed_ = true;
+        None
+    }
+
+    // quantiser matrices are transmitted in zig-zag order
+    fn read_quant_matrix(buffer: &mut B, matrix: &mut [u8; 64]) {
+        for &i in MP1V_ZIG_ZAG.iter() {
+            matrix[i as usize] = buffer.read(8) as u8;
+        }
+    }
+
+    // Parse the extension and user data that follow a sequence or picture header
+    fn decode_extensions(&mut self) {
+        loop {
+            if self.buffer_.find_start() == false {
+                return;
+            }
+            let code = self.read_bits(8);
+            if code == Self::START_EXTENSION_CODE {
+                self.decode_extension();
+            } else if code == Self::USER_DATA_CODE {
+                self.trace_start_code(TraceLevel::Headers, "user_data");
+            } else {
+                self.buffer_.back(32);
+                return;
+            }
+        }
+    }
+
+    fn decode_extension(&mut self) {
+        self.trace_start_code(TraceLevel::Headers, "extension");
+        match self.read_traced(TraceLevel::Headers, "extension_start_code_identifier", 4) {
+            Self::SEQUENCE_EXTENSION_ID => self.decode_sequence_extension(),
+            Self::QUANT_MATRIX_EXTENSION_ID => self.decode_quant_matrix_extension(),
+            Self::PICTURE_CODING_EXTENSION_ID => self.decode_picture_coding_extension(),
+            // display, scalable and copyright extensions don't change decoding
+            _ => {}
+        }
+    }
+
+    fn decode_sequence_extension(&mut self) {
+        self.info_.is_mpeg2 = true;
+
+        self.read_traced(TraceLevel::Headers, "profile_and_level_indication", 8);
+        self.info_.progressive_sequence = self.read_traced(TraceLevel::Headers, "progressive_sequence", 1) == 0x01;
+        self.info_.chroma_format = self.read_traced(TraceLevel::Headers, "chroma_format", 2);
+        self.info_.pic_width |= self.read_traced(TraceLevel::Headers, "horizontal_size_extension", 2) << 12;
+        self.info_.pic_height |= self.read_traced(TraceLevel::Headers, "vertical_size_extension", 2) << 12;
+
+        self.info_.bit_rate |= self.read_traced(TraceLevel::Headers, "bit_rate_extension", 12) << 18;
+        if self.read_traced(TraceLevel::Headers, "marker_bit", 1) != 0x01 {
+            self.violation(Severity::Error, "marker bit missing in sequence extension");
+        }
+        self.info_.vbv_buffer_size |= self.read_traced(TraceLevel::Headers, "vbv_buffer_size_extension", 8) << 10;
+        self.read_traced(TraceLevel::Headers, "low_delay", 1);
+
+        self.info_.frame_rate_n = self.read_traced(TraceLevel::Headers, "frame_rate_extension_n", 2) + 1;
+        self.info_.frame_rate_d = self.read_traced(TraceLevel::Headers, "frame_rate_extension_d", 5) + 1;
+        self.info_.frame_rate = self.info_.frame_rate * self.info_.frame_rate_n as f32 / self.info_.frame_rate_d as f32;
+    }
+
+    fn decode_quant_matrix_extension(&mut self) {
+        if self.read_traced(TraceLevel::Headers, "load_intra_quantiser_matrix", 1) == 0x01 {
+            Self::read_quant_matrix(&mut self.buffer_, &mut self.qmatrix_.intra_quant_matrix);
+        }
+        if self.read_traced(TraceLevel::Headers, "load_non_intra_quantiser_matrix", 1) == 0x01 {
+            Self::read_quant_matrix(&mut self.buffer_, &mut self.qmatrix_.non_intra_quant_matrix);
+        }
+
+        // chroma matrices are only used by 4:2:2 and 4:4:4 streams
+        if self.read_traced(TraceLevel::Headers, "load_chroma_intra_quantiser_matrix", 1) == 0x01 {
+            self.buffer_.skip(64 * 8);
+        }
+        if self.read_traced(TraceLevel::Headers, "load_chroma_non_intra_quantiser_matrix", 1) == 0x01 {
+            self.buffer_.skip(64 * 8);
+        }
+    }
+
+    fn decode_picture_coding_extension(&mut self) {
+        let mut f_code = [[0u32; 2]; 2];
+        for (s, codes) in f_code.iter_mut().enumerate() {
+            for (t, code) in codes.iter_mut().enumerate() {
+                let pos = self.buffer_.pos();
+                *code = self.read_bits(4);
+                if self.tracing(TraceLevel::Headers) {
+                    self.trace(TraceLevel::Headers, pos, &format!("f_code[{}][{}] = {}", s, t, code));
+                }
+            }
+        }
+        self.runtime_.motion_forward.r_size = [f_code[0][0] as i32 - 1, f_code[0][1] as i32 - 1];
+        self.runtime_.motion_backward.r_size = [f_code[1][0] as i32 - 1, f_code[1][1] as i32 - 1];
+
+        self.runtime_.intra_dc_precision = self.read_traced(TraceLevel::Headers, "intra_dc_precision", 2);
+        self.runtime_.picture_structure = self.read_traced(TraceLevel::Headers, "picture_structure", 2);
+        self.runtime_.top_field_first = self.read_traced(TraceLevel::Headers, "top_field_first", 1) == 0x01;
+        self.runtime_.frame_pred_frame_dct = self.read_traced(TraceLevel::Headers, "frame_pred_frame_dct", 1) == 0x01;
+        self.runtime_.concealment_motion_vectors =
+            self.read_traced(TraceLevel::Headers, "concealment_motion_vectors", 1) == 0x01;
+        if self.strict_ {
+            self.check_extension_f_code(f_code);
+        }
+        self.runtime_.q_scale_type = self.read_traced(TraceLevel::Headers, "q_scale_type", 1) == 0x01;
+        self.runtime_.intra_vlc_format = self.read_traced(TraceLevel::Headers, "intra_vlc_format", 1) == 0x01;
+        self.runtime_.alternate_scan = self.read_traced(TraceLevel::Headers, "alternate_scan", 1) == 0x01;
+
+        self.runtime_.repeat_first_field = self.read_traced(TraceLevel::Headers, "repeat_first_field", 1) == 0x01;
+        self.read_traced(TraceLevel::Headers, "chroma_420_type", 1);
+        self.runtime_.progressive_frame = self.read_traced(TraceLevel::Headers, "progressive_frame", 1) == 0x01;
+
+        // skip composite display information
+        if self.read_traced(TraceLevel::Headers, "composite_display_flag", 1) == 0x01 {
+            self.buffer_.skip(20);
+        }
+    }
+
+    fn tracing(&self, level: TraceLevel) -> bool {
+        self.trace_level_ >= level
+    }
+
+    fn trace(&mut self, level: TraceLevel, pos: usize, text: &str) {
+        if self.trace_level_ >= level {
+            Self::write_trace(&mut self.trace_out_, level, pos, text);
+        }
+    }
+
+    fn write_trace(out: &mut Option<Box<dyn Write>>, level: TraceLevel, pos: usize, text: &str) {
+        if let Some(out) = out {
+            let indent = (level as usize - 1) * 2;
+            // the trace is best effort, a closed pipe doesn't stop decoding
+            let _ = writeln!(out, "{:>10} {:indent$}{}", pos, "", text, indent = indent);
+        }
+    }
+
+    // the start code has been read already
+    fn trace_start_code(&mut self, level: TraceLevel, name: &str) {
+        let pos = self.buffer_.pos().saturating_sub(32);
+        self.trace(level, pos, name);
+    }
+
+    // Reads past the buffered data give 0 and mark the picture as broken
+    fn read_bits(&mut self, count: usize) -> u32 {
+        self.buffer_.read_checked(count).unwrap_or_else(|| {
+            self.underrun_ = true;
+            0
+        })
+    }
+
+    fn read_vlc(&mut self, vlc_table: &[(i16, i16)]) -> i16 {
+        self.buffer_.read_vlc_checked(vlc_table).unwrap_or_else(|| {
+            self.underrun_ = true;
+            0
+        })
+    }
+
+    fn read_vlc_u16(&mut self, vlc_table: &[(i16, u16)]) -> u16 {
+        self.buffer_.read_vlc_u16_checked(vlc_table).unwrap_or_else(|| {
+            self.underrun_ = true;
+            0
+        })
+    }
+
+    fn read_traced(&mut self, level: TraceLevel, name: &str, count: usize) -> u32 {
+        let pos = self.buffer_.pos();
+        let value = self.read_bits(count);
+        if self.trace_level_ >= level {
+            self.trace(level, pos, &format!("{} = {}", name, value));
+        }
+        value
+    }
+
+    fn violation(&mut self, severity: Severity, message: &str) {
+        if self.strict_ {
+            let es_offset = self.buffer_.pos() / 8;
+            self.violations_.push(Violation { severity, message: message.to_string(), es_offset });
+        }
+    }
+
+    fn check_sequence_header(&mut self, marker_bit: u32) {
+        let info = &self.info_;
+        let mut found: Vec<(Severity, String)> = Vec::new();
+
+        if marker_bit != 0x01 {
+            found.push((Severity::Error, "marker bit missing in sequence header".to_string()));
+        }
+        if info.pic_width == 0 || info.pic_height == 0 {
+            found.push((Severity::Error, format!("picture size {}x{} is forbidden", info.pic_width, info.pic_height)));
+        }
+        let last_aspect_ratio = if info.is_mpeg2 { 4 } else { 14 };
+        if info.aspect_ratio == 0 {
+            found.push((Severity::Error, "aspect_ratio_information 0 is forbidden".to_string()));
+        } else if info.aspect_ratio > last_aspect_ratio {
+            found.push((Severity::Warning, format!("aspect_ratio_information {} is reserved", info.aspect_ratio)));
+        }
+        if info.frame_rate_code == 0 {
+            found.push((Severity::Error, "frame_rate_code 0 is forbidden".to_string()));
+        } else if info.frame_rate_code > 8 {
+            found.push((Severity::Warning, format!("frame_rate_code {} is reserved", info.frame_rate_code)));
+        }
+        if info.bit_rate == 0 {
+            found.push((Severity::Error, "bit_rate 0 is forbidden".to_string()));
+        }
+
+        if info.constrained_parameters && info.is_mpeg2 {
+            found.push((Severity::Error, "constrained_parameters_flag must be 0 in MPEG-2".to_string()));
+        } else if info.constrained_parameters {
+            // ISO/IEC 11172-2 2.4.3.2, the bit rate and buffer size are in units of 400 bit/s and 16 kbit
+            let mb_count = info.mb_width * ((info.pic_height + 15) >> 4);
+            if info.pic_width > 768 {
+                found.push((Severity::Error, format!("constrained parameters: width {} exceeds 768", info.pic_width)));
+            }
+            if info.pic_height > 576 {
+                found.push((Severity::Error, format!("constrained parameters: height {} exceeds 576", info.pic_height)));
+            }
+            if mb_count > 396 {
+                found.push((Severity::Error, format!("constrained parameters: {} macroblocks exceed 396", mb_count)));
+            }
+            if mb_count as f32 * info.frame_rate > 396.0 * 25.0 {
+                found.push((Severity::Error, format!("constrained parameters: {} macroblocks per second exceed 9900",
+                                                     mb_count as f32 * info.frame_rate)));
+            }
+            if info.frame_rate_code > 5 {
+                found.push((Severity::Error, format!("constrained parameters: picture rate {} exceeds 30", info.frame_rate)));
+            }
+            if info.bit_rate == 0x3FFFF {
+                found.push((Severity::Error, "constrained parameters: variable bit rate".to_string()));
+            } else if info.bit_rate * 400 > 1856000 {
+                found.push((Severity::Error, format!("constrained parameters: bit rate {} exceeds 1856000", info.bit_rate * 400)));
+            }
+            if info.vbv_buffer_size > 20 {
+                found.push((Severity::Error, format!("constrained parameters: vbv_buffer_size {} exceeds 20", info.vbv_buffer_size)));
+            }
+        }
+
+        for (severity, message) in found {
+            self.violation(severity, &message);
+        }
+    }
+
+    fn check_picture_f_code(&mut self, direction: &str, full_pel: i32, f_code: i32) {
+        if self.info_.is_mpeg2 {
+            // the motion parameters live in the picture coding extension
+            if full_pel != 0 || f_code != 7 {
+                self.violation(Severity::Error, &format!("MPEG-2 {} full_pel and f_code must be 0 and 7", direction));
+            }
+        } else if self.info_.constrained_parameters && f_code > 4 {
+            self.violation(Severity::Error, &format!("constrained parameters: {} f_code {} exceeds 4", direction, f_code));
+        }
     }
 
+    fn check_extension_f_code(&mut self, f_code: [[u32; 2]; 2]) {
+        // forward and backward f_code, 15 marks a direction the picture doesn't use
+        let used = match self.runtime_.picture_type {
+            Self::PICTURE_TYPE_P => [true, false],
+            Self::PICTURE_TYPE_B => [true, true],
+            _ => [self.runtime_.concealment_motion_vectors, false],
+        };
+        for (direction, codes) in f_code.iter().enumerate() {
+            let name = if direction == 0 { "forward" } else { "backward" };
+            for &code in codes.iter() {
+                if code == 0 {
+                    self.violation(Severity::Error, &format!("{} f_code 0 is forbidden", name));
+                } else if (10..=14).contains(&code) {
+                    self.violation(Severity::Error, &format!("{} f_code {} is reserved", name, code));
+                } else if !used[direction] && code != 15 {
+                    self.violation(Severity::Warning, &format!("unused {} f_code should be 15", name));
+                }
+            }
+        }
+    }
+
+    // Frames are allocated on demand, once every frame of the pool is in use
     fn init_frames(&mut self) {
-        // Allocate one big chunk of data for all 3 frames = 9 planes
+        self.frames_.clear();
+    }
+
+    fn new_frame(&self) -> VideoFrame {
         let luma_plane_size: u32 = self.info_.luma_width * self.info_.luma_height;
         let chroma_plane_size: u32 = self.info_.chroma_width * self.info_.chroma_height;
         let frame_data_size: u32 = luma_plane_size + 2 * chroma_plane_size;
 
-        for i in  0..self.frames_.len() {
-            let frame = &mut self.frames_[i];
-            let base = i * frame_data_size as usize;
+        let chroma = |base: u32| VideoPlane {
+            base: base as usize,
+            width: self.info_.chroma_width,
+            height: self.info_.chroma_height,
+        };
 
-            frame.width = self.info_.pic_width;
-            frame.height = self.info_.pic_height;
+        VideoFrame {
+            width: self.info_.pic_width,
+            height: self.info_.pic_height,
+            y: VideoPlane {
+                base: 0,
+                width: self.info_.luma_width,
+                height: self.info_.luma_height,
+            },
+            cr: chroma(luma_plane_size),
+            cb: chroma(luma_plane_size + chroma_plane_size),
+            data_: vec![0; frame_data_size as usize].into_boxed_slice(),
+            ..Default::default()
+        }
+    }
 
-            frame.y.width = self.info_.luma_width;
-            frame.y.height = self.info_.luma_height;
-            frame.y.base = base + 0;
+    // A frame of the pool that is neither a reference picture, nor the one
+    // frame() returns, nor held by the caller
+    fn free_frame(&mut self) -> i32 {
+        let busy = [self.runtime_.frame_forward, self.runtime_.frame_backward, self.runtime_.frame_output];
+        let free = (0..self.frames_.len()).find(|&i| {
+            !busy.contains(&(i as i32)) && Rc::strong_count(&self.frames_[i]) == 1
+        });
+        if let Some(i) = free {
+            return i as i32;
+        }
 
-            frame.cr.width = self.info_.chroma_width;
-            frame.cr.height = self.info_.chroma_height;
-            frame.cr.base = base + luma_plane_size as usize;
+        let frame = self.new_frame();
+        self.frames_.push(Rc::new(frame));
+        self.frames_.len() as i32 - 1
+    }
+
+    fn current_frame_mut(&mut self) -> &mut VideoFrame {
+        Rc::get_mut(&mut self.frames_[self.runtime_.frame_current as usize])
+            .expect("decoding into a frame that is still in use")
+    }
 
-            frame.cb.width = self.info_.chroma_width;
-            frame.cb.height = self.info_.chroma_height;
-            frame.cb.base = base + luma_plane_size as usize + chroma_plane_size as usize;
+    // Only traced and checked, the time code and GOP flags don't change decoding
+    fn decode_gop_header(&mut self) {
+        self.trace_start_code(TraceLevel::Headers, "group_of_pictures_header");
+        self.read_traced(TraceLevel::Headers, "drop_frame_flag", 1);
+        self.read_traced(TraceLevel::Headers, "time_code_hours", 5);
+        self.read_traced(TraceLevel::Headers, "time_code_minutes", 6);
+        if self.read_traced(TraceLevel::Headers, "marker_bit", 1) != 0x01 {
+            self.violation(Severity::Error, "marker bit missing in group_of_pictures_header");
         }
+        self.read_traced(TraceLevel::Headers, "time_code_seconds", 6);
+        self.read_traced(TraceLevel::Headers, "time_code_pictures", 6);
+        self.read_traced(TraceLevel::Headers, "closed_gop", 1);
+        self.read_traced(TraceLevel::Headers, "broken_link", 1);
     }
 
-    fn decode_picture(&mut self) -> DecodeResult {
-        if self.buffer_.find_start_code(Mpeg1Video::PICTURE_START_CODE) == false {
-            println!("Can't find PICTURE_START_CODE");
-            return DecodeResult::InternalError;
+    // The headers up to the first slice, the sequence header may be repeated
+    // in front of the picture
+    fn decode_picture_header(&mut self, start: usize) -> Option<String> {
+        // skip GOP headers and user data, a repeated sequence header may reload the matrices
+        loop {
+            if self.buffer_.find_start() == false {
+                return Some("Can't find PICTURE_START_CODE".to_string());
+            }
+            let code = self.read_bits(8);
+            if code == (Self::PICTURE_START_CODE & 0xFF) {
+                break;
+            }
+            if code == (Self::SEQUENCE_START_CODE & 0xFF) {
+                if let Some(msg) = self.decode_sequence_header() {
+                    return Some(format!("Parsing sequence header error: {}", msg));
+                }
+            } else if code == Self::GROUP_START_CODE {
+                self.decode_gop_header();
+            }
         }
 
         // get current picture type
-        self.buffer_.skip(10); // skip temporalReference
-        self.runtime_.picture_type = self.buffer_.read(3);
-        self.buffer_.skip(16); // skip vbv_delay
-
-        if self.runtime_.picture_type != Mpeg1Video::PICTURE_TYPE_I &&
-            self.runtime_.picture_type != Mpeg1Video::PICTURE_TYPE_P {
-            println!("Can't support B or D Picture Type ");
-            return DecodeResult::InternalError;
+        self.trace_start_code(TraceLevel::Headers, "picture_header");
+        let temporal_reference = self.read_traced(TraceLevel::Headers, "temporal_reference", 10);
+        self.runtime_.picture_type = self.read_traced(TraceLevel::Headers, "picture_coding_type", 3);
+        let vbv_delay = self.read_traced(TraceLevel::Headers, "vbv_delay", 16);
+
+        if self.runtime_.picture_type != Self::PICTURE_TYPE_I &&
+            self.runtime_.picture_type != Self::PICTURE_TYPE_P &&
+            self.runtime_.picture_type != Self::PICTURE_TYPE_B &&
+            self.runtime_.picture_type != Self::PICTURE_TYPE_D {
+            return Some(format!("Can't support Picture Type {}", self.runtime_.picture_type));
         }
 
         // forward full_px, f_code
-        if self.runtime_.picture_type  == Mpeg1Video::PICTURE_TYPE_P {
-            self.runtime_.motion_forward.full_px = self.buffer_.read(1) as i32;
+        if self.runtime_.picture_type  == Self::PICTURE_TYPE_P ||
+            self.runtime_.picture_type == Self::PICTURE_TYPE_B {
+            self.runtime_.motion_forward.full_px =
+                self.read_traced(TraceLevel::Headers, "full_pel_forward_vector", 1) as i32;
 
-            let f_code: i32 = self.buffer_.read(3) as i32;
+            let f_code: i32 = self.read_traced(TraceLevel::Headers, "forward_f_code", 3) as i32;
             if f_code == 0x00 {
-                println!("f_code can't be 0x00");
-                return DecodeResult::InternalError;
+                return Some("f_code can't be 0x00".to_string());
+            }
+            self.runtime_.motion_forward.r_size = [f_code - 1, f_code - 1];
+            if self.strict_ {
+                self.check_picture_f_code("forward", self.runtime_.motion_forward.full_px, f_code);
             }
-            self.runtime_.motion_forward.r_size = f_code - 1;
         }
 
-        loop {
-            // skip user data and extension
-            if self.buffer_.find_start() == false {
-                println!("Can't find slice start code");
-                return DecodeResult::InternalError;
+        // backward full_px, f_code
+        if self.runtime_.picture_type == Self::PICTURE_TYPE_B {
+            self.runtime_.motion_backward.full_px =
+                self.read_traced(TraceLevel::Headers, "full_pel_backward_vector", 1) as i32;
+
+            let f_code: i32 = self.read_traced(TraceLevel::Headers, "backward_f_code", 3) as i32;
+            if f_code == 0x00 {
+                return Some("f_code can't be 0x00".to_string());
             }
-            let code = self.buffer_.read(8);
-            if code == Mpeg1Video::USER_DATA_CODE || code == Mpeg1Video::START_EXTENSION_CODE {
-                continue;
+            self.runtime_.motion_backward.r_size = [f_code - 1, f_code - 1];
+            if self.strict_ {
+                self.check_picture_f_code("backward", self.runtime_.motion_backward.full_px, f_code);
             }
-            if code == Mpeg1Video::SLICE_START {
-                break;
+        }
+
+        // skip extra_information_picture
+        while self.read_traced(TraceLevel::Headers, "extra_bit_picture", 1) != 0x00 {
+            self.violation(Severity::Warning, "extra_information_picture is reserved");
+            self.read_traced(TraceLevel::Headers, "extra_information_picture", 8);
+        }
+
+        // MPEG-1 pictures are progressive frames, the picture_coding_extension
+        // of an MPEG-2 stream overrides these.
+        self.runtime_.picture_structure = Self::PICTURE_STRUCTURE_FRAME;
+        self.runtime_.top_field_first = false;
+        self.runtime_.repeat_first_field = false;
+        self.runtime_.frame_pred_frame_dct = true;
+        self.runtime_.concealment_motion_vectors = false;
+        self.runtime_.q_scale_type = false;
+        self.runtime_.intra_vlc_format = false;
+        self.runtime_.alternate_scan = false;
+        self.runtime_.intra_dc_precision = 0;
+        self.runtime_.progressive_frame = true;
+        self.decode_extensions();
+
+        if self.underrun_ {
+            return Some("Picture header is truncated".to_string());
+        }
+        if self.runtime_.picture_structure == 0x00 {
+            return Some("picture_structure can't be 0x00".to_string());
+        }
+
+        self.runtime_.stats = PictureStats {
+            picture_type: self.runtime_.picture_type,
+            picture_structure: self.runtime_.picture_structure,
+            temporal_reference,
+            vbv_delay,
+            fields: self.display_fields(),
+            es_offset: start / 8,
+            quantizer_min: u32::MAX,
+            decoded: true,
+            ..Default::default()
+        };
+        None
+    }
+
+    fn decode_picture(&mut self, start: usize) -> DecodeResult {
+        if let Some(msg) = self.decode_picture_header(start) {
+            println!("{}", msg);
+            return DecodeResult::InternalError;
+        }
+
+        // the second field of a frame goes into the frame the first one started
+        if self.runtime_.picture_structure == Self::PICTURE_STRUCTURE_FRAME
+            || !self.runtime_.second_field {
+            self.runtime_.second_field = false;
+            if !self.start_frame(start / 8) {
+                // skip pictures whose reference pictures are missing, their
+                // bits still went through the decoder buffer
+                self.skip_slices();
+                self.runtime_.stats.decoded = false;
+                self.finish_stats(start);
+                return DecodeResult::NeedMoreData;
             }
+        }
+
+        if self.buffer_.find_start() == false {
+            println!("Can't find slice start code");
+            return DecodeResult::InternalError;
+        }
+        let mut next_code = self.read_bits(8);
+        if !(Self::SLICE_START..=Self::SLICE_LAST).contains(&next_code) {
             println!("Can't find first slice start code");
             return DecodeResult::InternalError;
         }
 
-        let mut next_code = Mpeg1Video::SLICE_START;
-        while next_code >= Mpeg1Video::SLICE_START && next_code <= Mpeg1Video::SLICE_LAST {
+        while next_code >= Self::SLICE_START && next_code <= Self::SLICE_LAST {
+            let slice_offset = self.buffer_.pos() / 8 - 4;
             if let Some(msg) = self.decode_slice(next_code) {
-                println!("{}", msg);
+                println!("slice at byte {}:{}", slice_offset, msg);
                 return DecodeResult::InternalError;
             }
             if self.buffer_.find_start() == true {
-                next_code = self.buffer_.read(8);
+                next_code = self.read_bits(8);
             } else {
                 println!("Can't find start after slice!");
                 return DecodeResult::InternalError;
             }
         }
 
-        self.buffer_.back(32);
+        self.buffer_.back(32);
+        self.finish_stats(start);
+
+        if self.runtime_.picture_structure != Self::PICTURE_STRUCTURE_FRAME
+            && !self.runtime_.second_field {
+            self.runtime_.second_field = true;
+            return DecodeResult::NeedMoreData;
+        }
+        self.runtime_.second_field = false;
+
+        self.finish_frame()
+    }
+
+    // Up to the start code behind the slices of the picture
+    fn skip_slices(&mut self) {
+        while self.buffer_.find_start() {
+            let code = self.read_bits(8);
+            if !(Self::SLICE_START..=Self::SLICE_LAST).contains(&code) {
+                self.buffer_.back(32);
+                return;
+            }
+        }
+    }
+
+    // The picture ends at the buffer position
+    fn finish_stats(&mut self, start: usize) {
+        if self.collect_stats_ {
+            let mut stats = std::mem::take(&mut self.runtime_.stats);
+            stats.bits = self.buffer_.distance(start);
+            if stats.quantizer_min > stats.quantizer_max {
+                stats.quantizer_min = 0;
+            }
+            self.stats_.push(stats);
+        }
+    }
+
+    // Pick the frame the new picture is decoded into and rotate the reference pictures
+    fn start_frame(&mut self, es_offset: usize) -> bool {
+        let forward = self.runtime_.frame_forward;
+        let backward = self.runtime_.frame_backward;
+
+        if self.runtime_.picture_type == Self::PICTURE_TYPE_D {
+            // D-pictures are never used for prediction
+            self.runtime_.frame_current = self.free_frame();
+        } else if self.runtime_.picture_type == Self::PICTURE_TYPE_B {
+            if forward < 0 || backward < 0 {
+                return false;
+            }
+            self.runtime_.frame_current = self.free_frame();
+        } else {
+            if self.runtime_.picture_type == Self::PICTURE_TYPE_P && backward < 0 {
+                return false;
+            }
+            self.runtime_.frame_forward = backward;
+            self.runtime_.frame_current = self.free_frame();
+            self.runtime_.frame_backward = self.runtime_.frame_current;
+            if self.runtime_.picture_type == Self::PICTURE_TYPE_P
+                && self.runtime_.picture_structure == Self::PICTURE_STRUCTURE_FRAME {
+                self.copy_reference_frame();
+            }
+        }
+
+        let progressive = self.runtime_.progressive_frame;
+        // the first field of a field picture pair is shown first
+        let top_field_first = if self.runtime_.picture_structure == Self::PICTURE_STRUCTURE_FRAME {
+            self.runtime_.top_field_first
+        } else {
+            self.runtime_.picture_structure == Self::PICTURE_STRUCTURE_TOP
+        };
+        let frame = self.current_frame_mut();
+        frame.es_offset = es_offset;
+        frame.progressive = progressive;
+        frame.top_field_first = top_field_first;
+        true
+    }
+
+    // Output frames in display order: B- and D-pictures right away, a reference
+    // picture only once the next reference picture has been decoded.
+    fn finish_frame(&mut self) -> DecodeResult {
+        if self.runtime_.picture_type == Self::PICTURE_TYPE_B
+            || self.runtime_.picture_type == Self::PICTURE_TYPE_D {
+            self.runtime_.frame_output = self.runtime_.frame_current;
+            return DecodeResult::GotOneFrame;
+        }
+
+        let pending = self.runtime_.anchor_pending;
+        self.runtime_.anchor_pending = true;
+        if pending {
+            self.runtime_.frame_output = self.runtime_.frame_forward;
+            return DecodeResult::GotOneFrame;
+        }
+        DecodeResult::NeedMoreData
+    }
+
+    fn display_fields(&self) -> u32 {
+        if self.runtime_.picture_structure != Self::PICTURE_STRUCTURE_FRAME {
+            return 1;
+        }
+        // a progressive sequence repeats whole frames
+        match (self.runtime_.repeat_first_field, self.info_.progressive_sequence, self.runtime_.top_field_first) {
+            (false, _, _) => 2,
+            (true, false, _) => 3,
+            (true, true, false) => 4,
+            (true, true, true) => 6,
+        }
+    }
+
+    fn picture_mb_height(&self) -> u32 {
+        if self.runtime_.picture_structure == Self::PICTURE_STRUCTURE_FRAME {
+            self.info_.mb_height
+        } else {
+            self.info_.mb_height >> 1
+        }
+    }
+
+    fn field_parity(&self) -> i32 {
+        if self.runtime_.picture_structure == Self::PICTURE_STRUCTURE_BOTTOM {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn reset_dc_predictors(&mut self) {
+        let predictor = 128 << self.runtime_.intra_dc_precision;
+        self.runtime_.dc_predictor = [predictor; 3];
+    }
+
+    fn reset_motion_vectors(&mut self) {
+        self.runtime_.motion_forward.pmv = [[0; 2]; 2];
+        self.runtime_.motion_backward.pmv = [[0; 2]; 2];
+    }
 
-        self.runtime_.frame_forward = self.runtime_.frame_current;
-        self.runtime_.frame_current = 1 - self.runtime_.frame_current;
-        return DecodeResult::GotOneFrame;
+    fn read_quantizer_scale(&mut self, level: TraceLevel) -> u32 {
+        let code = self.read_traced(level, "quantiser_scale_code", 5);
+        if !self.info_.is_mpeg2 {
+            return code;
+        }
+        if self.runtime_.q_scale_type {
+            MP2V_NON_LINEAR_QUANTIZER_SCALE[code as usize]
+        } else {
+            code << 1
+        }
     }
 
     fn decode_slice(&mut self, slice_code: u32) -> Option<String> {
+        if slice_code > self.picture_mb_height() {
+            return Some(" slice vertical position error !".to_string());
+        }
         self.runtime_.macroblock_address = ((slice_code - 1) * self.info_.mb_width) as i32 - 1;
+        if self.tracing(TraceLevel::Slices) {
+            self.trace_start_code(TraceLevel::Slices, &format!("slice {}", slice_code));
+        }
 
         // Reset motion vectors and DC predictors
-        self.runtime_.motion_forward.h = 0;
-        self.runtime_.motion_forward.v = 0;
-        self.runtime_.dc_predictor[0] = 128;
-        self.runtime_.dc_predictor[1] = 128;
-        self.runtime_.dc_predictor[2] = 128;
+        self.reset_motion_vectors();
+        self.reset_dc_predictors();
 
         // quantizer scale
-        self.runtime_.quantizer_scale = self.buffer_.read(5);
+        self.runtime_.quantizer_scale = self.read_quantizer_scale(TraceLevel::Slices);
 
-        // skip extra
-        while self.buffer_.read(1) != 0x00 {
-            self.buffer_.read(8);
+        // skip extra, this covers MPEG-2 intra_slice_flag, intra_slice and reserved bits too
+        while self.read_traced(TraceLevel::Slices, "extra_bit_slice", 1) != 0x00 {
+            self.read_traced(TraceLevel::Slices, "extra_information_slice", 8);
         }
 
+        let mb_size = (self.info_.mb_width * self.picture_mb_height()) as i32;
         let mut slice_begin = true;
         loop {
             let ret = self.decode_macroblock(slice_begin);
             if let Some(msg) = ret {
                 return Some(msg);
             }
+            if self.underrun_ {
+                return Some(" bitstream underrun !".to_string());
+            }
 
             slice_begin = false;
-            if  (self.runtime_.macroblock_address >= self.info_.mb_size as i32 - 1)
+            if  (self.runtime_.macroblock_address >= mb_size - 1)
                 || self.buffer_.next_is_start() {
                 break;
             }
@@ -398,48 +1276,62 @@ impl Mpeg1Video {
     fn decode_macroblock(&mut self, slice_begin:bool) -> Option<String> {
         // Decode self->macroblock_address_increment
         let mut increment:i32 = 0;
+        let increment_pos = self.buffer_.pos();
 
-        let mut t = self.buffer_.read_vlc(&vlc::MP1V_MACROBLOCK_ADDRESS_INCREMENT);
+        let mut t = self.read_vlc(&vlc::MP1V_MACROBLOCK_ADDRESS_INCREMENT);
         while t == 34 {
             // macroblock_stuffing
-            t = self.buffer_.read_vlc(&vlc::MP1V_MACROBLOCK_ADDRESS_INCREMENT);
+            t = self.read_vlc(&vlc::MP1V_MACROBLOCK_ADDRESS_INCREMENT);
         }
         while t == 35 {
             increment += 33;
-            t = self.buffer_.read_vlc(&vlc::MP1V_MACROBLOCK_ADDRESS_INCREMENT);
+            t = self.read_vlc(&vlc::MP1V_MACROBLOCK_ADDRESS_INCREMENT);
         }
         increment += t as i32;
 
+        let mb_height = self.picture_mb_height();
+        let mb_size = (self.info_.mb_width * mb_height) as i32;
 
         if slice_begin {
             // The first self->macroblock_address_increment of each slice is relative
             // to beginning of the preverious row, not the preverious macroblock
             self.runtime_.macroblock_address += increment as i32;
         } else {
-            if self.runtime_.macroblock_address + increment as i32 >= self.info_.mb_size as i32 {
+            if self.runtime_.macroblock_address + increment as i32 >= mb_size {
                 return Some(" macroblock increment error !".to_string()); // invalid
             }
 
             if increment > 1 {
+                self.runtime_.stats.skipped_macroblocks += (increment - 1) as u32;
+
                 // Skipped macroblocks reset DC predictors
-                self.runtime_.dc_predictor[0] = 128;
-                self.runtime_.dc_predictor[1] = 128;
-                self.runtime_.dc_predictor[2] = 128;
+                self.reset_dc_predictors();
 
                 // Skipped macroblocks in P-pictures reset motion vectors
-                if self.runtime_.picture_type == Mpeg1Video::PICTURE_TYPE_P {
-                    self.runtime_.motion_forward.h = 0;
-                    self.runtime_.motion_forward.v = 0;
+                // and are predicted with a zero vector, in B-pictures they
+                // repeat the prediction of the previous macroblock.
+                if self.runtime_.picture_type == Self::PICTURE_TYPE_P {
+                    self.runtime_.motion_forward.is_set = 0;
+                    self.set_zero_motion();
                 }
             }
 
             // Predict skipped macroblocks
+            if increment > 1 && self.runtime_.picture_type == Self::PICTURE_TYPE_P {
+                if let Some(msg) = self.copy_macroblocks(self.runtime_.macroblock_address + 1, increment - 1) {
+                    return Some(msg);
+                }
+                self.runtime_.macroblock_address += increment - 1;
+                increment = 1;
+            }
             while increment > 1 {
                 self.runtime_.macroblock_address += 1;
                 self.runtime_.mb_row = self.runtime_.macroblock_address as u32 / self.info_.mb_width;
                 self.runtime_.mb_col = self.runtime_.macroblock_address as u32 % self.info_.mb_width;
 
-                self.predict_macroblock();
+                if let Some(msg) = self.predict_macroblock() {
+                    return Some(msg);
+                }
                 increment -= 1;
             }
             self.runtime_.macroblock_address += 1;
@@ -447,48 +1339,96 @@ impl Mpeg1Video {
 
         self.runtime_.mb_row = self.runtime_.macroblock_address as u32 / self.info_.mb_width;
         self.runtime_.mb_col = self.runtime_.macroblock_address as u32 % self.info_.mb_width;
+        if self.tracing(TraceLevel::Macroblocks) {
+            let text = format!("macroblock {} ({}, {}), macroblock_address_increment = {}",
+                               self.runtime_.macroblock_address, self.runtime_.mb_col, self.runtime_.mb_row, increment);
+            self.trace(TraceLevel::Macroblocks, increment_pos, &text);
+        }
 
         if self.runtime_.mb_col >= self.info_.mb_width
-           || self.runtime_.mb_row >= self.info_.mb_height {
+           || self.runtime_.mb_row >= mb_height {
             return Some(" macroblock address error !".to_string()); // corrupt stream;
         }
 
         // Process the current macroblock
-        if self.runtime_.picture_type == Mpeg1Video::PICTURE_TYPE_I {
-            self.runtime_.macroblock_type = self.buffer_.read_vlc(&vlc::MP1V_MACROBLOCK_TYPE_INTRA) as i32;
-        } else if self.runtime_.picture_type == Mpeg1Video::PICTURE_TYPE_P {
-            self.runtime_.macroblock_type = self.buffer_.read_vlc(&vlc::MP1V_MACROBLOCK_TYPE_PREDICTIVE) as i32;
+        let type_pos = self.buffer_.pos();
+        if self.runtime_.picture_type == Self::PICTURE_TYPE_I {
+            self.runtime_.macroblock_type = self.read_vlc(&vlc::MP1V_MACROBLOCK_TYPE_INTRA) as i32;
+        } else if self.runtime_.picture_type == Self::PICTURE_TYPE_P {
+            self.runtime_.macroblock_type = self.read_vlc(&vlc::MP1V_MACROBLOCK_TYPE_PREDICTIVE) as i32;
+        } else if self.runtime_.picture_type == Self::PICTURE_TYPE_B {
+            self.runtime_.macroblock_type = self.read_vlc(&vlc::MP1V_MACROBLOCK_TYPE_B) as i32;
         } else {
-            return Some("Dont' support B/D picture type".to_string());
+            self.runtime_.macroblock_type = self.read_vlc(&vlc::MP1V_MACROBLOCK_TYPE_D) as i32;
+            if self.runtime_.macroblock_type == 0 {
+                return Some(" D picture macroblock type error !".to_string());
+            }
+        }
+
+        if self.tracing(TraceLevel::Macroblocks) {
+            let text = format!("macroblock_type = {}", Self::macroblock_type_name(self.runtime_.macroblock_type));
+            self.trace(TraceLevel::Macroblocks, type_pos, &text);
         }
 
         self.runtime_.macroblock_intra = self.runtime_.macroblock_type & 0x01;
         self.runtime_.macroblock_pattern = self.runtime_.macroblock_type & 0x02;
+        self.runtime_.motion_backward.is_set = self.runtime_.macroblock_type & 0x04;
         self.runtime_.motion_forward.is_set = self.runtime_.macroblock_type & 0x08;
 
+        self.decode_macroblock_modes();
+
         // Quantizer scale
         if (self.runtime_.macroblock_type & 0x10) != 0 {
-            self.runtime_.quantizer_scale = self.buffer_.read(5);
+            self.runtime_.quantizer_scale = self.read_quantizer_scale(TraceLevel::Macroblocks);
+        }
+
+        let quantizer_scale = self.runtime_.quantizer_scale;
+        let stats = &mut self.runtime_.stats;
+        stats.quantizer_min = stats.quantizer_min.min(quantizer_scale);
+        stats.quantizer_max = stats.quantizer_max.max(quantizer_scale);
+        stats.quantizer_sum += quantizer_scale as u64;
+        if self.runtime_.macroblock_intra != 0 {
+            stats.intra_macroblocks += 1;
+        } else {
+            stats.inter_macroblocks += 1;
         }
 
         if self.runtime_.macroblock_intra != 0 {
-            // Intra-coded macroblocks reset motion vectors
-            self.runtime_.motion_forward.h = 0;
-            self.runtime_.motion_forward.v = 0;
+            if self.runtime_.concealment_motion_vectors {
+                // Concealment vectors only update the predictors
+                self.decode_motion_vectors(0);
+                self.read_traced(TraceLevel::Macroblocks, "marker_bit", 1);
+            } else {
+                // Intra-coded macroblocks reset motion vectors
+                self.reset_motion_vectors();
+            }
         } else {
             // Non-intra macroblocks reset DC predictors
-            self.runtime_.dc_predictor[0] = 128;
-            self.runtime_.dc_predictor[1] = 128;
-            self.runtime_.dc_predictor[2] = 128;
+            self.reset_dc_predictors();
 
-            self.decode_motion_vectors();
-            self.predict_macroblock();
+            if self.runtime_.motion_forward.is_set != 0 {
+                self.decode_motion_vectors(0);
+            } else if self.runtime_.picture_type == Self::PICTURE_TYPE_P {
+                // No motion information in P-picture, reset vectors
+                self.set_zero_motion();
+            }
+            if self.runtime_.motion_backward.is_set != 0 {
+                self.decode_motion_vectors(1);
+            }
+            if let Some(msg) = self.predict_macroblock() {
+                return Some(msg);
+            }
         }
 
         // Decode blocks
+        let cbp_pos = self.buffer_.pos();
         let cbp:u32 =
             if self.runtime_.macroblock_pattern != 0 {
-                self.buffer_.read_vlc(&vlc::MP1V_CODE_BLOCK_PATTERN) as u32
+                let cbp = self.read_vlc(&vlc::MP1V_CODE_BLOCK_PATTERN) as u32;
+                if self.tracing(TraceLevel::Macroblocks) {
+                    self.trace(TraceLevel::Macroblocks, cbp_pos, &format!("coded_block_pattern = {:06b}", cbp));
+                }
+                cbp
             } else {
                 if self.runtime_.macroblock_intra != 0 {
                     0x3f
@@ -500,118 +1440,180 @@ impl Mpeg1Video {
         let mut mask:u32 = 0x20;
         for block in 0..6 {
             if (cbp & mask) != 0 {
-                self.decode_block(block as i32);
+                if let Some(msg) = self.decode_block(block) {
+                    return Some(msg);
+                }
             }
             mask >>= 1;
         }
 
+        // D-picture macroblocks end with end_of_macroblock
+        if self.runtime_.picture_type == Self::PICTURE_TYPE_D
+            && self.read_traced(TraceLevel::Macroblocks, "end_of_macroblock", 1) != 0x01 {
+            return Some(" end of macroblock error !".to_string());
+        }
+
         None
     }
 
-    fn predict_macroblock(&mut self) {
-        let mut fw_h = self.runtime_.motion_forward.h;
-        let mut fw_v = self.runtime_.motion_forward.v;
+    fn macroblock_type_name(macroblock_type: i32) -> String {
+        let flags = [(0x10, "quant"), (0x08, "motion_forward"), (0x04, "motion_backward"),
+                     (0x02, "pattern"), (0x01, "intra")];
+        let names: Vec<&str> = flags.iter()
+            .filter(|(bit, _)| macroblock_type & bit != 0)
+            .map(|(_, name)| *name)
+            .collect();
+        names.join("|")
+    }
+
+    // MPEG-2 frame_motion_type/field_motion_type and dct_type
+    fn decode_macroblock_modes(&mut self) {
+        let frame_picture = self.runtime_.picture_structure == Self::PICTURE_STRUCTURE_FRAME;
 
-        if self.runtime_.motion_forward.full_px == 1 {
-            fw_h <<= 1;
-            fw_v <<= 1;
-        }
+        self.runtime_.motion_type = if frame_picture {
+            Self::MOTION_TYPE_FRAME
+        } else {
+            Self::MOTION_TYPE_FIELD
+        };
+        self.runtime_.dct_type = false;
 
-        let src = self.frames_[self.runtime_.frame_forward as usize].y.base;
-        let dst = self.frames_[self.runtime_.frame_current as usize].y.base;
-        self.process_macroblock(src, dst, fw_h, fw_v, 16);
+        if !self.info_.is_mpeg2 {
+            return;
+        }
 
-        let src = self.frames_[self.runtime_.frame_forward as usize].cb.base;
-        let dst = self.frames_[self.runtime_.frame_current as usize].cb.base;
-        self.process_macroblock(src, dst, fw_h/2, fw_v/2, 8);
+        if (self.runtime_.motion_forward.is_set != 0 || self.runtime_.motion_backward.is_set != 0)
+            && (!frame_picture || !self.runtime_.frame_pred_frame_dct) {
+            let name = if frame_picture { "frame_motion_type" } else { "field_motion_type" };
+            self.runtime_.motion_type = self.read_traced(TraceLevel::Macroblocks, name, 2);
+        }
 
-        let src = self.frames_[self.runtime_.frame_forward as usize].cr.base;
-        let dst = self.frames_[self.runtime_.frame_current as usize].cr.base;
-        self.process_macroblock(src, dst, fw_h/2, fw_v/2, 8);
+        if frame_picture && !self.runtime_.frame_pred_frame_dct
+            && (self.runtime_.macroblock_intra != 0 || self.runtime_.macroblock_pattern != 0) {
+            self.runtime_.dct_type = self.read_traced(TraceLevel::Macroblocks, "dct_type", 1) == 0x01;
+        }
     }
 
-    // copy from source to dest with motion vector
-    fn process_macroblock(&mut self, src: usize, dst: usize,
-                          mv_h: i32, mv_v: i32, block_size: u32) {
-
-        let dw = block_size * self.info_.mb_width;
+    // Zero vector prediction from the same parity field for P-pictures
+    fn set_zero_motion(&mut self) {
+        let parity = self.field_parity();
+        self.runtime_.motion_type =
+            if self.runtime_.picture_structure == Self::PICTURE_STRUCTURE_FRAME {
+                Self::MOTION_TYPE_FRAME
+            } else {
+                Self::MOTION_TYPE_FIELD
+            };
+        self.runtime_.motion_forward.pmv = [[0; 2]; 2];
+        self.runtime_.motion_forward.field_select = [parity; 2];
+    }
 
-        let hp = mv_h >> 1;
-        let vp = mv_v >> 1;
-        let odd_h = (mv_h & 1) == 1;
-        let odd_v = (mv_v & 1) == 1;
+    fn motion(&self, direction: usize) -> &VideoMotion {
+        if direction == 0 {
+            &self.runtime_.motion_forward
+        } else {
+            &self.runtime_.motion_backward
+        }
+    }
 
-        let di:u32 = (self.runtime_.mb_row * dw + self.runtime_.mb_col) * block_size;
-        let si:i32 = ((self.runtime_.mb_row * block_size) as i32 + vp) * dw as i32 + (self.runtime_.mb_col * block_size) as i32 + hp;
+    fn motion_mut(&mut self, direction: usize) -> &mut VideoMotion {
+        if direction == 0 {
+            &mut self.runtime_.motion_forward
+        } else {
+            &mut self.runtime_.motion_backward
+        }
+    }
 
-        let mut di:usize = di as usize;
-        let mut si:usize = si as usize;
+    fn decode_motion_vectors(&mut self, direction: usize) {
+        let frame_picture = self.runtime_.picture_structure == Self::PICTURE_STRUCTURE_FRAME;
+        let motion_type = self.runtime_.motion_type;
+        let dual_prime = motion_type == Self::MOTION_TYPE_DUAL_PRIME;
 
-        let max_address = dw * (self.info_.mb_height * block_size - block_size + 1) - block_size;
-        let max_address = max_address as usize;
-        if si > max_address || di > max_address {
-            panic!("motion vector outof picture");  // corrupt video
-        }
+        // field prediction in frame pictures and 16x8 prediction in field pictures carry two vectors
+        let count = if (frame_picture && motion_type == Self::MOTION_TYPE_FIELD)
+            || (!frame_picture && motion_type == Self::MOTION_TYPE_16X8) {
+            2
+        } else {
+            1
+        };
+        let field_vectors = !frame_picture || motion_type != Self::MOTION_TYPE_FRAME;
+
+        for r in 0..count {
+            if field_vectors && !dual_prime {
+                let name = ["motion_vertical_field_select[0][0]", "motion_vertical_field_select[0][1]",
+                            "motion_vertical_field_select[1][0]", "motion_vertical_field_select[1][1]"];
+                let field_select = self.read_traced(TraceLevel::Macroblocks, name[r * 2 + direction], 1) as i32;
+                self.motion_mut(direction).field_select[r] = field_select;
+            }
+            let vector_pos = self.buffer_.pos();
 
-        let dest_scan = (dw - block_size) as usize;
-        let source_scan = (dw - block_size) as usize;
-        for _y in 0..block_size {
-            for _x in 0..block_size {
+            let r_size = self.motion(direction).r_size;
+            let pmv = self.motion(direction).pmv[r];
 
-                let pixel:u32 = match (odd_h, odd_v) {
-                        (false, false) => {
-                            self.frame_base_[src + si] as u32
-                        },
-                        (false, true) => {
-                            (self.frame_base_[src + si] as u32 + self.frame_base_[src + si + dw as usize] as u32 + 1) >> 1
-                        },
-                        (true, false) => {
-                            (self.frame_base_[src + si] as u32 + self.frame_base_[src + si + 1] as u32 + 1) >> 1
-                        },
-                        (true, true) => {
-                            (self.frame_base_[src + si] as u32 + self.frame_base_[src + si + 1] as u32 +
-                             self.frame_base_[src + si + dw as usize] as u32 + self.frame_base_[src + si + dw as usize + 1] as u32 + 2) >> 2
-                        },
-                    };
+            let h = self.decode_motion_vector(r_size[0], pmv[0]);
+            if dual_prime {
+                self.runtime_.dmvector[0] = self.read_dmvector();
+            }
 
-                self.frame_base_[dst + di] = pixel as u8;
+            // field vectors of frame pictures are predicted at half the vertical scale
+            let v = if frame_picture && field_vectors {
+                self.decode_motion_vector(r_size[1], pmv[1] >> 1) << 1
+            } else {
+                self.decode_motion_vector(r_size[1], pmv[1])
+            };
+            if dual_prime {
+                self.runtime_.dmvector[1] = self.read_dmvector();
+            }
 
-                di+=1;
-                si+=1;
+            self.motion_mut(direction).pmv[r] = [h, v];
+            if self.tracing(TraceLevel::Macroblocks) {
+                let text = format!("motion_vector[{}][{}] = ({}, {})", r, direction, h, v);
+                self.trace(TraceLevel::Macroblocks, vector_pos, &text);
             }
-            di += dest_scan;
-            si += source_scan;
+        }
+
+        if count == 1 {
+            let motion = self.motion_mut(direction);
+            motion.pmv[1] = motion.pmv[0];
         }
     }
 
-    fn decode_motion_vectors(&mut self) {
-        // Forward
-        if self.runtime_.motion_forward.is_set != 0 {
-            let r_size = self.runtime_.motion_forward.r_size;
-            self.runtime_.motion_forward.h = self.decode_motion_vector(r_size, self.runtime_.motion_forward.h);
-            self.runtime_.motion_forward.v = self.decode_motion_vector(r_size, self.runtime_.motion_forward.v);
-        } else if self.runtime_.picture_type == Mpeg1Video::PICTURE_TYPE_P {
-            // No motion information in P-picture, reset vectors
-            self.runtime_.motion_forward.h = 0;
-            self.runtime_.motion_forward.v = 0;
+    fn read_dmvector(&mut self) -> i32 {
+        let pos = self.buffer_.pos();
+        let dmvector = if self.read_bits(1) == 0x00 {
+            0
+        } else if self.read_bits(1) == 0x00 {
+            1
+        } else {
+            -1
+        };
+        if self.tracing(TraceLevel::Macroblocks) {
+            self.trace(TraceLevel::Macroblocks, pos, &format!("dmvector = {}", dmvector));
         }
+        dmvector
     }
 
     fn decode_motion_vector(&mut self, r_size:i32, mut motion: i32) -> i32 {
         let fscale = 1 << r_size;
-        let m_code = self.buffer_.read_vlc(&vlc::MP1V_VIDEO_MOTION) as i32;
+        let pos = self.buffer_.pos();
+        let m_code = self.read_vlc(&vlc::MP1V_VIDEO_MOTION) as i32;
         let r:i32;
         let mut d:i32;
 
         if (m_code != 0) && (fscale != 1) {
-            r = self.buffer_.read(r_size as usize) as i32;
+            r = self.read_bits(r_size as usize) as i32;
             d = ((m_code.abs() - 1) << r_size) + r + 1;
             if m_code < 0 {
                 d = -d;
             }
+            if self.tracing(TraceLevel::Macroblocks) {
+                let text = format!("motion_code = {}, motion_residual = {}", m_code, r);
+                self.trace(TraceLevel::Macroblocks, pos, &text);
+            }
         }
         else {
             d = m_code;
+            if self.tracing(TraceLevel::Macroblocks) {
+                self.trace(TraceLevel::Macroblocks, pos, &format!("motion_code = {}", m_code));
+            }
         }
 
         motion += d;
@@ -623,9 +1625,390 @@ impl Mpeg1Video {
         return motion;
     }
 
-    fn decode_block(&mut self, block: i32) {
+    fn reference_frame(&self, direction: usize) -> i32 {
+        if direction == 0 {
+            self.runtime_.frame_forward
+        } else {
+            self.runtime_.frame_backward
+        }
+    }
+
+    // The second field of a P-frame may predict from the first field of the same frame
+    fn field_reference(&self, direction: usize, field: i32) -> i32 {
+        if direction == 0 && self.runtime_.second_field
+            && self.runtime_.picture_type == Self::PICTURE_TYPE_P
+            && field != self.field_parity() {
+            return self.runtime_.frame_current;
+        }
+        self.reference_frame(direction)
+    }
+
+    fn predict_macroblock(&mut self) -> Option<String> {
+        let forward = self.runtime_.motion_forward.is_set != 0
+            || self.runtime_.picture_type == Self::PICTURE_TYPE_P;
+        let backward = self.runtime_.motion_backward.is_set != 0;
+
+        if forward {
+            if let Some(msg) = self.predict_motion(0, false) {
+                return Some(msg);
+            }
+        }
+        if backward {
+            // bidirectional prediction averages both directions
+            if let Some(msg) = self.predict_motion(1, forward) {
+                return Some(msg);
+            }
+        }
+        None
+    }
+
+    fn predict_motion(&mut self, direction: usize, average: bool) -> Option<String> {
+        let motion = self.motion(direction);
+        let field_select = motion.field_select;
+        let mut pmv = motion.pmv;
+        if motion.full_px == 1 {
+            for vector in pmv.iter_mut() {
+                vector[0] <<= 1;
+                vector[1] <<= 1;
+            }
+        }
+
+        let row = self.runtime_.mb_row;
+        let motion_type = self.runtime_.motion_type;
+
+        if self.runtime_.picture_structure == Self::PICTURE_STRUCTURE_FRAME {
+            let reference = self.reference_frame(direction);
+            if motion_type == Self::MOTION_TYPE_FIELD {
+                // each field of the macroblock has its own vector and reference field
+                for r in 0..2 {
+                    let src = (reference, field_select[r]);
+                    let mv = (pmv[r][0], pmv[r][1] >> 1);
+                    if let Some(msg) = self.predict_region(src, r as i32, row * 8, 8, mv, average) {
+                        return Some(msg);
+                    }
+                }
+                return None;
+            }
+            if motion_type == Self::MOTION_TYPE_DUAL_PRIME {
+                return self.predict_dual_prime_frame(reference, pmv[0]);
+            }
+            return self.predict_region((reference, -1), -1, row * 16, 16, (pmv[0][0], pmv[0][1]), average);
+        }
+
+        let parity = self.field_parity();
+        if motion_type == Self::MOTION_TYPE_16X8 {
+            // upper and lower half of the macroblock are predicted separately
+            for r in 0..2 {
+                let src = (self.field_reference(direction, field_select[r]), field_select[r]);
+                let mv = (pmv[r][0], pmv[r][1]);
+                if let Some(msg) = self.predict_region(src, parity, row * 16 + r as u32 * 8, 8, mv, average) {
+                    return Some(msg);
+                }
+            }
+            return None;
+        }
+        if motion_type == Self::MOTION_TYPE_DUAL_PRIME {
+            return self.predict_dual_prime_field(pmv[0]);
+        }
+        let src = (self.field_reference(direction, field_select[0]), field_select[0]);
+        self.predict_region(src, parity, row * 16, 16, (pmv[0][0], pmv[0][1]), average)
+    }
+
+    // Scale a same parity vector to the opposite parity field, rounding half away from zero
+    fn dual_prime_scale(vector: i32, m: i32) -> i32 {
+        (vector * m + (vector > 0) as i32) >> 1
+    }
+
+    fn predict_dual_prime_frame(&mut self, reference: i32, vector: [i32; 2]) -> Option<String> {
+        let row = self.runtime_.mb_row * 8;
+        let (mv_h, mv_v) = (vector[0], vector[1] >> 1);
+        let dmv = self.runtime_.dmvector;
+
+        // top field from the bottom reference field, then bottom field from the top one
+        let m = if self.runtime_.top_field_first { 1 } else { 3 };
+        let other = (Self::dual_prime_scale(mv_h, m) + dmv[0],
+                     Self::dual_prime_scale(mv_v, m) + dmv[1] - 1);
+        if let Some(msg) = self.predict_region((reference, 1), 0, row, 8, other, false) {
+            return Some(msg);
+        }
+
+        let m = if self.runtime_.top_field_first { 3 } else { 1 };
+        let other = (Self::dual_prime_scale(mv_h, m) + dmv[0],
+                     Self::dual_prime_scale(mv_v, m) + dmv[1] + 1);
+        if let Some(msg) = self.predict_region((reference, 0), 1, row, 8, other, false) {
+            return Some(msg);
+        }
+
+        // average with the same parity predictions
+        if let Some(msg) = self.predict_region((reference, 0), 0, row, 8, (mv_h, mv_v), true) {
+            return Some(msg);
+        }
+        self.predict_region((reference, 1), 1, row, 8, (mv_h, mv_v), true)
+    }
+
+    fn predict_dual_prime_field(&mut self, vector: [i32; 2]) -> Option<String> {
+        let row = self.runtime_.mb_row * 16;
+        let parity = self.field_parity();
+        let dmv = self.runtime_.dmvector;
+
+        let same = (self.field_reference(0, parity), parity);
+        if let Some(msg) = self.predict_region(same, parity, row, 16, (vector[0], vector[1]), false) {
+            return Some(msg);
+        }
+
+        let e = if parity == 0 { -1 } else { 1 };
+        let other = (Self::dual_prime_scale(vector[0], 1) + dmv[0],
+                     Self::dual_prime_scale(vector[1], 1) + dmv[1] + e);
+        let opposite = (self.field_reference(0, 1 - parity), 1 - parity);
+        self.predict_region(opposite, parity, row, 16, other, true)
+    }
+
+    // Predict `height` luma lines (and the matching chroma) of the current macroblock,
+    // starting at line `y` of the destination frame or field. Fields are -1 for frame
+    // addressing, 0 for the top and 1 for the bottom field.
+    fn predict_region(&mut self, src: (i32, i32), dst_field: i32, y: u32, height: u32,
+                      mv: (i32, i32), average: bool) -> Option<String> {
+        if src.0 < 0 {
+            return Some(" reference frame is missing !".to_string());
+        }
+        let x = self.runtime_.mb_col * 16;
+
+        for plane in 0..3 {
+            let src_frame = &self.frames_[src.0 as usize];
+            let dst_frame = &self.frames_[self.runtime_.frame_current as usize];
+            let (src_plane, dst_plane) = match plane {
+                0 => (&src_frame.y, &dst_frame.y),
+                1 => (&src_frame.cb, &dst_frame.cb),
+                _ => (&src_frame.cr, &dst_frame.cr),
+            };
+
+            // chroma vectors are halved, truncating towards zero
+            let (shift, mv_h, mv_v) = if plane == 0 {
+                (0, mv.0, mv.1)
+            } else {
+                (1, mv.0 / 2, mv.1 / 2)
+            };
+
+            let width = src_plane.width as usize;
+            let (src_base, src_scan, src_lines) =
+                Self::field_layout(src_plane.base, width, src_plane.height as usize, src.1);
+            let (dst_base, dst_scan, _) =
+                Self::field_layout(dst_plane.base, width, dst_plane.height as usize, dst_field);
+
+            let bw = (16 >> shift) as usize;
+            let bh = (height >> shift) as usize;
+            let bx = (x >> shift) as usize;
+            let by = (y >> shift) as usize;
+
+            let odd_h = (mv_h & 1) == 1;
+            let odd_v = (mv_v & 1) == 1;
+            let sx = bx as i32 + (mv_h >> 1);
+            let sy = by as i32 + (mv_v >> 1);
+            if sx < 0 || sy < 0
+                || sx as usize + bw + odd_h as usize > width
+                || sy as usize + bh + odd_v as usize > src_lines {
+                return Some(" motion vector out of picture !".to_string()); // corrupt video
+            }
+
+            let si = src_base + sy as usize * src_scan + sx as usize;
+            let di = dst_base + by * dst_scan + bx;
+            let current = self.runtime_.frame_current;
+            if src.0 == current {
+                // the other field of the picture being decoded, copy it out of the way
+                let (cw, ch) = (bw + odd_h as usize, bh + odd_v as usize);
+                let mut copy = vec![0u8; cw * ch];
+                let src_data = &self.frames_[current as usize].data_;
+                for (line, row) in copy.chunks_mut(cw).enumerate() {
+                    let begin = si + line * src_scan;
+                    row.copy_from_slice(&src_data[begin .. begin + cw]);
+                }
+                let dst_data = &mut self.current_frame_mut().data_;
+                Self::process_macroblock((&copy, 0, cw), (dst_data, di, dst_scan),
+                                         (bw, bh), (odd_h, odd_v), average);
+            } else {
+                let (src_frame, dst_frame) = Self::frame_pair(&mut self.frames_, src.0, current);
+                Self::process_macroblock((&src_frame.data_, si, src_scan), (&mut dst_frame.data_, di, dst_scan),
+                                         (bw, bh), (odd_h, odd_v), average);
+            }
+        }
+        None
+    }
+
+    // A reference frame to read from next to the frame being decoded
+    fn frame_pair(frames: &mut [Rc<VideoFrame>], src: i32, dst: i32) -> (&VideoFrame, &mut VideoFrame) {
+        let (src, dst) = (src as usize, dst as usize);
+        let (src_frame, dst_frame) = if src < dst {
+            let (head, tail) = frames.split_at_mut(dst);
+            (&head[src], &mut tail[0])
+        } else {
+            let (head, tail) = frames.split_at_mut(src);
+            (&tail[0], &mut head[dst])
+        };
+        let dst_frame = Rc::get_mut(dst_frame).expect("decoding into a frame that is still in use");
+        (src_frame, dst_frame)
+    }
+
+    // base, line scan and line count of a plane or one of its fields
+    fn field_layout(base: usize, width: usize, height: usize, field: i32) -> (usize, usize, usize) {
+        if field < 0 {
+            (base, width, height)
+        } else {
+            (base + field as usize * width, width << 1, height >> 1)
+        }
+    }
+
+    // A P frame picture starts as a copy of its reference frame. Its skipped
+    // macroblocks, a zero vector prediction from the same place, are then in
+    // place already, and a picture of nothing but skips costs one copy.
+    fn copy_reference_frame(&mut self) {
+        let (src_frame, dst_frame) =
+            Self::frame_pair(&mut self.frames_, self.runtime_.frame_forward, self.runtime_.frame_current);
+        dst_frame.data_.copy_from_slice(&src_frame.data_);
+    }
+
+    // Skipped macroblocks of a P field picture are copied line by line from the
+    // same place of the reference field, a frame picture already holds them.
+    fn copy_macroblocks(&mut self, first: i32, count: i32) -> Option<String> {
+        if self.runtime_.picture_structure == Self::PICTURE_STRUCTURE_FRAME {
+            return None;
+        }
+        let reference = self.runtime_.frame_forward;
+        if reference < 0 {
+            return Some(" reference frame is missing !".to_string());
+        }
+        let field = self.field_parity();
+        let mb_width = self.info_.mb_width as usize;
+        let (src_frame, dst_frame) = Self::frame_pair(&mut self.frames_, reference, self.runtime_.frame_current);
+
+        for plane in 0..3 {
+            let (src_plane, dst_plane) = match plane {
+                0 => (&src_frame.y, &dst_frame.y),
+                1 => (&src_frame.cb, &dst_frame.cb),
+                _ => (&src_frame.cr, &dst_frame.cr),
+            };
+            let size: usize = if plane == 0 { 16 } else { 8 };
+            let width = src_plane.width as usize;
+            let (src_base, scan, _) = Self::field_layout(src_plane.base, width, src_plane.height as usize, field);
+            let (dst_base, _, _) = Self::field_layout(dst_plane.base, width, dst_plane.height as usize, field);
+
+            let mut address = first as usize;
+            let end = (first + count) as usize;
+            while address < end {
+                let row = address / mb_width;
+                let col = address % mb_width;
+                let offset = row * size * scan + col * size;
+                let cols = (mb_width - col).min(end - address);
+                let len = cols * size;
+                for line in 0..size {
+                    let si = src_base + offset + line * scan;
+                    let di = dst_base + offset + line * scan;
+                    dst_frame.data_[di .. di + len].copy_from_slice(&src_frame.data_[si .. si + len]);
+                }
+                address += cols;
+            }
+        }
+        None
+    }
+
+    // copy from source to dest with motion vector
+    fn process_macroblock(src: (&[u8], usize, usize), dst: (&mut [u8], usize, usize),
+                          size: (usize, usize), odd: (bool, bool), average: bool) {
+        let (source, mut si, sw) = src;
+        let (dest, mut di, dw) = dst;
+        let (odd_h, odd_v) = odd;
+
+        if !odd_h && !odd_v && !average {
+            // full pel vectors copy whole lines
+            for _y in 0..size.1 {
+                dest[di .. di + size.0].copy_from_slice(&source[si .. si + size.0]);
+                di += dw;
+                si += sw;
+            }
+            return;
+        }
+
+        let dest_scan = dw - size.0;
+        let source_scan = sw - size.0;
+        for _y in 0..size.1 {
+            for _x in 0..size.0 {
+
+                let pixel:u32 = match (odd_h, odd_v) {
+                        (false, false) => {
+                            source[si] as u32
+                        },
+                        (false, true) => {
+                            (source[si] as u32 + source[si + sw] as u32 + 1) >> 1
+                        },
+                        (true, false) => {
+                            (source[si] as u32 + source[si + 1] as u32 + 1) >> 1
+                        },
+                        (true, true) => {
+                            (source[si] as u32 + source[si + 1] as u32 +
+                             source[si + sw] as u32 + source[si + sw + 1] as u32 + 2) >> 2
+                        },
+                    };
+
+                if average {
+                    dest[di] = ((dest[di] as u32 + pixel + 1) >> 1) as u8;
+                } else {
+                    dest[di] = pixel as u8;
+                }
+
+                di+=1;
+                si+=1;
+            }
+            di += dest_scan;
+            si += source_scan;
+        }
+    }
+
+    // Plane base, line scan and start offset of a block in the current picture
+    fn block_destination(&self, block: i32) -> (usize, u32, u32) {
+        let frame_current = &self.frames_[self.runtime_.frame_current as usize];
+        let (plane, size) = match block {
+            0..=3 => (&frame_current.y, 16),
+            4 => (&frame_current.cb, 8),
+            _ => (&frame_current.cr, 8),
+        };
+
+        let mut d = plane.base;
+        let mut dw = plane.width;
+        if self.runtime_.picture_structure != Self::PICTURE_STRUCTURE_FRAME {
+            // field pictures cover every other line of the frame
+            d += self.field_parity() as usize * plane.width as usize;
+            dw <<= 1;
+        }
+
+        let mut di = self.runtime_.mb_row * size * dw + self.runtime_.mb_col * size;
+        if block < 4 {
+            if (block & 1) != 0 {
+                di += 8;
+            }
+            if self.runtime_.dct_type {
+                // field DCT: blocks 0 and 1 hold the top field lines, 2 and 3 the bottom ones
+                if (block & 2) != 0 {
+                    di += dw;
+                }
+                dw <<= 1;
+            } else if (block & 2) != 0 {
+                di += dw << 3;
+            }
+        }
+        (d, dw, di)
+    }
+
+    fn decode_block(&mut self, block: i32) -> Option<String> {
         let mut n:i32 = 0;
-        let quant_matrix: &[u8];
+        let is_mpeg2 = self.info_.is_mpeg2;
+
+        // sum of the coefficients for MPEG-2 mismatch control
+        let mut sum:i32 = 0;
+
+        let trace = self.trace_level_ >= TraceLevel::Blocks;
+        if trace {
+            let pos = self.buffer_.pos();
+            Self::write_trace(&mut self.trace_out_, TraceLevel::Blocks, pos, &format!("block {}", block));
+        }
 
         // Decode DC coefficient of intra-coded blocks
         if self.runtime_.macroblock_intra != 0 {
@@ -636,12 +2019,18 @@ impl Mpeg1Video {
                 0
             };
 
-            let dct_size = self.buffer_.read_vlc(Mpeg1Video::DCT_SIZE_TABLE[plane_index as usize]);
+            let dc_pos = self.buffer_.pos();
+            let table = if self.info_.is_mpeg2 { Self::MP2V_DCT_SIZE_TABLE } else { Self::DCT_SIZE_TABLE };
+            let dct_size = self.read_vlc(table[plane_index as usize]);
             let predictor = self.runtime_.dc_predictor[plane_index as usize];
 
             // Read DC coeff
             if dct_size > 0 {
-                let differential:i32  = self.buffer_.read(dct_size as usize) as i32;
+                let differential:i32  = self.read_bits(dct_size as usize) as i32;
+                if trace {
+                    let text = format!("dct_dc_size = {}, dct_dc_differential = {}", dct_size, differential);
+                    Self::write_trace(&mut self.trace_out_, TraceLevel::Blocks, dc_pos, &text);
+                }
 
                 if (differential & (1 << (dct_size - 1))) != 0 {
                     self.block_data_[0] = predictor + differential;
@@ -651,100 +2040,147 @@ impl Mpeg1Video {
                 }
             } else {
                 self.block_data_[0] = predictor;
+                if trace {
+                    Self::write_trace(&mut self.trace_out_, TraceLevel::Blocks, dc_pos, "dct_dc_size = 0");
+                }
             }
 
             // Save predictor value
             self.runtime_.dc_predictor[plane_index as usize] = self.block_data_[0];
 
-            // Dequantize + premultiply
-            self.block_data_[0] <<= 3 + 5;
+            // Dequantize
+            sum = self.block_data_[0] << (3 - self.runtime_.intra_dc_precision);
+            self.block_data_[0] = sum;
 
-            quant_matrix = &self.qmatrix_.intra_quant_matrix;
             n = 1;
-        } else {
-            quant_matrix = &self.qmatrix_.non_intra_quant_matrix;
         }
 
+        let scan = if self.runtime_.alternate_scan {
+            &MP2V_ALTERNATE_SCAN
+        } else {
+            &MP1V_ZIG_ZAG
+        };
+        let intra_vlc = self.runtime_.macroblock_intra != 0 && self.runtime_.intra_vlc_format;
+        let coeff_table: &[(i16, u16)] = if intra_vlc {
+            &vlc::MP2V_DCT_COEFF_INTRA
+        } else {
+            &vlc::MP1V_DCT_COEFF
+        };
+        // MPEG-2 divides by 32 because its quantiser_scale is twice the MPEG-1 one
+        let divisor = if is_mpeg2 { 32 } else { 16 };
+
         // Decode AC coefficients (+DC for non-intra)
         let mut level:i32;
         loop {
-            let run:i32;
-            let coeff:u16 = self.buffer_.read_vlc_u16(&vlc::MP1V_DCT_COEFF);
+            // D-pictures only carry the DC coefficient
+            if self.runtime_.picture_type == Self::PICTURE_TYPE_D {
+                break;
+            }
 
-            if (coeff == 0x0001) && (n > 0) && (self.buffer_.read(1) == 0) {
+            let run:i32;
+            let coeff_pos = self.buffer_.pos();
+            let coeff:u16 = self.read_vlc_u16(coeff_table);
+
+            if intra_vlc {
+                if coeff == 0x0000 {
+                    // end_of_block
+                    if trace {
+                        Self::write_trace(&mut self.trace_out_, TraceLevel::Blocks, coeff_pos, "end_of_block");
+                    }
+                    break;
+                }
+            } else if (coeff == 0x0001) && (n > 0) && (self.read_bits(1) == 0) {
                 // end_of_block
+                if trace {
+                    Self::write_trace(&mut self.trace_out_, TraceLevel::Blocks, coeff_pos, "end_of_block");
+                }
                 break;
             }
 
             if coeff == 0xffff {
                 // escape, table B.5f
-                run = self.buffer_.read(6) as i32;
-                level = self.buffer_.read(8) as i32;
-                if level == 0 {
-                    level = self.buffer_.read(8) as i32;
-                } else if level == 128 {
-                    level = self.buffer_.read(8) as i32 - 256;
-                } else if level > 128 {
-                    level = level - 256;
+                run = self.read_bits(6) as i32;
+                if is_mpeg2 {
+                    level = self.read_bits(12) as i32;
+                    if level >= 2048 {
+                        level -= 4096;
+                    }
+                } else {
+                    level = self.read_bits(8) as i32;
+                    if level == 0 {
+                        level = self.read_bits(8) as i32;
+                    } else if level == 128 {
+                        level = self.read_bits(8) as i32 - 256;
+                    } else if level > 128 {
+                        level = level - 256;
+                    }
                 }
             } else {
                 run = (coeff >> 8) as i32;
                 level = (coeff & 0xff) as i32;
-                if self.buffer_.read(1) != 0 {
+                if self.read_bits(1) != 0 {
                     level = -level;
                 }
             }
 
+            if trace {
+                let text = format!("run = {}, level = {}{}", run, level, if coeff == 0xffff { " (escape)" } else { "" });
+                Self::write_trace(&mut self.trace_out_, TraceLevel::Blocks, coeff_pos, &text);
+            }
+
             n += run;
-            if n < 0 || n >= 64 {
-                panic!("Can't do run/level for DCT");   // invalid
+            if !(0..64).contains(&n) || self.underrun_ {
+                // corrupt or truncated block
+                self.block_data_ = [0; 64];
+                if self.underrun_ {
+                    return Some(" bitstream underrun in block !".to_string());
+                }
+                return Some(" run/level beyond the end of the block !".to_string());
             }
 
-            let de_zig_zagged = MP1V_ZIG_ZAG[n as usize];
+            let de_zig_zagged = scan[n as usize];
             n+=1;
 
-            // Dequantize, oddify, clip
+            // Dequantize, oddify, clip. ISO/IEC 11172-2 2.4.4.1 divides with "/",
+            // which truncates toward zero, and makes even levels odd toward zero,
+            // a shift would round negative levels down instead.
             level <<= 1;
             if self.runtime_.macroblock_intra == 0 {
                 level += if level < 0 { -1 } else { 1};
             }
-            level = (level * self.runtime_.quantizer_scale as i32 * quant_matrix[de_zig_zagged as usize] as i32) >> 4;
-            if (level & 1) == 0 {
-                level -= if level > 0 { 1 } else { -1 };
+            let weight = if self.runtime_.macroblock_intra != 0 {
+                self.qmatrix_.intra_quant_matrix[de_zig_zagged as usize]
+            } else {
+                self.qmatrix_.non_intra_quant_matrix[de_zig_zagged as usize]
+            };
+            level = level * self.runtime_.quantizer_scale as i32 * weight as i32 / divisor;
+            if !is_mpeg2 && (level & 1) == 0 {
+                level -= level.signum();
             }
             if level > 2047 {
                 level = 2047;
             } else if level < -2048 {
                 level = -2048;
             }
+            sum += level;
 
-            // Save premultiplied coefficient
-            self.block_data_[de_zig_zagged as usize] = level * MP1V_PREMULTIPLIER_MATRIX[de_zig_zagged as usize] as i32;
+            // Save dequantized coefficient
+            self.block_data_[de_zig_zagged as usize] = level;
         }
 
-        // Move block to its place
-        let d: usize;
-        let dw: u32;
-        let mut di: u32;
-
-        let frame_current = &self.frames_[self.runtime_.frame_current as usize];
-        if block < 4 {
-            d = frame_current.y.base;
-            dw = self.info_.luma_width;
-            di = (self.runtime_.mb_row * self.info_.luma_width + self.runtime_.mb_col) << 4;
-            if (block & 1) != 0 {
-                di += 8;
-            }
-            if (block & 2) != 0 {
-                di += self.info_.luma_width << 3;
-            }
-        }
-        else {
-            d = if block == 4 { frame_current.cb.base } else { frame_current.cr.base};
-            dw = self.info_.chroma_width;
-            di = ((self.runtime_.mb_row * self.info_.luma_width) << 2) + (self.runtime_.mb_col << 3);
+        if is_mpeg2 && (sum & 1) == 0 {
+            // Mismatch control, toggle the last coefficient to make the sum odd
+            let last = self.block_data_[63];
+            self.block_data_[63] = if (last & 1) != 0 { last - 1 } else { last + 1 };
+            n = 64;
         }
 
+        // Move block to its place
+        let (d, dw, mut di) = self.block_destination(block);
+        let current = self.runtime_.frame_current as usize;
+        let frame_base = &mut Rc::get_mut(&mut self.frames_[current])
+            .expect("decoding into a frame that is still in use").data_;
+
         let plm_clamp = |x:i32| -> u8 {
             if x > 255 {
                 return 255;
@@ -764,11 +2200,11 @@ impl Mpeg1Video {
         if self.runtime_.macroblock_intra != 0 {
             // Overwrite (no prediction)
             if n == 1 {
-                let clamped  = plm_clamp((self.block_data_[0] + 128) >> 8);
+                let clamped  = plm_clamp((self.block_data_[0] + 4) >> 3);
                 {
                     for _y in 0..8 {
                         for _x in 0..8 {
-                            self.frame_base_[d + di as usize] = clamped;
+                            frame_base[d + di as usize] = clamped;
                             di+=1;
                         }
                         di += dw - 8;
@@ -777,12 +2213,12 @@ impl Mpeg1Video {
                 self.block_data_[0] = 0;
             }
             else {
-                self.idct();
+                idct::idct(self.idct_mode_, &mut self.block_data_);
                 let mut si:usize = 0;
                 {
                     for _y in 0..8 {
                         for _x in 0..8 {
-                            self.frame_base_[d + di as usize] = plm_clamp(self.block_data_[si]);
+                            frame_base[d + di as usize] = plm_clamp(self.block_data_[si]);
                             di += 1;
                             si += 1;
                         }
@@ -795,11 +2231,11 @@ impl Mpeg1Video {
         else {
             // Add data to the predicted macroblock
             if n == 1 {
-                let value = (self.block_data_[0] + 128) >> 8;
+                let value = (self.block_data_[0] + 4) >> 3;
                 {
                     for _y in 0..8 {
                         for _x in 0..8 {
-                            self.frame_base_[d + di as usize] = plm_clamp(self.frame_base_[d + di as usize] as i32 + value);
+                            frame_base[d + di as usize] = plm_clamp(frame_base[d + di as usize] as i32 + value);
                             di+=1;
                         }
                         di += dw - 8;
@@ -808,11 +2244,11 @@ impl Mpeg1Video {
                 self.block_data_[0] = 0;
             }
             else {
-                self.idct();
+                idct::idct(self.idct_mode_, &mut self.block_data_);
                 let mut si:usize = 0;
                 for _y in 0..8 {
                     for _x in 0..8 {
-                        self.frame_base_[d + di as usize] = plm_clamp(self.frame_base_[d + di as usize] as i32 + self.block_data_[si]);
+                        frame_base[d + di as usize] = plm_clamp(frame_base[d + di as usize] as i32 + self.block_data_[si]);
                         di += 1;
                         si += 1;
                     }
@@ -821,89 +2257,50 @@ impl Mpeg1Video {
                 zero_block(&mut self.block_data_);
             }
         }
+        None
+    }
+}
+
+// Decoder fed incrementally through its ring buffer
+impl Mpeg1Video {
+    pub fn new() -> Self {
+        Self::with_buffer(bitbuf::RingBitBuffer::new())
+    }
+
+    // A ring of `capacity` bytes that grows up to `limit` bytes for large pictures
+    pub fn with_capacity(capacity: usize, limit: usize) -> Self {
+        Self::with_buffer(bitbuf::RingBitBuffer::with_capacity(capacity, limit))
+    }
+
+    // Bytes taken from `data`, fewer than offered once the ring is at its limit.
+    // Decode what is buffered, then push the rest again.
+    pub fn push(&mut self, data: &[u8]) -> Option<usize> {
+        self.buffer_.push(data)
     }
 
-    fn idct(&mut self) {
-        let block = &mut self.block_data_;
-        let mut b1:i32;
-        let mut b3:i32;
-        let mut b4:i32;
-        let mut b6:i32;
-        let mut b7:i32;
-        let mut tmp1:i32;
-        let mut tmp2:i32;
-        let mut m0:i32;
-        let mut x0:i32;
-        let mut x1:i32;
-        let mut x2:i32;
-        let mut x3:i32;
-        let mut x4:i32;
-        let mut y3:i32;
-        let mut y4:i32;
-        let mut y5:i32;
-        let mut y6:i32;
-        let mut y7:i32;
-
-        // Transform columns
-        for i in 0..8 {
-            b1 = block[4 * 8 + i];
-            b3 = block[2 * 8 + i] + block[6 * 8 + i];
-            b4 = block[5 * 8 + i] - block[3 * 8 + i];
-            tmp1 = block[1 * 8 + i] + block[7 * 8 + i];
-            tmp2 = block[3 * 8 + i] + block[5 * 8 + i];
-            b6 = block[1 * 8 + i] - block[7 * 8 + i];
-            b7 = tmp1 + tmp2;
-            m0 = block[0 * 8 + i];
-            x4 = ((b6 * 473 - b4 * 196 + 128) >> 8) - b7;
-            x0 = x4 - (((tmp1 - tmp2) * 362 + 128) >> 8);
-            x1 = m0 - b1;
-            x2 = (((block[2 * 8 + i] - block[6 * 8 + i]) * 362 + 128) >> 8) - b3;
-            x3 = m0 + b1;
-            y3 = x1 + x2;
-            y4 = x3 + b3;
-            y5 = x1 - x2;
-            y6 = x3 - b3;
-            y7 = -x0 - ((b4 * 473 + b6 * 196 + 128) >> 8);
-            block[0 * 8 + i] = b7 + y4;
-            block[1 * 8 + i] = x4 + y3;
-            block[2 * 8 + i] = y5 - x0;
-            block[3 * 8 + i] = y6 - y7;
-            block[4 * 8 + i] = y6 + y7;
-            block[5 * 8 + i] = x0 + y5;
-            block[6 * 8 + i] = y3 - x4;
-            block[7 * 8 + i] = y4 - b7;
-        }
-
-        // Transform rows
-        for ii in 0..8 {
-            let i = ii * 8;
-            b1 = block[4 + i];
-            b3 = block[2 + i] + block[6 + i];
-            b4 = block[5 + i] - block[3 + i];
-            tmp1 = block[1 + i] + block[7 + i];
-            tmp2 = block[3 + i] + block[5 + i];
-            b6 = block[1 + i] - block[7 + i];
-            b7 = tmp1 + tmp2;
-            m0 = block[0 + i];
-            x4 = ((b6 * 473 - b4 * 196 + 128) >> 8) - b7;
-            x0 = x4 - (((tmp1 - tmp2) * 362 + 128) >> 8);
-            x1 = m0 - b1;
-            x2 = (((block[2 + i] - block[6 + i]) * 362 + 128) >> 8) - b3;
-            x3 = m0 + b1;
-            y3 = x1 + x2;
-            y4 = x3 + b3;
-            y5 = x1 - x2;
-            y6 = x3 - b3;
-            y7 = -x0 - ((b4 * 473 + b6 * 196 + 128) >> 8);
-            block[0 + i] = (b7 + y4 + 128) >> 8;
-            block[1 + i] = (x4 + y3 + 128) >> 8;
-            block[2 + i] = (y5 - x0 + 128) >> 8;
-            block[3 + i] = (y6 - y7 + 128) >> 8;
-            block[4 + i] = (y6 + y7 + 128) >> 8;
-            block[5 + i] = (x0 + y5 + 128) >> 8;
-            block[6 + i] = (y3 - x4 + 128) >> 8;
-            block[7 + i] = (y4 - b7 + 128) >> 8;
+    // No more data will be pushed. The sequence end code terminates the last picture,
+    // so it can be decoded without waiting for the next picture start code.
+    pub fn end_of_stream(&mut self) -> Option<usize> {
+        if !self.buffer_.reserve(Self::SEQUENCE_END.len()) {
+            return None;
         }
+        self.end_of_stream_ = true;
+        self.buffer_.push(&Self::SEQUENCE_END)
+    }
+}
+
+// Decoder reading a whole elementary stream in place, without copying it
+impl<'a> Mpeg1Video<bitbuf::BitBuffer<'a>> {
+    pub fn from_slice(data: &'a [u8]) -> Self {
+        let buffer = bitbuf::BitBuffer::with_tail(data, &Self::SEQUENCE_END);
+        let mut decoder = Self::with_buffer(buffer);
+        decoder.end_of_stream_ = true;
+        decoder
     }
 }
 
+impl Default for Mpeg1Video {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -56,6 +56,24 @@ pub const MP1V_MACROBLOCK_TYPE_PREDICTIVE: [(i16, i16); 14]  = [
     (      -1,    0), (       0, 0x11),  //   6: 0000 0x
 ];
 
+pub const MP1V_MACROBLOCK_TYPE_B: [(i16, i16); 22] = [
+    (  1 << 1,    0), (  2 << 1,    0),  //   0: x
+    (  3 << 1,    0), (  4 << 1,    0),  //   1: 0x
+    (       0, 0x0c), (       0, 0x0e),  //   2: 1x
+    (  5 << 1,    0), (  6 << 1,    0),  //   3: 00x
+    (       0, 0x04), (       0, 0x06),  //   4: 01x
+    (  7 << 1,    0), (  8 << 1,    0),  //   5: 000x
+    (       0, 0x08), (       0, 0x0a),  //   6: 001x
+    (  9 << 1,    0), ( 10 << 1,    0),  //   7: 0000x
+    (       0, 0x1e), (       0, 0x01),  //   8: 0001x
+    (      -1,    0), (       0, 0x11),  //   9: 0000 0x
+    (       0, 0x16), (       0, 0x1a),  //  10: 0000 1x
+];
+
+pub const MP1V_MACROBLOCK_TYPE_D: [(i16, i16); 2]  = [
+    (      -1,    0), (       0,  0x01),  //   0: x
+];
+
 pub const MP1V_CODE_BLOCK_PATTERN: [(i16, i16); 126] = [
     (  1 << 1,    0), (  2 << 1,    0),  //   0: x
     (  3 << 1,    0), (  4 << 1,    0),  //   1: 0x
@@ -183,6 +201,36 @@ pub const MP1V_DCT_SIZE_CHROMINANCE: [(i16,i16); 18] = [
     (       0,    8), (      -1,    0),  //   8: 1111 111x
 ];
 
+// MPEG-2 extends dct_dc_size to 11 for intra_dc_precision 9 to 11 bits,
+// the code MPEG-1 leaves unused is the prefix of the longer ones
+pub const MP2V_DCT_SIZE_LUMINANCE: [(i16,i16); 22] = [
+    (  1 << 1,    0), (  2 << 1,    0),  //   0: x
+    (       0,    1), (       0,    2),  //   1: 0x
+    (  3 << 1,    0), (  4 << 1,    0),  //   2: 1x
+    (       0,    0), (       0,    3),  //   3: 10x
+    (       0,    4), (  5 << 1,    0),  //   4: 11x
+    (       0,    5), (  6 << 1,    0),  //   5: 111x
+    (       0,    6), (  7 << 1,    0),  //   6: 1111x
+    (       0,    7), (  8 << 1,    0),  //   7: 1111 1x
+    (       0,    8), (  9 << 1,    0),  //   8: 1111 11x
+    (       0,    9), ( 10 << 1,    0),  //   9: 1111 111x
+    (       0,   10), (       0,   11),  //  10: 1111 1111x
+];
+
+pub const MP2V_DCT_SIZE_CHROMINANCE: [(i16,i16); 22] = [
+    (  1 << 1,    0), (  2 << 1,    0),  //   0: x
+    (       0,    0), (       0,    1),  //   1: 0x
+    (       0,    2), (  3 << 1,    0),  //   2: 1x
+    (       0,    3), (  4 << 1,    0),  //   3: 11x
+    (       0,    4), (  5 << 1,    0),  //   4: 111x
+    (       0,    5), (  6 << 1,    0),  //   5: 1111x
+    (       0,    6), (  7 << 1,    0),  //   6: 1111 1x
+    (       0,    7), (  8 << 1,    0),  //   7: 1111 11x
+    (       0,    8), (  9 << 1,    0),  //   8: 1111 111x
+    (       0,    9), ( 10 << 1,    0),  //   9: 1111 1111x
+    (       0,   10), (       0,   11),  //  10: 1111 1111 1x
+];
+
 pub const MP1V_DCT_COEFF: [(i16, u16); 224] = [
     (  1 << 1,        0), (       0,   0x0001),  //   0: x
     (  2 << 1,        0), (  3 << 1,        0),  //   1: 0x
@@ -297,3 +345,168 @@ pub const MP1V_DCT_COEFF: [(i16, u16); 224] = [
     (       0,   0x1e01), (       0,   0x1d01),  // 110: 0000 0000 0001 110x
     (       0,   0x1c01), (       0,   0x1b01),  // 111: 0000 0000 0001 111x
 ];
+
+pub const MP2V_DCT_COEFF_INTRA: [(i16, u16); 246] = [
+    (  1 << 1,        0), (  2 << 1,        0),  //   0: x
+    (  3 << 1,        0), (  4 << 1,        0),  //   1: 0x
+    (       0,   0x0001), (  5 << 1,        0),  //   2: 1x
+    (  6 << 1,        0), (  7 << 1,        0),  //   3: 00x
+    (       0,   0x0101), (  8 << 1,        0),  //   4: 01x
+    (       0,   0x0002), (  9 << 1,        0),  //   5: 11x
+    ( 10 << 1,        0), ( 11 << 1,        0),  //   6: 000x
+    ( 12 << 1,        0), ( 13 << 1,        0),  //   7: 001x
+    (       0,   0x0000), (       0,   0x0003),  //   8: 011x
+    ( 14 << 1,        0), ( 15 << 1,        0),  //   9: 111x
+    ( 16 << 1,        0), ( 17 << 1,        0),  //  10: 0000x
+    ( 18 << 1,        0), ( 19 << 1,        0),  //  11: 0001x
+    ( 20 << 1,        0), (       0,   0x0201),  //  12: 0010x
+    (       0,   0x0102), (       0,   0x0301),  //  13: 0011x
+    (       0,   0x0004), (       0,   0x0005),  //  14: 1110x
+    ( 21 << 1,        0), ( 22 << 1,        0),  //  15: 1111x
+    ( 23 << 1,        0), (       0,   0xffff),  //  16: 0000 0x
+    ( 24 << 1,        0), ( 25 << 1,        0),  //  17: 0000 1x
+    (       0,   0x0007), (       0,   0x0006),  //  18: 0001 0x
+    (       0,   0x0401), (       0,   0x0501),  //  19: 0001 1x
+    ( 26 << 1,        0), ( 27 << 1,        0),  //  20: 0010 0x
+    ( 28 << 1,        0), ( 29 << 1,        0),  //  21: 1111 0x
+    ( 30 << 1,        0), ( 31 << 1,        0),  //  22: 1111 1x
+    ( 32 << 1,        0), ( 33 << 1,        0),  //  23: 0000 00x
+    (       0,   0x0701), (       0,   0x0801),  //  24: 0000 10x
+    (       0,   0x0601), (       0,   0x0202),  //  25: 0000 11x
+    ( 34 << 1,        0), ( 35 << 1,        0),  //  26: 0010 00x
+    ( 36 << 1,        0), ( 37 << 1,        0),  //  27: 0010 01x
+    (       0,   0x0901), (       0,   0x0103),  //  28: 1111 00x
+    (       0,   0x0a01), (       0,   0x0008),  //  29: 1111 01x
+    (       0,   0x0009), ( 38 << 1,        0),  //  30: 1111 10x
+    ( 39 << 1,        0), ( 40 << 1,        0),  //  31: 1111 11x
+    ( 41 << 1,        0), ( 42 << 1,        0),  //  32: 0000 000x
+    ( 43 << 1,        0), ( 44 << 1,        0),  //  33: 0000 001x
+    (       0,   0x0105), (       0,   0x0b01),  //  34: 0010 000x
+    (       0,   0x000b), (       0,   0x000a),  //  35: 0010 001x
+    (       0,   0x0d01), (       0,   0x0c01),  //  36: 0010 010x
+    (       0,   0x0302), (       0,   0x0104),  //  37: 0010 011x
+    (       0,   0x000c), (       0,   0x000d),  //  38: 1111 101x
+    (       0,   0x0203), (       0,   0x0402),  //  39: 1111 110x
+    (       0,   0x000e), (       0,   0x000f),  //  40: 1111 111x
+    ( 45 << 1,        0), ( 46 << 1,        0),  //  41: 0000 0000x
+    ( 47 << 1,        0), ( 48 << 1,        0),  //  42: 0000 0001x
+    (       0,   0x0502), (       0,   0x0e01),  //  43: 0000 0010x
+    ( 49 << 1,        0), (       0,   0x0f01),  //  44: 0000 0011x
+    ( 50 << 1,        0), ( 51 << 1,        0),  //  45: 0000 0000 0x
+    ( 52 << 1,        0), ( 53 << 1,        0),  //  46: 0000 0000 1x
+    ( 54 << 1,        0), ( 55 << 1,        0),  //  47: 0000 0001 0x
+    ( 56 << 1,        0), ( 57 << 1,        0),  //  48: 0000 0001 1x
+    (       0,   0x0602), (       0,   0x1001),  //  49: 0000 0011 0x
+    ( 58 << 1,        0), ( 59 << 1,        0),  //  50: 0000 0000 00x
+    ( 60 << 1,        0), ( 61 << 1,        0),  //  51: 0000 0000 01x
+    ( 62 << 1,        0), ( 63 << 1,        0),  //  52: 0000 0000 10x
+    ( 64 << 1,        0), ( 65 << 1,        0),  //  53: 0000 0000 11x
+    ( 66 << 1,        0), ( 67 << 1,        0),  //  54: 0000 0001 00x
+    ( 68 << 1,        0), ( 69 << 1,        0),  //  55: 0000 0001 01x
+    ( 70 << 1,        0), ( 71 << 1,        0),  //  56: 0000 0001 10x
+    ( 72 << 1,        0), ( 73 << 1,        0),  //  57: 0000 0001 11x
+    (      -1,        0), ( 74 << 1,        0),  //  58: 0000 0000 000x
+    ( 75 << 1,        0), ( 76 << 1,        0),  //  59: 0000 0000 001x
+    ( 77 << 1,        0), ( 78 << 1,        0),  //  60: 0000 0000 010x
+    ( 79 << 1,        0), ( 80 << 1,        0),  //  61: 0000 0000 011x
+    ( 81 << 1,        0), ( 82 << 1,        0),  //  62: 0000 0000 100x
+    ( 83 << 1,        0), ( 84 << 1,        0),  //  63: 0000 0000 101x
+    ( 85 << 1,        0), ( 86 << 1,        0),  //  64: 0000 0000 110x
+    ( 87 << 1,        0), ( 88 << 1,        0),  //  65: 0000 0000 111x
+    (       0,   0x000b), (       0,   0x0802),  //  66: 0000 0001 000x
+    (       0,   0x0403), (       0,   0x000a),  //  67: 0000 0001 001x
+    (       0,   0x0204), (       0,   0x0702),  //  68: 0000 0001 010x
+    (       0,   0x1501), (       0,   0x1401),  //  69: 0000 0001 011x
+    (       0,   0x0009), (       0,   0x1301),  //  70: 0000 0001 100x
+    (       0,   0x1201), (       0,   0x0105),  //  71: 0000 0001 101x
+    (       0,   0x0303), (       0,   0x0008),  //  72: 0000 0001 110x
+    (       0,   0x0602), (       0,   0x1101),  //  73: 0000 0001 111x
+    ( 89 << 1,        0), ( 90 << 1,        0),  //  74: 0000 0000 0001x
+    ( 91 << 1,        0), ( 92 << 1,        0),  //  75: 0000 0000 0010x
+    ( 93 << 1,        0), ( 94 << 1,        0),  //  76: 0000 0000 0011x
+    ( 95 << 1,        0), ( 96 << 1,        0),  //  77: 0000 0000 0100x
+    ( 97 << 1,        0), ( 98 << 1,        0),  //  78: 0000 0000 0101x
+    ( 99 << 1,        0), (100 << 1,        0),  //  79: 0000 0000 0110x
+    (101 << 1,        0), (102 << 1,        0),  //  80: 0000 0000 0111x
+    (       0,   0x0a02), (       0,   0x0902),  //  81: 0000 0000 1000x
+    (       0,   0x0503), (       0,   0x0304),  //  82: 0000 0000 1001x
+    (       0,   0x0205), (       0,   0x0107),  //  83: 0000 0000 1010x
+    (       0,   0x0106), (       0,   0x000f),  //  84: 0000 0000 1011x
+    (       0,   0x000e), (       0,   0x000d),  //  85: 0000 0000 1100x
+    (       0,   0x000c), (       0,   0x1a01),  //  86: 0000 0000 1101x
+    (       0,   0x1901), (       0,   0x1801),  //  87: 0000 0000 1110x
+    (       0,   0x1701), (       0,   0x1601),  //  88: 0000 0000 1111x
+    (103 << 1,        0), (104 << 1,        0),  //  89: 0000 0000 0001 0x
+    (105 << 1,        0), (106 << 1,        0),  //  90: 0000 0000 0001 1x
+    (107 << 1,        0), (108 << 1,        0),  //  91: 0000 0000 0010 0x
+    (109 << 1,        0), (110 << 1,        0),  //  92: 0000 0000 0010 1x
+    (111 << 1,        0), (112 << 1,        0),  //  93: 0000 0000 0011 0x
+    (113 << 1,        0), (114 << 1,        0),  //  94: 0000 0000 0011 1x
+    (       0,   0x001f), (       0,   0x001e),  //  95: 0000 0000 0100 0x
+    (       0,   0x001d), (       0,   0x001c),  //  96: 0000 0000 0100 1x
+    (       0,   0x001b), (       0,   0x001a),  //  97: 0000 0000 0101 0x
+    (       0,   0x0019), (       0,   0x0018),  //  98: 0000 0000 0101 1x
+    (       0,   0x0017), (       0,   0x0016),  //  99: 0000 0000 0110 0x
+    (       0,   0x0015), (       0,   0x0014),  // 100: 0000 0000 0110 1x
+    (       0,   0x0013), (       0,   0x0012),  // 101: 0000 0000 0111 0x
+    (       0,   0x0011), (       0,   0x0010),  // 102: 0000 0000 0111 1x
+    (115 << 1,        0), (116 << 1,        0),  // 103: 0000 0000 0001 00x
+    (117 << 1,        0), (118 << 1,        0),  // 104: 0000 0000 0001 01x
+    (119 << 1,        0), (120 << 1,        0),  // 105: 0000 0000 0001 10x
+    (121 << 1,        0), (122 << 1,        0),  // 106: 0000 0000 0001 11x
+    (       0,   0x0028), (       0,   0x0027),  // 107: 0000 0000 0010 00x
+    (       0,   0x0026), (       0,   0x0025),  // 108: 0000 0000 0010 01x
+    (       0,   0x0024), (       0,   0x0023),  // 109: 0000 0000 0010 10x
+    (       0,   0x0022), (       0,   0x0021),  // 110: 0000 0000 0010 11x
+    (       0,   0x0020), (       0,   0x010e),  // 111: 0000 0000 0011 00x
+    (       0,   0x010d), (       0,   0x010c),  // 112: 0000 0000 0011 01x
+    (       0,   0x010b), (       0,   0x010a),  // 113: 0000 0000 0011 10x
+    (       0,   0x0109), (       0,   0x0108),  // 114: 0000 0000 0011 11x
+    (       0,   0x0112), (       0,   0x0111),  // 115: 0000 0000 0001 000x
+    (       0,   0x0110), (       0,   0x010f),  // 116: 0000 0000 0001 001x
+    (       0,   0x0603), (       0,   0x1002),  // 117: 0000 0000 0001 010x
+    (       0,   0x0f02), (       0,   0x0e02),  // 118: 0000 0000 0001 011x
+    (       0,   0x0d02), (       0,   0x0c02),  // 119: 0000 0000 0001 100x
+    (       0,   0x0b02), (       0,   0x1f01),  // 120: 0000 0000 0001 101x
+    (       0,   0x1e01), (       0,   0x1d01),  // 121: 0000 0000 0001 110x
+    (       0,   0x1c01), (       0,   0x1b01),  // 122: 0000 0000 0001 111x
+];
+
+// The code words of a decoding table above as (value, code, length), sorted
+// by value for VLC writing. A value reachable by more than one code keeps the
+// shortest one. MP1V_DCT_COEFF needs dct_coeff_encode_table() instead.
+pub fn encode_table<T: Copy + Ord>(table: &[(i16, T)]) -> Vec<(T, u32, usize)> {
+    let mut codes: Vec<(T, u32, usize)> = Vec::new();
+    // nodes still to visit as (table index of the node, code so far, length so far)
+    let mut nodes = vec![(0usize, 0u32, 0usize)];
+    while let Some((node, code, length)) = nodes.pop() {
+        for bit in 0..2 {
+            let (next, value) = table[node + bit];
+            let code = (code << 1) | bit as u32;
+            if next > 0 {
+                nodes.push((next as usize, code, length + 1));
+            } else if next == 0 {
+                codes.push((value, code, length + 1));
+            }
+        }
+    }
+    codes.sort_by_key(|&(value, _, length)| (value, length));
+    codes.dedup_by_key(|&mut (value, _, _)| value);
+    codes
+}
+
+// end_of_block of table B.14, table B.15 has it as value 0
+pub const MP1V_END_OF_BLOCK: (u32, usize) = (0b10, 2);
+
+// Table B.14 for writing. Run 0 level 1 is '1s' only as the first coefficient
+// of a non-intra block. Anywhere else it is '11s', as the decoder takes '10'
+// for end_of_block, so MP1V_DCT_COEFF has the short code alone.
+pub fn dct_coeff_encode_table(first: bool) -> Vec<(u16, u32, usize)> {
+    let mut codes = encode_table(&MP1V_DCT_COEFF);
+    if !first {
+        if let Ok(index) = codes.binary_search_by_key(&0x0001, |&(value, _, _)| value) {
+            codes[index] = (0x0001, 0b11, 2);
+        }
+    }
+    codes
+}
@@ -0,0 +1,95 @@
+// YUV4MPEG2 output of decoded frames
+// https://wiki.multimedia.cx/index.php/YUV4MPEG2
+
+use std::io;
+use std::io::Write;
+
+use crate::bitbuf::BitReader;
+use crate::video::{CodecInfo, Mpeg1Video, Plane, VideoFrame};
+
+// frame_rate_code as a fraction, frame_rate_code 0 and 9..15 are forbidden
+static FRAME_RATE_FRACTION: [(u32, u32); 16] = [
+    (0, 1), (24000, 1001), (24, 1), (25, 1), (30000, 1001), (30, 1), (50, 1), (60000, 1001),
+    (60, 1), (0, 1), (0, 1), (0, 1), (0, 1), (0, 1), (0, 1), (0, 1)
+];
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// "0:0" stands for unknown in Y4M
+fn fraction(num: u64, den: u64) -> String {
+    if num == 0 || den == 0 {
+        return "0:0".to_string();
+    }
+    let d = gcd(num, den);
+    format!("{}:{}", num / d, den / d)
+}
+
+pub fn frame_rate(info: &CodecInfo) -> String {
+    let (num, den) = FRAME_RATE_FRACTION[(info.frame_rate_code & 0x0F) as usize];
+    fraction(num as u64 * info.frame_rate_n as u64, den as u64 * info.frame_rate_d as u64)
+}
+
+// Width : height of one sample
+pub fn pixel_aspect(info: &CodecInfo) -> String {
+    let (width, height) = info.sample_aspect_ratio().unwrap_or((0, 0));
+    fraction(width as u64, height as u64)
+}
+
+pub struct Y4mWriter<W: Write> {
+    out_:     W,
+    started_: bool,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(out: W) -> Self {
+        Y4mWriter {
+            out_:     out,
+            started_: false,
+        }
+    }
+
+    // Write the frame of the last GotOneFrame, the stream header goes before the first one
+    pub fn write_frame<B: BitReader>(&mut self, vcodec: &Mpeg1Video<B>) -> io::Result<()> {
+        let (info, frame) = match (vcodec.info(), vcodec.frame()) {
+            (Some(info), Some(frame)) => (info, frame),
+            _ => return Ok(()),
+        };
+        if !self.started_ {
+            self.write_header(info, &frame)?;
+            self.started_ = true;
+        }
+
+        self.out_.write_all(b"FRAME\n")?;
+        for &plane in Plane::ALL.iter() {
+            for row in frame.visible(plane) {
+                self.out_.write_all(row)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out_.flush()
+    }
+
+    fn write_header(&mut self, info: &CodecInfo, frame: &VideoFrame) -> io::Result<()> {
+        // MPEG-1 chroma sits between the luma samples, MPEG-2 chroma on the left ones
+        let (interlace, chroma) = if !info.is_mpeg2 {
+            ("p", "420jpeg")
+        } else if info.progressive_sequence {
+            ("p", "420mpeg2")
+        } else if frame.top_field_first {
+            ("t", "420mpeg2")
+        } else {
+            ("b", "420mpeg2")
+        };
+        writeln!(self.out_, "YUV4MPEG2 W{} H{} F{} I{} A{} C{}",
+                 frame.width, frame.height, frame_rate(info), interlace, pixel_aspect(info), chroma)
+    }
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use common::{Picture, Sequence, PICTURE_I, PICTURE_P};
+use std::path::PathBuf;
+use std::process::Command;
+
+// A 32x32 MPEG-2 stream of an I and a P frame picture, written once to `name`
+fn mpeg2_stream(name: &str) -> String {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let pictures = [Picture::new(PICTURE_I, 0), Picture { f_code: 2, ..Picture::new(PICTURE_P, 1) }];
+    std::fs::write(&path, common::textured_stream(&Sequence::mpeg2(32, 32, true), &pictures)).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+fn run(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_ez-mpeg1")).args(args).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+// `expected` as consecutive lines of `output`
+fn assert_lines(output: &str, expected: &[&str]) {
+    let lines: Vec<&str> = output.lines().collect();
+    assert!(lines.windows(expected.len()).any(|window| window == expected), "missing {:#?}", expected);
+}
+
+#[test]
+fn trace_headers() {
+    let output = run(&["--trace=headers", &mpeg2_stream("trace_headers.m2v")]);
+    assert_lines(&output, &[
+        "         0 sequence_header",
+        "        32 horizontal_size_value = 32",
+        "        44 vertical_size_value = 32",
+        "        56 aspect_ratio_information = 1",
+        "        60 frame_rate_code = 3",
+    ]);
+    // the forward f_code of an MPEG-2 P-picture is in its coding extension
+    assert_lines(&output, &[
+        "       776 picture_header",
+        "       808 temporal_reference = 1",
+        "       818 picture_coding_type = 2",
+        "       821 vbv_delay = 65535",
+        "       837 full_pel_forward_vector = 0",
+        "       838 forward_f_code = 7",
+        "       841 extra_bit_picture = 0",
+        "       848 extension",
+        "       880 extension_start_code_identifier = 8",
+        "       884 f_code[0][0] = 2",
+        "       888 f_code[0][1] = 2",
+        "       892 f_code[1][0] = 15",
+        "       896 f_code[1][1] = 15",
+        "       900 intra_dc_precision = 0",
+        "       902 picture_structure = 3",
+    ]);
+
+    // headers only, nothing of the slices is indented below them
+    let trace: Vec<&str> = output.lines().filter(|line| line.starts_with("  ")).collect();
+    assert_eq!(trace.len(), 72);
+    assert!(trace.iter().all(|line| line.as_bytes()[11] != b' '));
+    assert!(!output.contains("slice"));
+}
+
+#[test]
+fn trace_off_by_default() {
+    let output = run(&[&mpeg2_stream("trace_off.m2v")]);
+    assert!(!output.contains("sequence_header"));
+    assert!(!output.contains("f_code"));
+}
+
+// Checksums alone on stdout, to diff like ffmpeg's framemd5; the rest goes to
+// stderr
+#[test]
+fn framemd5_keeps_stdout_clean() {
+    let path = mpeg2_stream("framemd5.m2v");
+    let output = Command::new(env!("CARGO_BIN_EXE_ez-mpeg1")).args(["--framemd5", "--trace=headers", &path]).output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", stdout);
+    for (index, line) in lines.iter().enumerate() {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        assert_eq!(fields[..2], [index.to_string().as_str(), "1536"]);
+        assert_eq!(fields[2].len(), 32);
+    }
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("sequence_header"));
+}
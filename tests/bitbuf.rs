@@ -0,0 +1,147 @@
+use ez_mpeg1::bitbuf::{BitBuffer, BitReader, RingBitBuffer};
+
+// `count` bits of `data` from bit `pos` on, None past the end
+fn model_peek(data: &[u8], pos: usize, count: usize) -> Option<u32> {
+    if pos + count > data.len() * 8 {
+        return None;
+    }
+    Some((pos..pos + count).fold(0, |value, bit| (value << 1) | ((data[bit >> 3] >> (7 - (bit & 7))) & 1) as u32))
+}
+
+struct Random(u32);
+
+impl Random {
+    fn next(&mut self, range: usize) -> usize {
+        self.0 = self.0.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (self.0 >> 8) as usize % range
+    }
+}
+
+// Random pushes, peeks, reads, skips, aligns and backs against a plain model
+// of everything pushed. back() only goes over bytes no push could overwrite.
+fn ring_against_model(capacity: usize, limit: usize, seed: u32) {
+    let mut ring = RingBitBuffer::with_capacity(capacity, limit);
+    let mut random = Random(seed);
+    let mut data: Vec<u8> = Vec::new();
+    let mut pos = 0;
+    let mut floor = 0;
+    for step in 0..20_000 {
+        match random.next(8) {
+            0 => {
+                let bytes: Vec<u8> = (0..random.next(capacity)).map(|_| random.next(256) as u8).collect();
+                let taken = ring.push(&bytes).unwrap_or(0);
+                data.extend_from_slice(&bytes[..taken]);
+                floor = pos & !7;
+            }
+            1 | 2 => {
+                let count = random.next(33);
+                assert_eq!(ring.peek_checked(count), model_peek(&data, pos, count), "peek {} at {}, step {}", count, pos, step);
+            }
+            3 | 4 => {
+                let count = random.next(33);
+                let expected = model_peek(&data, pos, count);
+                assert_eq!(ring.read_checked(count), expected, "read {} at {}, step {}", count, pos, step);
+                if expected.is_some() {
+                    pos += count;
+                }
+            }
+            5 => {
+                ring.align();
+                pos = (pos + 7) & !7;
+            }
+            6 => {
+                let count = random.next(pos - floor + 1);
+                ring.back(count);
+                pos -= count;
+            }
+            _ => {
+                let count = random.next(64);
+                if ring.skip(count) == count && count > 0 {
+                    pos += count;
+                }
+            }
+        }
+        assert_eq!(ring.pos(), pos, "step {}", step);
+        assert_eq!(ring.bits_left(), data.len() * 8 - pos, "step {}", step);
+    }
+}
+
+#[test]
+fn ring_reads_across_wraps() {
+    ring_against_model(16, 16, 1);
+    ring_against_model(9, 9, 2);
+}
+
+#[test]
+fn ring_reads_across_growth() {
+    ring_against_model(4, 1024, 3);
+}
+
+#[test]
+fn push_replaces_cached_bytes() {
+    let mut ring = RingBitBuffer::with_capacity(8, 8);
+    assert_eq!(ring.push(&[1, 2, 3, 4, 5, 6, 7]), Some(7));
+    // caches all seven bytes from byte 0 of the ring on
+    assert_eq!(ring.peek(32), 0x0102_0304);
+    ring.skip(48);
+    // the ring wraps, bytes 0..5 hold new data and skip() passes the cache by
+    assert_eq!(ring.push(&[8, 9, 10, 11, 12, 13]), Some(6));
+    ring.skip(24);
+    assert_eq!(ring.peek(16), 0x0A0B);
+    assert_eq!(ring.read(32), 0x0A0B_0C0D);
+    assert_eq!(ring.peek_checked(1), None);
+}
+
+#[test]
+fn back_before_the_cached_bytes() {
+    let mut ring = RingBitBuffer::with_capacity(16, 16);
+    ring.push(&[0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0, 0x11, 0x22]).unwrap();
+    ring.skip(36);
+    assert_eq!(ring.peek(16), 0xABCD);
+    ring.back(28);
+    assert_eq!(ring.read(12), 0x345);
+    ring.back(4);
+    assert_eq!(ring.read(32), 0x5678_9ABC);
+    ring.align();
+    assert_eq!(ring.read(24), 0xDEF011);
+}
+
+#[test]
+fn grow_keeps_unread_bits() {
+    let mut ring = RingBitBuffer::with_capacity(4, 64);
+    ring.push(&[0xA5, 0x5A, 0xFF]).unwrap();
+    assert_eq!(ring.peek(16), 0xA55A);
+    assert_eq!(ring.read(12), 0xA55);
+    // room for more than the ring holds moves the unread bytes to the start of a larger one
+    assert!(ring.reserve(6));
+    assert_eq!(ring.pos(), 12);
+    assert_eq!(ring.peek(12), 0xAFF);
+    assert_eq!(ring.push(&[1, 2, 3, 4, 5, 6]), Some(6));
+    assert_eq!(ring.read(12), 0xAFF);
+    assert_eq!(ring.read(32), 0x0102_0304);
+}
+
+#[test]
+fn peeks_near_the_end() {
+    let mut ring = RingBitBuffer::with_capacity(16, 16);
+    ring.push(&[0xC3, 0x81]).unwrap();
+    ring.skip(3);
+    assert_eq!(ring.peek_checked(13), Some(0x0381));
+    assert_eq!(ring.peek_checked(14), None);
+    assert_eq!(ring.peek(14), 0);
+    // a failed read consumes nothing
+    assert_eq!(ring.read_checked(20), None);
+    assert_eq!(ring.read(5), 0x03);
+    assert_eq!(ring.pos(), 8);
+
+    // the tail follows the slice as if it were part of it
+    let mut reader = BitBuffer::with_tail(&[0xAB, 0xCD, 0xEF], &[0x00, 0x00, 0x01, 0xB7]);
+    reader.skip(20);
+    assert_eq!(reader.peek_checked(32), Some(0xF000_001B));
+    assert_eq!(reader.read(32), 0xF000_001B);
+    assert_eq!(reader.peek_checked(4), Some(0x7));
+    assert_eq!(reader.peek_checked(5), None);
+    reader.back(12);
+    assert_eq!(reader.read(16), 0x01B7);
+    assert!(!reader.has(1));
+}
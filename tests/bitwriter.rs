@@ -0,0 +1,191 @@
+mod common;
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use common::{Picture, Sequence, StreamWriter, FLAT, PICTURE_I, PICTURE_P};
+use ez_mpeg1::bitbuf::{BitBuffer, BitReader, BitWriter};
+use ez_mpeg1::video::{Mpeg1Video, TraceLevel};
+use ez_mpeg1::vlc;
+
+// fields of every width at every bit offset, from a fixed pseudo random sequence
+fn fields() -> Vec<(u32, usize)> {
+    let mut seed: u32 = 12345;
+    (0..2000).map(|i| {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        let count = i % 32 + 1;
+        let value = if count == 32 { seed } else { seed & ((1 << count) - 1) };
+        (value, count)
+    }).collect()
+}
+
+#[test]
+fn fields_round_trip() {
+    let mut writer = BitWriter::new();
+    let mut bits = 0;
+    for &(value, count) in fields().iter() {
+        writer.write(value, count);
+        bits += count;
+        assert_eq!(writer.pos(), bits);
+    }
+    let data = writer.finish();
+    assert_eq!(data.len(), bits.div_ceil(8));
+
+    let mut reader = BitBuffer::new(&data);
+    for &(value, count) in fields().iter() {
+        assert_eq!(reader.read_checked(count), Some(value), "{} bits at {}", count, reader.pos());
+    }
+    assert!(reader.bits_left() < 8);
+}
+
+#[test]
+fn write_masks_wide_values() {
+    let mut writer = BitWriter::new();
+    writer.write(0xFFFF_FFFF, 3);
+    writer.write(0, 5);
+    assert_eq!(writer.finish(), [0xE0]);
+}
+
+type ReadVlc<T> = fn(&mut BitBuffer, &[(i16, T)]) -> Option<T>;
+
+fn vlc_round_trip<T: Copy + Ord + std::fmt::Debug>(name: &str, table: &[(i16, T)], read: ReadVlc<T>) {
+    let codes = vlc::encode_table(table);
+    assert!(!codes.is_empty(), "{} has no codes", name);
+
+    let mut writer = BitWriter::new();
+    for &(value, _, _) in codes.iter() {
+        assert!(writer.write_vlc(&codes, value));
+        // a one bit field between the codes keeps them unaligned
+        writer.write(1, 1);
+    }
+    let data = writer.finish();
+
+    let mut reader = BitBuffer::new(&data);
+    for &(value, _, length) in codes.iter() {
+        let start = reader.pos();
+        assert_eq!(read(&mut reader, table), Some(value), "{}", name);
+        assert_eq!(reader.pos() - start, length, "{} code length of {:?}", name, value);
+        assert_eq!(reader.read(1), 1);
+    }
+}
+
+#[test]
+fn vlc_tables_round_trip() {
+    let read = |reader: &mut BitBuffer, table: &[(i16, i16)]| reader.read_vlc_checked(table);
+    vlc_round_trip("macroblock_address_increment", &vlc::MP1V_MACROBLOCK_ADDRESS_INCREMENT, read);
+    vlc_round_trip("macroblock_type I", &vlc::MP1V_MACROBLOCK_TYPE_INTRA, read);
+    vlc_round_trip("macroblock_type P", &vlc::MP1V_MACROBLOCK_TYPE_PREDICTIVE, read);
+    vlc_round_trip("macroblock_type B", &vlc::MP1V_MACROBLOCK_TYPE_B, read);
+    vlc_round_trip("macroblock_type D", &vlc::MP1V_MACROBLOCK_TYPE_D, read);
+    vlc_round_trip("coded_block_pattern", &vlc::MP1V_CODE_BLOCK_PATTERN, read);
+    vlc_round_trip("motion_code", &vlc::MP1V_VIDEO_MOTION, read);
+    vlc_round_trip("dct_dc_size_luminance", &vlc::MP1V_DCT_SIZE_LUMINANCE, read);
+    vlc_round_trip("dct_dc_size_chrominance", &vlc::MP1V_DCT_SIZE_CHROMINANCE, read);
+    vlc_round_trip("dct_dc_size_luminance MPEG-2", &vlc::MP2V_DCT_SIZE_LUMINANCE, read);
+    vlc_round_trip("dct_dc_size_chrominance MPEG-2", &vlc::MP2V_DCT_SIZE_CHROMINANCE, read);
+
+    let read = |reader: &mut BitBuffer, table: &[(i16, u16)]| reader.read_vlc_u16_checked(table);
+    vlc_round_trip("dct_coefficient B.14", &vlc::MP1V_DCT_COEFF, read);
+    vlc_round_trip("dct_coefficient B.15", &vlc::MP2V_DCT_COEFF_INTRA, read);
+}
+
+#[test]
+fn encode_table_codes() {
+    let codes = vlc::encode_table(&vlc::MP1V_MACROBLOCK_ADDRESS_INCREMENT);
+    // increment 1 is '1', macroblock_escape '0000 0001 000'
+    assert_eq!(codes.iter().find(|code| code.0 == 1), Some(&(1, 0b1, 1)));
+    assert_eq!(codes.iter().find(|code| code.0 == 35), Some(&(35, 0x008, 11)));
+
+    let mut writer = BitWriter::new();
+    assert!(!writer.write_vlc(&codes, 99));
+    assert_eq!(writer.pos(), 0);
+}
+
+#[test]
+fn start_codes_and_stuffing() {
+    let mut writer = BitWriter::new();
+    writer.write(0b101, 3);
+    writer.write_start_code(0xB3);
+    assert!(writer.is_aligned());
+    writer.write(0x5, 4);
+    writer.stuff(0x00, 2);
+    writer.write_start_code(0x00);
+    writer.write(0x3, 2);
+    let data = writer.finish();
+    assert_eq!(data, [0xA0, 0x00, 0x00, 0x01, 0xB3, 0x50, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0xC0]);
+
+    let mut reader = BitBuffer::new(&data);
+    assert!(reader.find_start_code(0x000001B3));
+    assert_eq!(reader.read(4), 0x5);
+    assert!(reader.find_start_code(0x00000100));
+    assert_eq!(reader.read(2), 0x3);
+}
+
+// trace output the test can read back
+#[derive(Clone, Default)]
+struct SharedOut(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedOut {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// A 32x16 MPEG-1 stream, an I-picture of flat macroblocks and a P-picture with
+// one coded block in each macroblock, written with the B.14 tables for the
+// first and the later coefficients
+fn dct_coeff_stream(blocks: &[&[(u8, i32)]]) -> Vec<u8> {
+    let mut stream = StreamWriter::new();
+    stream.sequence_header(&Sequence::mpeg1(32, 16));
+    stream.picture(&Picture::new(PICTURE_I, 0));
+    stream.slice(0);
+    stream.intra(0, &FLAT);
+    stream.intra(1, &FLAT);
+    stream.picture(&Picture::new(PICTURE_P, 1));
+    stream.slice(0);
+    for (address, &coeffs) in blocks.iter().enumerate() {
+        stream.forward(address as i32, &[coeffs]);
+    }
+    stream.finish()
+}
+
+#[test]
+fn dct_coefficients_as_the_decoder_reads_them() {
+    // run 0 level 1 first, again later, and behind other pairs
+    let blocks: [&[(u8, i32)]; 2] = [
+        &[(0, 1), (0, 1), (0, -1), (2, 1), (0, 1)],
+        &[(0, -1), (1, 2), (0, 1), (0, -1)],
+    ];
+    let data = dct_coeff_stream(&blocks);
+
+    let out = SharedOut::default();
+    let mut vcodec = Mpeg1Video::from_slice(&data);
+    vcodec.set_trace(TraceLevel::Blocks, Box::new(out.clone()));
+    let mut frames = 0;
+    common::drain(&mut vcodec, &mut |_| frames += 1);
+    common::flush(&mut vcodec, &mut |_| frames += 1);
+    assert_eq!(frames, 2);
+
+    let trace = String::from_utf8(out.0.borrow().clone()).unwrap();
+    let events: Vec<&str> = trace.lines()
+        .map(|line| line.trim_start().split_once(' ').unwrap().1.trim())
+        .filter(|event| event.starts_with("run =") || *event == "end_of_block")
+        .collect();
+    let mut expected = vec!["end_of_block".to_string(); 12];
+    for coeffs in blocks.iter() {
+        expected.extend(coeffs.iter().map(|(run, level)| format!("run = {}, level = {}", run, level)));
+        expected.push("end_of_block".to_string());
+    }
+    assert_eq!(events, expected);
+
+    // the short code alone would have ended the first block after one pair
+    let short = vlc::encode_table(&vlc::MP1V_DCT_COEFF);
+    assert!(short.contains(&(0x0001, 0b1, 1)));
+    assert!(vlc::dct_coeff_encode_table(false).contains(&(0x0001, 0b11, 2)));
+}
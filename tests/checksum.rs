@@ -0,0 +1,72 @@
+mod common;
+
+use common::{Picture, Sequence, PICTURE_I};
+use ez_mpeg1::checksum::{self, Md5};
+use ez_mpeg1::video::{DecodeResult, Mpeg1Video, Plane};
+
+#[test]
+fn crc32_check_values() {
+    assert_eq!(checksum::crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(checksum::crc32(b""), 0);
+    assert_eq!(checksum::crc32_update(checksum::crc32(b"1234"), b"56789"), 0xCBF4_3926);
+}
+
+#[test]
+fn crc32_mpeg_check_values() {
+    assert_eq!(checksum::crc32_mpeg(b"123456789"), 0x0376_E6E7);
+    // a section followed by its own CRC_32 sums to 0
+    let mut section = b"123456789".to_vec();
+    section.extend_from_slice(&0x0376_E6E7u32.to_be_bytes());
+    assert_eq!(checksum::crc32_mpeg(&section), 0);
+}
+
+#[test]
+fn adler32_check_values() {
+    assert_eq!(checksum::adler32(b"Wikipedia"), 0x11E6_0398);
+    assert_eq!(checksum::adler32(b""), 1);
+    // past the 5552 bytes between reductions
+    let data = vec![0xFFu8; 100_000];
+    let (mut a, mut b) = (1u64, 0u64);
+    for &byte in data.iter() {
+        a = (a + byte as u64) % 65521;
+        b = (b + a) % 65521;
+    }
+    assert_eq!(checksum::adler32(&data), ((b << 16) | a) as u32);
+}
+
+#[test]
+fn md5_check_values() {
+    assert_eq!(checksum::hex(&checksum::md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+    assert_eq!(checksum::hex(&checksum::md5(b"The quick brown fox jumps over the lazy dog")),
+               "9e107d9d372bb6826bd81d3542a419d6");
+    // RFC 1321 A.5, 80 bytes span two blocks
+    let digits = b"12345678901234567890123456789012345678901234567890123456789012345678901234567890";
+    assert_eq!(checksum::hex(&checksum::md5(digits)), "57edf4a22be3c955ac49da2e2107b67a");
+
+    // pieces that end inside and on block boundaries give the same digest
+    let mut md5 = Md5::new();
+    for piece in [&digits[..1], &digits[1..63], &digits[63..64], &digits[64..]].iter() {
+        md5.update(piece);
+    }
+    assert_eq!(checksum::hex(&md5.finish()), "57edf4a22be3c955ac49da2e2107b67a");
+}
+
+#[test]
+fn frame_checksums_cover_the_visible_planes() {
+    // the second picture puts out the first
+    let pictures = [Picture::new(PICTURE_I, 0), Picture::new(PICTURE_I, 1)];
+    let data = common::textured_stream(&Sequence::mpeg1(96, 64), &pictures);
+    let mut vcodec = Mpeg1Video::from_slice(&data);
+    assert!(matches!(vcodec.decode(), DecodeResult::GotOneFrame));
+    let frame = vcodec.frame().unwrap();
+
+    let mut samples = Vec::new();
+    for &(plane, width, height) in [(Plane::Y, 96, 64), (Plane::Cb, 48, 32), (Plane::Cr, 48, 32)].iter() {
+        let rows: Vec<&[u8]> = frame.visible(plane).collect();
+        assert_eq!(rows.len(), height, "{:?}", plane);
+        assert!(rows.iter().all(|row| row.len() == width), "{:?}", plane);
+        samples.extend(rows.concat());
+    }
+    assert_eq!(checksum::frame_crc32(&frame), checksum::crc32(&samples));
+    assert_eq!(checksum::frame_md5(&frame), checksum::md5(&samples));
+}
@@ -0,0 +1,341 @@
+// Stream building and decoding shared by the integration tests, each test
+// crate uses part of it
+#![allow(dead_code)]
+
+use ez_mpeg1::bitbuf::{BitReader, BitWriter};
+use ez_mpeg1::video::{DecodeResult, Mpeg1Video, VideoFrame};
+use ez_mpeg1::vlc;
+
+pub const PICTURE_I: u32 = 1;
+pub const PICTURE_P: u32 = 2;
+pub const PICTURE_B: u32 = 3;
+
+pub const TOP_FIELD: u32 = 1;
+pub const BOTTOM_FIELD: u32 = 2;
+pub const FRAME: u32 = 3;
+
+// sequence header fields in header order, MPEG-2 adds a sequence_extension
+#[derive(Clone)]
+pub struct Sequence {
+    pub width:           u32,
+    pub height:          u32,
+    pub aspect_ratio:    u32,
+    pub frame_rate_code: u32,
+    pub bit_rate:        u32,
+    pub vbv_buffer_size: u32,
+    pub constrained:     bool,
+    pub mpeg2:           bool,
+    pub progressive:     bool,
+}
+
+impl Sequence {
+    // square samples at 25 Hz, within the constrained parameters
+    pub fn mpeg1(width: u32, height: u32) -> Self {
+        Sequence {
+            width,
+            height,
+            aspect_ratio:    1,
+            frame_rate_code: 3,
+            bit_rate:        1000,
+            vbv_buffer_size: 20,
+            constrained:     true,
+            mpeg2:           false,
+            progressive:     true,
+        }
+    }
+
+    pub fn mpeg2(width: u32, height: u32, progressive: bool) -> Self {
+        Sequence { constrained: false, mpeg2: true, progressive, ..Self::mpeg1(width, height) }
+    }
+}
+
+pub struct Picture {
+    pub coding_type:        u32,
+    pub temporal_reference: u32,
+    // forward_f_code of MPEG-1, f_code[0][0] and f_code[0][1] of MPEG-2
+    pub f_code:             u32,
+    // picture_coding_extension of MPEG-2
+    pub structure:          u32,
+    pub top_field_first:    bool,
+}
+
+impl Picture {
+    pub fn new(coding_type: u32, temporal_reference: u32) -> Self {
+        Picture { coding_type, temporal_reference, f_code: 1, structure: FRAME, top_field_first: true }
+    }
+}
+
+// dct_dc_differential and the AC run and level pairs of an intra block
+pub type IntraBlock<'a> = (i32, &'a [(u8, i32)]);
+
+pub const FLAT: [IntraBlock<'static>; 6] = [(0, &[]); 6];
+
+// Writes pictures of intra macroblocks and zero vector predictions, slice by slice
+pub struct StreamWriter {
+    writer:       BitWriter,
+    mpeg2:        bool,
+    progressive:  bool,
+    mb_width:     i32,
+    picture_type: u32,
+    structure:    u32,
+    // of the last macroblock in the slice
+    address:      i32,
+}
+
+impl StreamWriter {
+    pub fn new() -> Self {
+        StreamWriter {
+            writer:       BitWriter::new(),
+            mpeg2:        false,
+            progressive:  true,
+            mb_width:     0,
+            picture_type: 0,
+            structure:    FRAME,
+            address:      -1,
+        }
+    }
+
+    // for what the helpers don't write
+    pub fn writer(&mut self) -> &mut BitWriter {
+        &mut self.writer
+    }
+
+    pub fn sequence_header(&mut self, sequence: &Sequence) {
+        self.mpeg2 = sequence.mpeg2;
+        self.progressive = sequence.progressive;
+        self.mb_width = ((sequence.width + 15) >> 4) as i32;
+
+        let writer = &mut self.writer;
+        writer.write_start_code(0xB3);
+        writer.write(sequence.width, 12);
+        writer.write(sequence.height, 12);
+        writer.write(sequence.aspect_ratio, 4);
+        writer.write(sequence.frame_rate_code, 4);
+        writer.write(sequence.bit_rate, 18);
+        writer.write(1, 1);
+        writer.write(sequence.vbv_buffer_size, 10);
+        writer.write(sequence.constrained as u32, 1);
+        // no quantiser matrices
+        writer.write(0, 2);
+
+        if sequence.mpeg2 {
+            writer.write_start_code(0xB5);
+            writer.write(1, 4);
+            // main profile at main level, 4:2:0
+            writer.write(0x48, 8);
+            writer.write(sequence.progressive as u32, 1);
+            writer.write(1, 2);
+            // no size, bit rate or buffer size extensions, a marker bit between
+            writer.write(0, 4);
+            writer.write(0, 12);
+            writer.write(1, 1);
+            writer.write(0, 8);
+            // low_delay 0, frame_rate_extension_n and _d 0
+            writer.write(0, 8);
+        }
+    }
+
+    pub fn picture(&mut self, picture: &Picture) {
+        self.picture_type = picture.coding_type;
+        self.structure = picture.structure;
+
+        let writer = &mut self.writer;
+        writer.write_start_code(0x00);
+        writer.write(picture.temporal_reference, 10);
+        writer.write(picture.coding_type, 3);
+        writer.write(0xFFFF, 16);
+        // MPEG-2 has the f_codes in the extension and 7 here
+        let f_code = if self.mpeg2 { 7 } else { picture.f_code };
+        if picture.coding_type == PICTURE_P || picture.coding_type == PICTURE_B {
+            writer.write(0, 1);
+            writer.write(f_code, 3);
+        }
+        if picture.coding_type == PICTURE_B {
+            writer.write(0, 1);
+            writer.write(f_code, 3);
+        }
+        writer.write(0, 1);
+
+        if self.mpeg2 {
+            let forward = if picture.coding_type == PICTURE_I { 15 } else { picture.f_code };
+            let backward = if picture.coding_type == PICTURE_B { picture.f_code } else { 15 };
+            writer.write_start_code(0xB5);
+            writer.write(8, 4);
+            for &code in [forward, forward, backward, backward].iter() {
+                writer.write(code, 4);
+            }
+            // intra_dc_precision 8 bits
+            writer.write(0, 2);
+            writer.write(picture.structure, 2);
+            writer.write(picture.top_field_first as u32, 1);
+            // frame_pred_frame_dct in frame pictures, no concealment vectors,
+            // linear q_scale_type, table B.14 for intra blocks, zig-zag scan
+            writer.write((picture.structure == FRAME) as u32, 1);
+            writer.write(0, 4);
+            // repeat_first_field 0, chroma_420_type and progressive_frame, no
+            // composite display information
+            writer.write(0, 1);
+            writer.write(self.progressive as u32, 1);
+            writer.write(self.progressive as u32, 1);
+            writer.write(0, 1);
+        }
+    }
+
+    // slice of the macroblock row `row` of the picture, quantiser_scale 8
+    pub fn slice(&mut self, row: u32) {
+        self.writer.write_start_code(row as u8 + 1);
+        self.writer.write(8, 5);
+        self.writer.write(0, 1);
+        self.address = row as i32 * self.mb_width - 1;
+    }
+
+    // macroblock_address_increment up to `address`, the ones between are skipped
+    fn increment(&mut self, address: i32) {
+        let mut increment = address - self.address;
+        assert!(increment > 0, "macroblock {} is not after {}", address, self.address);
+        let codes = vlc::encode_table(&vlc::MP1V_MACROBLOCK_ADDRESS_INCREMENT);
+        while increment > 33 {
+            // macroblock_escape
+            assert!(self.writer.write_vlc(&codes, 35));
+            increment -= 33;
+        }
+        assert!(self.writer.write_vlc(&codes, increment as i16));
+        self.address = address;
+    }
+
+    pub fn intra(&mut self, address: i32, blocks: &[IntraBlock; 6]) {
+        self.increment(address);
+        if self.picture_type == PICTURE_I {
+            self.writer.write(0b1, 1);
+        } else {
+            self.writer.write(0b00011, 5);
+        }
+
+        let luma_sizes = vlc::encode_table(&vlc::MP1V_DCT_SIZE_LUMINANCE);
+        let chroma_sizes = vlc::encode_table(&vlc::MP1V_DCT_SIZE_CHROMINANCE);
+        let next = vlc::dct_coeff_encode_table(false);
+        for (index, &(dc, ac)) in blocks.iter().enumerate() {
+            let size = 32 - dc.unsigned_abs().leading_zeros();
+            let sizes = if index < 4 { &luma_sizes } else { &chroma_sizes };
+            assert!(self.writer.write_vlc(sizes, size as i16), "no dct_dc_size {}", size);
+            // negative differentials are one less than their size's range
+            let differential = if dc < 0 { dc + (1 << size) - 1 } else { dc };
+            self.writer.write(differential as u32, size as usize);
+            self.coefficients(&next, &next, ac);
+        }
+    }
+
+    // An intra macroblock with a different DC and two AC coefficients in every
+    // block, picked by `seed`
+    pub fn textured(&mut self, address: i32, seed: usize) {
+        let ac: Vec<[(u8, i32); 2]> = (0..6).map(|block| {
+            let sign = if (seed + block) & 1 == 0 { 1 } else { -1 };
+            [(0, sign * (1 + (seed + block) as i32 % 3)), (0, sign * 2)]
+        }).collect();
+        let blocks = [0, 1, 2, 3, 4, 5].map(|block: usize| ([2, 3, -3, -2][(seed + block) % 4], &ac[block][..]));
+        self.intra(address, &blocks);
+    }
+
+    // A zero vector prediction from the forward reference, of the same parity
+    // in field pictures. `coded` has the run and level pairs of the first blocks,
+    // none makes it a macroblock without residual.
+    pub fn forward(&mut self, address: i32, coded: &[&[(u8, i32)]]) {
+        assert_eq!(self.picture_type, PICTURE_P);
+        self.increment(address);
+        // motion compensated, coded or not coded
+        if coded.is_empty() {
+            self.writer.write(0b001, 3);
+        } else {
+            self.writer.write(0b1, 1);
+        }
+        if self.structure != FRAME {
+            // field_motion_type field, motion_vertical_field_select
+            self.writer.write(0b01, 2);
+            self.writer.write((self.structure == BOTTOM_FIELD) as u32, 1);
+        }
+        // motion_code 0 for both components
+        self.writer.write(0b11, 2);
+        if coded.is_empty() {
+            return;
+        }
+
+        let pattern = ((1 << coded.len()) - 1) << (6 - coded.len());
+        assert!(self.writer.write_vlc(&vlc::encode_table(&vlc::MP1V_CODE_BLOCK_PATTERN), pattern));
+        let first = vlc::dct_coeff_encode_table(true);
+        let next = vlc::dct_coeff_encode_table(false);
+        for coeffs in coded.iter() {
+            self.coefficients(&first, &next, coeffs);
+        }
+    }
+
+    // run and level pairs with their sign bit, then end_of_block
+    fn coefficients(&mut self, first: &[(u16, u32, usize)], next: &[(u16, u32, usize)], coeffs: &[(u8, i32)]) {
+        for (index, &(run, level)) in coeffs.iter().enumerate() {
+            let codes = if index == 0 { first } else { next };
+            let value = ((run as u16) << 8) | level.unsigned_abs() as u16;
+            assert!(self.writer.write_vlc(codes, value), "no code for run {} level {}", run, level);
+            self.writer.write((level < 0) as u32, 1);
+        }
+        self.writer.write(vlc::MP1V_END_OF_BLOCK.0, vlc::MP1V_END_OF_BLOCK.1);
+    }
+
+    // with a sequence_end_code
+    pub fn finish(mut self) -> Vec<u8> {
+        self.writer.write_start_code(0xB7);
+        self.writer.finish()
+    }
+}
+
+// `sequence` and `pictures` in coding order, every macroblock of them intra
+// and textured by its own seed
+pub fn textured_stream(sequence: &Sequence, pictures: &[Picture]) -> Vec<u8> {
+    let mut stream = StreamWriter::new();
+    stream.sequence_header(sequence);
+    let mb_width = (sequence.width + 15) >> 4;
+    let mut seed = 0;
+    for picture in pictures.iter() {
+        stream.picture(picture);
+        let rows = if picture.structure == FRAME { (sequence.height + 15) >> 4 } else { (sequence.height + 31) >> 5 };
+        for row in 0..rows {
+            stream.slice(row);
+            for col in 0..mb_width {
+                stream.textured((row * mb_width + col) as i32, seed);
+                seed += 1;
+            }
+        }
+    }
+    stream.finish()
+}
+
+// Decodes every picture the decoder holds, `got` sees each frame
+pub fn drain<B: BitReader>(vcodec: &mut Mpeg1Video<B>, got: &mut impl FnMut(&Mpeg1Video<B>)) {
+    loop {
+        match vcodec.decode() {
+            DecodeResult::GotOneFrame => got(vcodec),
+            DecodeResult::NeedMoreData => break,
+            DecodeResult::InternalError => panic!("decoding failed"),
+        }
+    }
+}
+
+// the reference picture held back for reordering, at the end of the stream
+pub fn flush<B: BitReader>(vcodec: &mut Mpeg1Video<B>, got: &mut impl FnMut(&Mpeg1Video<B>)) {
+    if let DecodeResult::GotOneFrame = vcodec.flush() {
+        got(vcodec);
+    }
+}
+
+// samples of every frame of an elementary stream in display order, taken as
+// each frame comes out
+pub fn decoded(data: &[u8]) -> Vec<Vec<u8>> {
+    let mut vcodec = Mpeg1Video::from_slice(data);
+    let mut frames = Vec::new();
+    drain(&mut vcodec, &mut |vcodec| frames.push(samples(&vcodec.frame().unwrap())));
+    flush(&mut vcodec, &mut |vcodec| frames.push(samples(&vcodec.frame().unwrap())));
+    frames
+}
+
+// Y, Cb and Cr with their macroblock padding
+pub fn samples(frame: &VideoFrame) -> Vec<u8> {
+    [frame.plane(&frame.y), frame.plane(&frame.cb), frame.plane(&frame.cr)].concat()
+}
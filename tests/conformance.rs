@@ -0,0 +1,488 @@
+mod common;
+
+use ez_mpeg1::bitbuf::BitReader;
+use ez_mpeg1::checksum;
+use ez_mpeg1::idct::IdctMode;
+use ez_mpeg1::pkt;
+use ez_mpeg1::video::{DecodeResult, Mpeg1Video};
+
+// The samples in tests/data come from the randomised test encoder in tests/gen,
+// samples.sh has the parameters and seeds of each. With the reference IDCT the
+// decoder must reproduce the encoder's reconstruction, whose frame MD5s are
+// listed here in display order.
+
+// 96x64 MPEG-1, I/P/B pictures with skipped macroblocks, escape coded
+// coefficients, downloaded quantiser matrices, half-pel vectors and f_code 2 and 3.
+// The .mpg and .ts files carry the same video in 700 byte PES packets.
+const MPEG1_IPB: [&str; 8] = [
+    "983570617e4f4e9d811cea5f55b3479f", "5c096a0715c8a2cc8865ea02796fb331",
+    "fa37f333cbac2dc5e019105a160bb4d0", "c844d837de4a3a60fc570d41c60fdd8b",
+    "12c90baca483ea5f68a2424dcb47f049", "823f22c7d71ab4bec837f2ffac756a40",
+    "43eb91e6bb873335ed97f4f9387d4566", "50f038dc65918bff29a061f2e1b7ae72",
+];
+
+// the same stream with the default fast IDCT, a regression check only
+const MPEG1_IPB_FAST: [&str; 8] = [
+    "626bf52d65d5b0a6c63d75264c4aaabe", "26e8b4572d5d272c28abf5c0db0da7aa",
+    "904c6a00dae58f9a01977e9ab0d00b81", "a1aa1e49e9d9ba3ab6bd6fd5a67216b8",
+    "8465c16729b006c0a2f344b4fd49a6c5", "c5344e5f3e599eeaf25ec49f0a03115b",
+    "9b43fdfc7cdc9f638a963688445427ec", "d977845047762446a688a28bcfb04d79",
+];
+
+// 48x32 MPEG-1 D-pictures
+const MPEG1_DPICTURES: [&str; 3] = [
+    "e03b8b060c7eda1582c02f8f42910d41", "fd011ae6fdb834b4cdc895470edd82e8",
+    "d44331bc8280f692867b6b95d34f0e32",
+];
+
+// 64x64 interlaced MPEG-2 with frame and field pictures
+const MPEG2_FIELDS: [&str; 5] = [
+    "ed98d9da28af4a87d5f0da22806b8242", "6bc63fd34036477cc97175748325654b",
+    "f435db34083e44e963efe0b4b3e95ece", "57404946c98807b3b57f3e88629d8b5d",
+    "46b26031bce1b78d854ce3f3a6421d26",
+];
+
+// 64x64 interlaced MPEG-2 with concealment motion vectors, repeat_first_field on
+// progressive frames, q_scale_type, intra_vlc_format, alternate_scan and
+// intra_dc_precision up to 11 bits
+const MPEG2_TOOLS: [&str; 7] = [
+    "4c51525f580a3cf81ad63e15679ce974", "b43d5d092b30b120949c736fa97e3c91",
+    "8135036206d0674ed86519057214b7a4", "385a45c06cdd02d86ef6698fd4edfd40",
+    "98aa122965219a47a19e9783603febf8", "502b2440fdd251254bcaa325b4e8df42",
+    "001f32127d3a01cc23e03c26650e8db9",
+];
+
+// 64x64 interlaced MPEG-2 I/P with dual prime macroblocks in frame pictures and
+// in top and bottom field pictures
+const MPEG2_DUAL_PRIME: [&str; 6] = [
+    "79c59fbbe0c5f6ffbdf03c7ff8a9257d", "be447e05d615a18b4c57c84a482e0690",
+    "51f668508e8fd88861b837297714231a", "43711ba0d3bc62b5ae21199bb43e5caa",
+    "1fcf63b38e0e9e3a75bd74eeb273cdb7", "f55d7a459b0d71f8424f33e18d60aef5",
+];
+
+fn sample(name: &str) -> Vec<u8> {
+    let path = format!("{}/tests/data/{}", env!("CARGO_MANIFEST_DIR"), name);
+    std::fs::read(&path).unwrap_or_else(|e| panic!("can't read {}: {}", path, e))
+}
+
+fn frame_md5<B: BitReader>(vcodec: &Mpeg1Video<B>) -> String {
+    checksum::hex(&checksum::frame_md5(&vcodec.frame().unwrap()))
+}
+
+fn collect<B: BitReader>(vcodec: &mut Mpeg1Video<B>, sums: &mut Vec<String>) {
+    common::drain(vcodec, &mut |vcodec| sums.push(frame_md5(vcodec)));
+}
+
+fn finish<B: BitReader>(vcodec: &mut Mpeg1Video<B>, sums: &mut Vec<String>) {
+    common::flush(vcodec, &mut |vcodec| sums.push(frame_md5(vcodec)));
+}
+
+// the whole elementary stream in place
+fn decode_slice(data: &[u8], mode: IdctMode) -> Vec<String> {
+    let mut vcodec = Mpeg1Video::from_slice(data);
+    vcodec.set_idct(mode);
+    let mut sums = Vec::new();
+    collect(&mut vcodec, &mut sums);
+    finish(&mut vcodec, &mut sums);
+    sums
+}
+
+// the elementary stream pushed into the ring buffer a few bytes at a time
+fn decode_pushed(data: &[u8], chunk: usize) -> Vec<String> {
+    let mut vcodec = Mpeg1Video::new();
+    vcodec.set_idct(IdctMode::Reference);
+    let mut sums = Vec::new();
+    for piece in data.chunks(chunk) {
+        assert!(vcodec.push(piece).is_some(), "ring buffer full");
+        collect(&mut vcodec, &mut sums);
+    }
+    assert!(vcodec.end_of_stream().is_some(), "ring buffer full");
+    collect(&mut vcodec, &mut sums);
+    finish(&mut vcodec, &mut sums);
+    sums
+}
+
+// pushed into a ring that starts tiny and may only grow to `limit` bytes,
+// the rest of a partial push goes in after decoding
+fn decode_limited(data: &[u8], chunk: usize, limit: usize) -> Option<Vec<String>> {
+    let mut vcodec = Mpeg1Video::with_capacity(64, limit);
+    vcodec.set_idct(IdctMode::Reference);
+    let mut sums = Vec::new();
+    for mut piece in data.chunks(chunk) {
+        while !piece.is_empty() {
+            let taken = vcodec.push(piece)?;
+            piece = &piece[taken..];
+            collect(&mut vcodec, &mut sums);
+        }
+    }
+    vcodec.end_of_stream()?;
+    collect(&mut vcodec, &mut sums);
+    finish(&mut vcodec, &mut sums);
+    Some(sums)
+}
+
+// program or transport stream through the demuxer, like the command line tool
+fn decode_system(data: &[u8], is_ts: bool) -> Vec<String> {
+    demux(data, is_ts, |_| {}).0
+}
+
+// `setup` picks transport stream programs or PIDs, gives the frame MD5s, the
+// audio packets with their payload sizes and the es_offset of every picture
+fn demux(data: &[u8], is_ts: bool, setup: fn(&mut pkt::MpegPS)) -> (Vec<String>, Vec<usize>, Vec<usize>) {
+    let mut ps = pkt::MpegPS::new();
+    setup(&mut ps);
+    let push = |ps: &mut pkt::MpegPS, data: &[u8]| if is_ts { ps.push_ts(data) } else { ps.push(data) };
+    let mut vcodec = Mpeg1Video::new();
+    vcodec.set_idct(IdctMode::Reference);
+    vcodec.set_stats(true);
+    let mut sums = Vec::new();
+    let mut audio = Vec::new();
+
+    let mut index = push(&mut ps, &data[..data.len().min(1400)]);
+    loop {
+        match ps.get() {
+            Ok(pkt) => {
+                if pkt.pes_type == pkt::PacketType::PES_VIDEO {
+                    assert!(vcodec.push(ps.payload(&pkt)).is_some(), "ring buffer full");
+                    collect(&mut vcodec, &mut sums);
+                } else if pkt.pes_type == pkt::PacketType::PES_AUDIO {
+                    audio.push(ps.payload(&pkt).len());
+                }
+            },
+            Err(e) => {
+                if index == data.len() {
+                    if ps.end_of_stream() {
+                        continue;
+                    }
+                    break;
+                }
+                let wanted = match e {
+                    pkt::PacketError::OUT_LENGTH(more) => 1280 + more,
+                    pkt::PacketError::NO_START_CODE => 1280,
+                    other => panic!("demuxing failed: {:?}", other),
+                };
+                let end = data.len().min(index + wanted);
+                index += push(&mut ps, &data[index..end]);
+            }
+        }
+    }
+
+    assert!(vcodec.end_of_stream().is_some(), "ring buffer full");
+    collect(&mut vcodec, &mut sums);
+    finish(&mut vcodec, &mut sums);
+    let offsets = vcodec.take_stats().iter().map(|stats| stats.es_offset).collect();
+    (sums, audio, offsets)
+}
+
+#[test]
+fn mpeg1_ipb_elementary_stream() {
+    assert_eq!(decode_slice(&sample("mpeg1_ipb.m1v"), IdctMode::Reference), MPEG1_IPB);
+}
+
+#[test]
+fn mpeg1_ipb_fast_idct() {
+    assert_eq!(decode_slice(&sample("mpeg1_ipb.m1v"), IdctMode::Fast), MPEG1_IPB_FAST);
+}
+
+#[test]
+fn mpeg1_ipb_pushed_in_pieces() {
+    let data = sample("mpeg1_ipb.m1v");
+    for &chunk in [1, 7, 188, 4096].iter() {
+        assert_eq!(decode_pushed(&data, chunk), MPEG1_IPB, "pushed {} bytes at a time", chunk);
+    }
+}
+
+#[test]
+fn mpeg1_ipb_growing_ring() {
+    let data = sample("mpeg1_ipb.m1v");
+    assert_eq!(decode_limited(&data, 1000, 1 << 20).expect("ring buffer full"), MPEG1_IPB);
+    // a limit just above the largest pictures forces partial pushes
+    assert_eq!(decode_limited(&data, 1000, 2200).expect("ring buffer full"), MPEG1_IPB);
+    // a picture larger than the limit is reported, not a panic
+    assert!(decode_limited(&data, 1000, 1024).is_none());
+}
+
+// elementary stream byte of every picture's headers, from the stats
+fn picture_offsets<B: BitReader>(vcodec: &mut Mpeg1Video<B>, offsets: &mut Vec<usize>) {
+    let mut sums = Vec::new();
+    collect(vcodec, &mut sums);
+    offsets.extend(vcodec.take_stats().iter().map(|stats| stats.es_offset));
+}
+
+#[test]
+fn mpeg1_ipb_picture_offsets() {
+    let data = sample("mpeg1_ipb.m1v");
+    let mut vcodec = Mpeg1Video::from_slice(&data);
+    vcodec.set_stats(true);
+    let mut expected = Vec::new();
+    picture_offsets(&mut vcodec, &mut expected);
+    // the sequence header, then the picture or GOP start code after each picture
+    assert_eq!(expected[..4], [0, 1102, 1682, 2856]);
+
+    // a small ring wraps many times, the offsets still count from the first byte
+    let mut vcodec = Mpeg1Video::with_capacity(64, 2200);
+    vcodec.set_stats(true);
+    let mut offsets = Vec::new();
+    for mut piece in data.chunks(1000) {
+        while !piece.is_empty() {
+            let taken = vcodec.push(piece).expect("ring buffer full");
+            piece = &piece[taken..];
+            picture_offsets(&mut vcodec, &mut offsets);
+        }
+    }
+    vcodec.end_of_stream().expect("ring buffer full");
+    picture_offsets(&mut vcodec, &mut offsets);
+    assert_eq!(offsets, expected);
+
+    // offsets count video elementary stream bytes, not container bytes
+    assert_eq!(demux(&sample("mpeg1_ipb.mpg"), false, |_| {}).2, expected);
+    assert_eq!(demux(&sample("mpeg1_ipb.ts"), true, |_| {}).2, expected);
+}
+
+#[test]
+fn mpeg1_ipb_program_stream() {
+    let data = sample("mpeg1_ipb.mpg");
+    assert_eq!(pkt::probe(&data), pkt::StreamType::PROGRAM_STREAM);
+    assert_eq!(decode_system(&data, false), MPEG1_IPB);
+}
+
+#[test]
+fn mpeg1_ipb_transport_stream() {
+    let data = sample("mpeg1_ipb.ts");
+    assert_eq!(pkt::probe(&data), pkt::StreamType::TRANSPORT_STREAM);
+    assert_eq!(decode_system(&data, true), MPEG1_IPB);
+}
+
+// Starts in the middle of a packet, has junk between packets, a packet with
+// transport_error_indicator set, one with the reserved adaptation_field_control
+// and ends with a cut off packet
+#[test]
+fn transport_stream_resync() {
+    let clean = sample("mpeg1_ipb.ts");
+    let packets: Vec<&[u8]> = clean.chunks(188).collect();
+    let mut data = packets[0][88..].to_vec();
+    for (index, packet) in packets.iter().enumerate() {
+        data.extend_from_slice(packet);
+        if index == 5 {
+            data.extend_from_slice(&[0x47; 3]);
+            data.extend_from_slice(&[0xFF; 74]);
+        }
+        if index == 10 {
+            let mut broken = packet.to_vec();
+            broken[1] |= 0x80;
+            data.extend_from_slice(&broken);
+            broken[1] &= 0x7F;
+            broken[3] &= 0xCF;
+            data.extend_from_slice(&broken);
+        }
+    }
+    data.extend_from_slice(&packets[1][..100]);
+    assert_eq!(pkt::probe(&data), pkt::StreamType::TRANSPORT_STREAM);
+    assert_eq!(decode_system(&data, true), MPEG1_IPB);
+
+    let mut ps = pkt::MpegPS::new();
+    let mut index = 0;
+    while index < data.len() {
+        index += ps.push_ts(&data[index..]);
+    }
+    ps.end_of_stream();
+    assert_eq!(ps.dropped_packets, 2);
+    assert_eq!(ps.skipped_bytes, 100 + 77 + 100);
+}
+
+// whole input demuxed at once into its video PES packets
+fn video_pes(data: &[u8]) -> (pkt::MpegPS, Vec<pkt::PESPacketInfo>) {
+    let mut ps = pkt::MpegPS::new();
+    let mut index = 0;
+    while index < data.len() {
+        index += ps.push_ts(&data[index..]);
+    }
+    ps.end_of_stream();
+    let mut packets = Vec::new();
+    while let Ok(packet) = ps.get() {
+        if packet.pes_type == pkt::PacketType::PES_VIDEO {
+            packets.push(packet);
+        }
+    }
+    (ps, packets)
+}
+
+// damaged and discontinuity of the video PES packets
+fn video_pes_flags(data: &[u8]) -> (pkt::MpegPS, Vec<(bool, bool)>) {
+    let (ps, packets) = video_pes(data);
+    (ps, packets.iter().map(|packet| (packet.damaged, packet.discontinuity)).collect())
+}
+
+// The video PES packets of mpeg1_ipb.ts start in TS packets 2, 6 and 10
+#[test]
+fn transport_stream_continuity() {
+    let clean = sample("mpeg1_ipb.ts");
+    let packets: Vec<&[u8]> = clean.chunks(188).collect();
+    let (ps, flags) = video_pes_flags(&clean);
+    assert_eq!((ps.lost_packets, ps.duplicate_packets), (0, 0));
+    assert!(flags.iter().all(|&flag| flag == (false, false)));
+
+    // a duplicate is dropped
+    let mut data = clean.clone();
+    data.splice(188 * 8..188 * 8, packets[7].iter().cloned());
+    assert_eq!(decode_system(&data, true), MPEG1_IPB);
+    let (ps, flags) = video_pes_flags(&data);
+    assert_eq!((ps.lost_packets, ps.duplicate_packets), (0, 1));
+    assert!(flags.iter().all(|&flag| flag == (false, false)));
+
+    // a lost packet damages its PES packet only
+    let mut data = clean.clone();
+    data.drain(188 * 7..188 * 8);
+    let (ps, damaged) = video_pes_flags(&data);
+    assert_eq!((ps.lost_packets, ps.duplicate_packets), (1, 0));
+    assert_eq!(damaged.len(), flags.len());
+    assert_eq!(&damaged[..3], [(false, false), (true, false), (false, false)]);
+
+    // continuity_counter may jump at a discontinuity_indicator
+    let mut data = clean.clone();
+    data[188 * 10 + 5] |= 0x80;
+    for packet in data.chunks_mut(188).skip(10).filter(|packet| packet[1] & 0x1F == 0x01) {
+        packet[3] = (packet[3] & 0xF0) | ((packet[3] + 5) & 0x0F);
+    }
+    let (ps, flags) = video_pes_flags(&data);
+    assert_eq!((ps.lost_packets, ps.duplicate_packets), (0, 0));
+    assert_eq!(&flags[..3], [(false, false), (false, false), (false, true)]);
+    assert!(flags[3..].iter().all(|&flag| flag == (false, false)));
+}
+
+// program_clock_reference or its original, 33 bit base and 9 bit extension
+fn clock_ref(clock: u64) -> [u8; 6] {
+    let (base, extension) = (clock / 300, clock % 300);
+    [(base >> 25) as u8, (base >> 17) as u8, (base >> 9) as u8, (base >> 1) as u8,
+     ((base & 1) << 7) as u8 | 0x7E | (extension >> 8) as u8, extension as u8]
+}
+
+// mpeg1_ipb.ts has a PCR in the first TS packet of some video PES packets
+#[test]
+fn transport_stream_clock() {
+    let clean = sample("mpeg1_ipb.ts");
+    let (ps, packets) = video_pes(&clean);
+    let pcrs: Vec<_> = packets.iter().map(|packet| packet.adaptation.and_then(|field| field.pcr)).collect();
+    assert_eq!(&pcrs[..3], [Some(0), None, Some(1800 * 300)]);
+    let last = pcrs.iter().rev().flatten().next().cloned();
+    assert!(ps.has_program_clock_ref);
+    assert_eq!(Some(ps.program_clock_ref), last);
+    assert_eq!(ps.system_clock_ref, ps.program_clock_ref / 300);
+
+    // the second PES packet starts with every field, the rest of its first
+    // TS packet moves to one more, the ones after it count one further
+    let packets: Vec<&[u8]> = clean.chunks(188).collect();
+    let field = [&[0x5C][..], &clock_ref(1800 * 300 + 299), &clock_ref(123_456_789), &[0xFD]].concat();
+    let payload = &packets[6][4..];
+    let split = 184 - 1 - field.len();
+    let mut data = packets[..6].concat();
+    data.extend_from_slice(&[0x47, 0x41, 0x00, 0x34, field.len() as u8]);
+    data.extend_from_slice(&field);
+    data.extend_from_slice(&payload[..split]);
+    let stuffing = 184 - 1 - (payload.len() - split);
+    data.extend_from_slice(&[0x47, 0x01, 0x00, 0x35, stuffing as u8, 0x00]);
+    data.extend_from_slice(&vec![0xFF; stuffing - 1]);
+    data.extend_from_slice(&payload[split..]);
+    for packet in packets[7..].iter() {
+        let mut packet = packet.to_vec();
+        if packet[1] & 0x1F == 0x01 {
+            packet[3] = (packet[3] & 0xF0) | ((packet[3] + 1) & 0x0F);
+        }
+        data.extend_from_slice(&packet);
+    }
+    assert_eq!(decode_system(&data, true), MPEG1_IPB);
+
+    let (ps, packets) = video_pes(&data);
+    assert_eq!((ps.lost_packets, ps.dropped_packets), (0, 0));
+    assert_eq!(packets[1].adaptation, Some(pkt::TsAdaptationField {
+        discontinuity:      false,
+        random_access:      true,
+        pcr:                Some(1800 * 300 + 299),
+        opcr:               Some(123_456_789),
+        splice_countdown:   Some(-3),
+    }));
+}
+
+// Program 1 has MPEG1_IPB video on PID 0x100 and audio on PID 0x101, program 2
+// the D-picture stream on PID 0x200. Their TS packets are interleaved.
+#[test]
+fn transport_stream_programs() {
+    let data = sample("two_programs.ts");
+
+    // the first video stream of the first program by default
+    let (sums, audio, _) = demux(&data, true, |_| {});
+    assert_eq!(sums, MPEG1_IPB);
+    assert!(audio.is_empty());
+
+    let (sums, audio, _) = demux(&data, true, |ps| ps.select_program(2));
+    assert_eq!(sums, MPEG1_DPICTURES);
+    assert!(audio.is_empty());
+
+    let (sums, audio, _) = demux(&data, true, |ps| ps.select_streams(&[0x100, 0x101]));
+    assert_eq!(sums, MPEG1_IPB);
+    assert_eq!(audio, [300; 24]);
+}
+
+#[test]
+fn transport_stream_tables() {
+    let data = sample("two_programs.ts");
+    let mut ps = pkt::MpegPS::new();
+    ps.push_ts(&data[..188 * 3]);
+
+    let programs = ps.programs();
+    assert_eq!(programs.len(), 2);
+    assert_eq!((programs[0].number, programs[0].pmt_pid, programs[0].pcr_pid), (1, 0x1000, 0x100));
+    let streams: Vec<_> = programs[0].streams.iter().map(|stream| (stream.pid, stream.stream_type, stream.kind)).collect();
+    assert_eq!(streams, [(0x101, 0x03, pkt::TsStreamKind::AUDIO), (0x100, 0x01, pkt::TsStreamKind::VIDEO)]);
+    assert_eq!((programs[1].number, programs[1].pmt_pid), (2, 0x1001));
+    assert_eq!(programs[1].streams[0].kind, pkt::TsStreamKind::VIDEO);
+}
+
+#[test]
+fn mpeg1_dpictures() {
+    assert_eq!(decode_slice(&sample("mpeg1_dpictures.m1v"), IdctMode::Reference), MPEG1_DPICTURES);
+}
+
+#[test]
+fn mpeg2_field_pictures() {
+    let data = sample("mpeg2_fields.m2v");
+    assert_eq!(pkt::probe(&data), pkt::StreamType::VIDEO_ES);
+    assert_eq!(decode_slice(&data, IdctMode::Reference), MPEG2_FIELDS);
+}
+
+#[test]
+fn mpeg2_coding_tools() {
+    let data = sample("mpeg2_tools.m2v");
+    assert_eq!(decode_slice(&data, IdctMode::Reference), MPEG2_TOOLS);
+
+    // the repeated first fields show in the display durations
+    let mut vcodec = Mpeg1Video::from_slice(&data);
+    vcodec.set_stats(true);
+    let mut sums = Vec::new();
+    collect(&mut vcodec, &mut sums);
+    finish(&mut vcodec, &mut sums);
+    let fields: Vec<u32> = vcodec.take_stats().iter().map(|stats| stats.fields).collect();
+    assert_eq!(fields.iter().filter(|&&fields| fields == 3).count(), 3);
+}
+
+#[test]
+fn mpeg2_dual_prime() {
+    assert_eq!(decode_slice(&sample("mpeg2_dual_prime.m2v"), IdctMode::Reference), MPEG2_DUAL_PRIME);
+}
+
+#[test]
+fn mpeg1_ipb_truncated() {
+    let data = sample("mpeg1_ipb.m1v");
+    // cut inside the third picture, the frames before it are intact
+    let mut vcodec = Mpeg1Video::from_slice(&data[..2000]);
+    vcodec.set_idct(IdctMode::Reference);
+    let mut sums = Vec::new();
+    loop {
+        match vcodec.decode() {
+            DecodeResult::GotOneFrame => sums.push(frame_md5(&vcodec)),
+            DecodeResult::NeedMoreData => panic!("the truncated picture was not reported"),
+            DecodeResult::InternalError => break,
+        }
+    }
+    assert_eq!(sums, MPEG1_IPB[..sums.len()]);
+}
@@ -0,0 +1,998 @@
+#!/usr/bin/env python3
+"""Tiny randomised MPEG-1/MPEG-2 video encoder used to produce test streams.
+
+The encoder makes random (but legal) coding decisions so that every syntax
+path of the decoder gets exercised, and writes its own reconstruction so the
+decoder output can be compared against it.
+
+usage: mpegenc.py out.m1v out.yuv [options]
+"""
+import argparse, math, os, random, re, sys
+
+DEBUG = os.environ.get('ENCDEBUG', '')
+
+VLC_RS = os.path.join(os.path.dirname(os.path.abspath(__file__)), '..', '..', 'src', 'vlc.rs')
+
+
+def codes(name):
+    """code string -> value of a decoder table in src/vlc.rs, walking its tree."""
+    src = open(VLC_RS).read()
+    body = re.search(r'pub const ' + name + r':.*?= \[(.*?)\];', src, re.S).group(1)
+    table = [(eval(a), eval(b)) for a, b in re.findall(r'\(\s*([^,()]+?)\s*,\s*([^,()]+?)\s*\)', body)]
+    out = {}
+
+    def walk(index, prefix):
+        for bit in (0, 1):
+            next_index, value = table[index + bit]
+            if next_index > 0:
+                walk(next_index, prefix + str(bit))
+            elif next_index == 0:
+                out[prefix + str(bit)] = value
+    walk(0, '')
+    return out
+
+
+def inv(name):
+    return {v: k for k, v in codes(name).items()}
+
+ADDR = inv('MP1V_MACROBLOCK_ADDRESS_INCREMENT')
+MBT_I = inv('MP1V_MACROBLOCK_TYPE_INTRA')
+MBT_P = inv('MP1V_MACROBLOCK_TYPE_PREDICTIVE')
+MBT_B = inv('MP1V_MACROBLOCK_TYPE_B')
+CBP = inv('MP1V_CODE_BLOCK_PATTERN')
+MOTION = inv('MP1V_VIDEO_MOTION')
+DC_LUMA = inv('MP2V_DCT_SIZE_LUMINANCE')
+DC_CHROMA = inv('MP2V_DCT_SIZE_CHROMINANCE')
+B14 = inv('MP1V_DCT_COEFF')
+B15 = inv('MP2V_DCT_COEFF_INTRA')
+
+ZIGZAG = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5,
+    12, 19, 26, 33, 40, 48, 41, 34, 27, 20, 13, 6, 7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51,
+    58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63]
+ALTERNATE = [
+    0, 8, 16, 24, 1, 9, 2, 10, 17, 25, 32, 40, 48, 56, 57, 49,
+    41, 33, 26, 18, 3, 11, 4, 12, 19, 27, 34, 42, 50, 58, 35, 43,
+    51, 59, 20, 28, 5, 13, 6, 14, 21, 29, 36, 44, 52, 60, 37, 45,
+    53, 61, 22, 30, 7, 15, 23, 31, 38, 46, 54, 62, 39, 47, 55, 63]
+DEFAULT_INTRA = [
+    8, 16, 19, 22, 26, 27, 29, 34, 16, 16, 22, 24, 27, 29, 34, 37,
+    19, 22, 26, 27, 29, 34, 34, 38, 22, 22, 26, 27, 29, 34, 37, 40,
+    22, 26, 27, 29, 32, 35, 40, 48, 26, 27, 29, 32, 35, 40, 48, 58,
+    26, 27, 29, 34, 38, 46, 56, 69, 27, 29, 35, 38, 46, 56, 69, 83]
+NONLINEAR = [0, 1, 2, 3, 4, 5, 6, 7, 8, 10, 12, 14, 16, 18, 20, 22,
+             24, 28, 32, 36, 40, 44, 48, 52, 56, 64, 72, 80, 88, 96, 104, 112]
+
+COS = [[(math.sqrt(0.5) if u == 0 else 1.0) / 2 * math.cos((2 * x + 1) * u * math.pi / 16)
+        for x in range(8)] for u in range(8)]
+
+
+def fdct(b):
+    t = [[sum(COS[u][x] * b[y * 8 + x] for x in range(8)) for u in range(8)] for y in range(8)]
+    return [sum(COS[v][y] * t[y][u] for y in range(8)) for v in range(8) for u in range(8)]
+
+
+def idct(f):
+    t = [[sum(COS[u][x] * f[v * 8 + u] for u in range(8)) for x in range(8)] for v in range(8)]
+    out = []
+    for y in range(8):
+        for x in range(8):
+            s = sum(COS[v][y] * t[v][x] for v in range(8))
+            s = int(math.floor(s + 0.5))
+            out.append(max(-256, min(255, s)))
+    return out
+
+
+class BitWriter:
+    def __init__(self):
+        self.bits = []
+
+    def put(self, value, count):
+        for i in range(count - 1, -1, -1):
+            self.bits.append((value >> i) & 1)
+
+    def code(self, c):
+        self.bits.extend(1 if ch == '1' else 0 for ch in c)
+
+    def align(self):
+        while len(self.bits) % 8:
+            self.bits.append(0)
+
+    def start(self, code):
+        self.align()
+        self.put(0x000001, 24)
+        self.put(code, 8)
+
+    def data(self):
+        self.align()
+        out = bytearray()
+        for i in range(0, len(self.bits), 8):
+            v = 0
+            for b in self.bits[i:i + 8]:
+                v = (v << 1) | b
+            out.append(v)
+        return bytes(out)
+
+
+class Plane:
+    def __init__(self, w, h, data=None):
+        self.w, self.h = w, h
+        self.d = data if data is not None else [0] * (w * h)
+
+    def view(self, field):
+        """(get, lines) addressing a frame (-1) or a field (0/1)."""
+        if field < 0:
+            return (lambda x, y: self.d[y * self.w + x]), self.h
+        return (lambda x, y: self.d[(2 * y + field) * self.w + x]), self.h // 2
+
+    def setter(self, field):
+        if field < 0:
+            def s(x, y, v):
+                self.d[y * self.w + x] = v
+        else:
+            def s(x, y, v):
+                self.d[(2 * y + field) * self.w + x] = v
+        return s
+
+
+class Frame:
+    def __init__(self, w, h):
+        self.p = [Plane(w, h), Plane(w // 2, h // 2), Plane(w // 2, h // 2)]
+
+    def copy(self):
+        f = Frame(self.p[0].w, self.p[0].h)
+        for i in range(3):
+            f.p[i].d = list(self.p[i].d)
+        return f
+
+
+def source_frame(w, h, t, rng):
+    f = Frame(w, h)
+    for y in range(h):
+        for x in range(w):
+            v = 128 + 60 * math.sin((x + 1.5 * t) / 7.0) * math.cos((y - t) / 5.0)
+            if (x - 3 * t) % 40 < 12 and (y + 2 * t) % 30 < 10:
+                v = 230 - (x * 3) % 40
+            v += rng.randint(-6, 6)
+            f.p[0].d[y * w + x] = max(0, min(255, int(v)))
+    for c in (1, 2):
+        for y in range(h // 2):
+            for x in range(w // 2):
+                v = 128 + (40 if c == 1 else -30) * math.sin((x + y + t * (c + 1)) / 6.0)
+                v += rng.randint(-4, 4)
+                f.p[c].d[y * (w // 2) + x] = max(0, min(255, int(v)))
+    return f
+
+
+def predict(plane, field, x, y, bw, bh, mvx, mvy):
+    """Half-pel prediction of a bw x bh block, None if it leaves the plane."""
+    get, lines = plane.view(field)
+    sx, sy = x + (mvx >> 1), y + (mvy >> 1)
+    hx, hy = mvx & 1, mvy & 1
+    if sx < 0 or sy < 0 or sx + bw + hx > plane.w or sy + bh + hy > lines:
+        return None
+    out = []
+    for j in range(bh):
+        for i in range(bw):
+            a = get(sx + i, sy + j)
+            if hx and hy:
+                v = (a + get(sx + i + 1, sy + j) + get(sx + i, sy + j + 1) + get(sx + i + 1, sy + j + 1) + 2) >> 2
+            elif hx:
+                v = (a + get(sx + i + 1, sy + j) + 1) >> 1
+            elif hy:
+                v = (a + get(sx + i, sy + j + 1) + 1) >> 1
+            else:
+                v = a
+            out.append(v)
+    return out
+
+
+def cdiv2(v):
+    return int(v / 2)
+
+
+def dp_scale(v, m):
+    return (v * m + (1 if v > 0 else 0)) >> 1
+
+
+class Encoder:
+    def __init__(self, a):
+        self.a = a
+        self.rng = random.Random(a.seed)
+        self.mpeg2 = a.mpeg2
+        self.w, self.h = a.width, a.height
+        self.mbw = (self.w + 15) >> 4
+        if self.mpeg2 and not a.progressive:
+            self.mbh = ((self.h + 31) >> 5) << 1
+        else:
+            self.mbh = (self.h + 15) >> 4
+        self.cw, self.ch = self.mbw * 16, self.mbh * 16
+        self.bw = BitWriter()
+        self.intra_matrix = list(DEFAULT_INTRA)
+        self.non_intra_matrix = [16] * 64
+
+    # ------------------------------------------------------------ headers
+    def random_matrix(self, intra):
+        m = [self.rng.randint(8, 60) for _ in range(64)]
+        if intra:
+            m[0] = 8
+        return m
+
+    def write_matrix(self, m):
+        for i in ZIGZAG:
+            self.bw.put(m[i], 8)
+
+    def sequence_header(self):
+        bw = self.bw
+        bw.start(0xB3)
+        bw.put(self.w & 0xfff, 12)
+        bw.put(self.h & 0xfff, 12)
+        bw.put(1, 4)
+        bw.put(3, 4)
+        bw.put(0x3ffff, 18)
+        bw.put(1, 1)
+        bw.put(20, 10)
+        bw.put(0, 1)
+        if self.a.matrices and self.rng.random() < 0.5:
+            self.intra_matrix = self.random_matrix(True)
+            bw.put(1, 1)
+            self.write_matrix(self.intra_matrix)
+        else:
+            self.intra_matrix = list(DEFAULT_INTRA)
+            bw.put(0, 1)
+        if self.a.matrices and self.rng.random() < 0.5:
+            self.non_intra_matrix = self.random_matrix(False)
+            bw.put(1, 1)
+            self.write_matrix(self.non_intra_matrix)
+        else:
+            self.non_intra_matrix = [16] * 64
+            bw.put(0, 1)
+        if self.mpeg2:
+            bw.start(0xB5)
+            bw.put(1, 4)
+            bw.put(0x48, 8)
+            bw.put(1 if self.a.progressive else 0, 1)
+            bw.put(1, 2)
+            bw.put(self.w >> 12, 2)
+            bw.put(self.h >> 12, 2)
+            bw.put(0, 12)
+            bw.put(1, 1)
+            bw.put(0, 8)
+            bw.put(0, 1)
+            bw.put(0, 2)
+            bw.put(0, 5)
+        if self.rng.random() < 0.5:
+            bw.start(0xB2)
+            bw.put(0x12345678, 32)
+
+    def gop_header(self):
+        self.bw.start(0xB8)
+        self.bw.put(0, 12)      # drop_frame_flag, hours, minutes
+        self.bw.put(1, 1)       # marker_bit
+        self.bw.put(0, 12)      # seconds, pictures
+        self.bw.put(1, 1)       # closed_gop
+        self.bw.put(0, 1)
+
+    def picture_header(self, tref, ptype):
+        bw = self.bw
+        bw.start(0x00)
+        bw.put(tref & 0x3ff, 10)
+        bw.put(ptype, 3)
+        bw.put(0xffff, 16)
+        for s in range(2 if ptype == 3 else (1 if ptype == 2 else 0)):
+            bw.put(0, 1)
+            bw.put(7 if self.mpeg2 else self.fcode[s][0], 3)
+        if self.rng.random() < 0.2:
+            bw.put(1, 1)
+            bw.put(0xa5, 8)
+        bw.put(0, 1)
+        if not self.mpeg2:
+            return
+        bw.start(0xB5)
+        bw.put(8, 4)
+        for s in range(2):
+            for t in range(2):
+                bw.put(self.fcode[s][t], 4)
+        bw.put(self.dc_precision, 2)
+        bw.put(self.structure, 2)
+        bw.put(1 if self.tff else 0, 1)
+        bw.put(1 if self.fpfd else 0, 1)
+        bw.put(1 if self.concealment else 0, 1)
+        bw.put(1 if self.q_scale_type else 0, 1)
+        bw.put(1 if self.intra_vlc else 0, 1)
+        bw.put(1 if self.alt_scan else 0, 1)
+        bw.put(1 if self.repeat_first_field else 0, 1)
+        bw.put(1, 1)
+        bw.put(1 if self.progressive_frame else 0, 1)
+        bw.put(0, 1)
+        if self.a.matrices and self.rng.random() < 0.3:
+            bw.start(0xB5)
+            bw.put(3, 4)
+            for intra in (True, False):
+                if self.rng.random() < 0.6:
+                    m = self.random_matrix(intra)
+                    if intra:
+                        self.intra_matrix = m
+                    else:
+                        self.non_intra_matrix = m
+                    bw.put(1, 1)
+                    self.write_matrix(m)
+                else:
+                    bw.put(0, 1)
+            # chroma matrices are ignored for 4:2:0
+            bw.put(1, 1)
+            self.write_matrix(self.random_matrix(True))
+            bw.put(0, 1)
+
+    # ------------------------------------------------------------ coding helpers
+    def q_scale(self, code):
+        if not self.mpeg2:
+            return code
+        return NONLINEAR[code] if self.q_scale_type else code << 1
+
+    def put_increment(self, inc):
+        while inc > 33:
+            self.bw.code(ADDR[35])
+            inc -= 33
+        self.bw.code(ADDR[inc])
+
+    def put_vector_component(self, target, pred, r_size):
+        f = 1 << r_size
+        low, high = -16 * f, 16 * f - 1
+        assert low <= target <= high, (target, r_size)
+        delta = target - pred
+        if delta > high:
+            delta -= 32 * f
+        elif delta < low:
+            delta += 32 * f
+        if f == 1 or delta == 0:
+            self.bw.code(MOTION[delta])
+            return
+        a = abs(delta) - 1
+        code = a // f + 1
+        residual = a % f
+        self.bw.code(MOTION[code if delta > 0 else -code])
+        self.bw.put(residual, r_size)
+
+    def put_dmv(self, v):
+        self.bw.code({0: '0', 1: '10', -1: '11'}[v])
+
+    def put_motion_vectors(self, s, mv):
+        """mv: dict with type, vectors [[h,v],..], field_select, dmv."""
+        frame_pic = self.structure == 3
+        mtype = mv['type']
+        dual = mtype == 'dual'
+        if frame_pic:
+            count = 2 if mtype == 'field' else 1
+            field_fmt = mtype != 'frame'
+        else:
+            count = 2 if mtype == '16x8' else 1
+            field_fmt = True
+        r_size = [self.fcode[s][0] - 1, self.fcode[s][1] - 1]
+        for r in range(count):
+            if self.mpeg2 and field_fmt and not dual:
+                self.bw.put(mv['fs'][r], 1)
+            h, v = mv['vec'][r]
+            self.put_vector_component(h, self.pmv[s][r][0], r_size[0])
+            if dual:
+                self.put_dmv(mv['dmv'][0])
+            if frame_pic and field_fmt:
+                self.put_vector_component(v, self.pmv[s][r][1] >> 1, r_size[1])
+                self.pmv[s][r] = [h, v * 2]
+            else:
+                self.put_vector_component(v, self.pmv[s][r][1], r_size[1])
+                self.pmv[s][r] = [h, v]
+            if dual:
+                self.put_dmv(mv['dmv'][1])
+        if count == 1:
+            self.pmv[s][1] = list(self.pmv[s][0])
+
+    def put_dc(self, dc, comp):
+        diff = dc - self.dc_pred[comp]
+        self.dc_pred[comp] = dc
+        size = abs(diff).bit_length()
+        self.bw.code((DC_LUMA if comp == 0 else DC_CHROMA)[size])
+        if size:
+            self.bw.put(diff if diff > 0 else diff + (1 << size) - 1, size)
+
+    def put_block(self, qf, intra, comp):
+        """qf: quantised coefficients in raster order."""
+        bw = self.bw
+        scan = ALTERNATE if self.alt_scan else ZIGZAG
+        start = 0
+        if intra:
+            self.put_dc(qf[0], comp)
+            start = 1
+        table = B15 if (intra and self.intra_vlc) else B14
+        run = 0
+        first = True
+        for i in range(start, 64):
+            level = qf[scan[i]]
+            if level == 0:
+                run += 1
+                continue
+            key = (run << 8) | abs(level)
+            if abs(level) < 256 and key in table and key != 0xffff:
+                c = table[key]
+                if table is B14 and key == 0x0001 and not (first and not intra):
+                    c = '11'
+                bw.code(c)
+                bw.put(1 if level < 0 else 0, 1)
+            else:
+                bw.code(table[0xffff])
+                bw.put(run, 6)
+                if self.mpeg2:
+                    bw.put(level & 0xfff, 12)
+                elif -127 <= level <= 127:
+                    bw.put(level & 0xff, 8)
+                elif level > 0:
+                    bw.put(0, 8)
+                    bw.put(level, 8)
+                else:
+                    bw.put(0x80, 8)
+                    bw.put(level + 256, 8)
+            run = 0
+            first = False
+        bw.code(B15[0x0000] if table is B15 else '10')
+
+    def quantise(self, coeffs, intra, qs):
+        m = self.intra_matrix if intra else self.non_intra_matrix
+        div = 32 if self.mpeg2 else 16
+        limit = 2047 if self.mpeg2 else 255
+        qf = [0] * 64
+        start = 0
+        if intra:
+            mult = 8 >> self.dc_precision
+            dcmax = (1 << (8 + self.dc_precision)) - 1
+            qf[0] = max(0, min(dcmax, int(round(coeffs[0] / mult))))
+            start = 1
+        for i in range(start, 64):
+            x = coeffs[i] * div / (2 * qs * m[i])
+            if intra:
+                l = int(round(x))
+            else:
+                l = int(x)
+            qf[i] = max(-limit, min(limit, l))
+        return qf
+
+    def dequantise(self, qf, intra, qs):
+        m = self.intra_matrix if intra else self.non_intra_matrix
+        div = 32 if self.mpeg2 else 16
+        f = [0] * 64
+        start = 0
+        if intra:
+            f[0] = qf[0] * (8 >> self.dc_precision)
+            start = 1
+        for i in range(start, 64):
+            l = qf[i]
+            if l == 0:
+                continue
+            if intra:
+                v = 2 * l * qs * m[i]
+            else:
+                v = (2 * l + (1 if l > 0 else -1)) * qs * m[i]
+            v = int(v / div)
+            if not self.mpeg2 and v & 1 == 0 and v != 0:
+                v -= 1 if v > 0 else -1
+            f[i] = max(-2048, min(2047, v))
+        if self.mpeg2 and sum(f) & 1 == 0:
+            f[63] = f[63] - 1 if f[63] & 1 else f[63] + 1
+        return f
+
+    # ------------------------------------------------------------ prediction
+    def ref_for(self, s, field):
+        """Reference frame for direction s when reading field `field` (-1: frame)."""
+        if s == 0:
+            if (self.structure != 3 and self.second_field and self.ptype == 2
+                    and field != self.parity):
+                return self.cur
+            return self.fwd
+        return self.bwd
+
+    def mb_prediction(self, col, row, mv, s):
+        """Prediction of one direction as 3 lists in picture MB layout, or None."""
+        frame_pic = self.structure == 3
+        out = [[0] * 256, [0] * 64, [0] * 64]
+
+        def place(pl, rows, dst_rows, block, bw_):
+            for j, dy in enumerate(dst_rows):
+                for i in range(bw_):
+                    out[pl][dy * bw_ + i] = block[j * bw_ + i]
+
+        def region(ref, field, y, height, vec, dst_rows_fn):
+            if ref is None:
+                return False
+            for pl in range(3):
+                sh = 0 if pl == 0 else 1
+                vx, vy = (vec[0], vec[1]) if pl == 0 else (cdiv2(vec[0]), cdiv2(vec[1]))
+                bwid = 16 >> sh
+                b = predict(ref.p[pl], field, (col * 16) >> sh, y >> sh, bwid, height >> sh, vx, vy)
+                if b is None:
+                    return False
+                place(pl, None, dst_rows_fn(pl, sh), b, bwid)
+            return True
+
+        mtype = mv['type']
+        if frame_pic:
+            ref = self.fwd if s == 0 else self.bwd
+            if mtype == 'frame':
+                ok = region(ref, -1, row * 16, 16, mv['vec'][0], lambda pl, sh: range(16 >> sh))
+                return out if ok else None
+            if mtype == 'field':
+                for r in range(2):
+                    ok = region(ref, mv['fs'][r], row * 8, 8, mv['vec'][r],
+                                lambda pl, sh, r=r: range(r, 16 >> sh, 2))
+                    if not ok:
+                        return None
+                return out
+            # dual prime, frame picture
+            vx, vy = mv['vec'][0]
+            dmv = mv['dmv']
+            res = []
+            for r in range(2):
+                if r == 0:
+                    m, e = (1 if self.tff else 3), -1
+                else:
+                    m, e = (3 if self.tff else 1), 1
+                other = (dp_scale(vx, m) + dmv[0], dp_scale(vy, m) + dmv[1] + e)
+                a = [[0] * 256, [0] * 64, [0] * 64]
+                rows = lambda pl, sh, r=r: range(r, 16 >> sh, 2)
+                saved = out
+                ok1 = region(ref, r, row * 8, 8, (vx, vy), rows)
+                if not ok1:
+                    return None
+                same = [list(p) for p in out]
+                ok2 = region(ref, 1 - r, row * 8, 8, other, rows)
+                if not ok2:
+                    return None
+                for pl in range(3):
+                    bwid = 16 >> (0 if pl == 0 else 1)
+                    for dy in rows(pl, 0 if pl == 0 else 1):
+                        for i in range(bwid):
+                            k = dy * bwid + i
+                            out[pl][k] = (same[pl][k] + out[pl][k] + 1) >> 1
+            return out
+        # field picture
+        if mtype == 'field':
+            f = mv['fs'][0]
+            ok = region(self.ref_for(s, f), f, row * 16, 16, mv['vec'][0], lambda pl, sh: range(16 >> sh))
+            return out if ok else None
+        if mtype == '16x8':
+            for r in range(2):
+                f = mv['fs'][r]
+                ok = region(self.ref_for(s, f), f, row * 16 + r * 8, 8, mv['vec'][r],
+                            lambda pl, sh, r=r: range(r * (8 >> sh), (r + 1) * (8 >> sh)))
+                if not ok:
+                    return None
+            return out
+        # dual prime, field picture
+        vx, vy = mv['vec'][0]
+        dmv = mv['dmv']
+        p = self.parity
+        e = -1 if p == 0 else 1
+        ok = region(self.ref_for(0, p), p, row * 16, 16, (vx, vy), lambda pl, sh: range(16 >> sh))
+        if not ok:
+            return None
+        same = [list(q) for q in out]
+        other = (dp_scale(vx, 1) + dmv[0], dp_scale(vy, 1) + dmv[1] + e)
+        ok = region(self.ref_for(0, 1 - p), 1 - p, row * 16, 16, other, lambda pl, sh: range(16 >> sh))
+        if not ok:
+            return None
+        for pl in range(3):
+            for k in range(len(out[pl])):
+                out[pl][k] = (same[pl][k] + out[pl][k] + 1) >> 1
+        return out
+
+    def full_prediction(self, col, row, motion):
+        preds = []
+        for s in (0, 1):
+            if motion[s] is not None:
+                p = self.mb_prediction(col, row, motion[s], s)
+                if p is None:
+                    return None
+                preds.append(p)
+        if len(preds) == 1:
+            return preds[0]
+        return [[(a + b + 1) >> 1 for a, b in zip(preds[0][pl], preds[1][pl])] for pl in range(3)]
+
+    def random_motion(self, s, allow_dual, force=None):
+        frame_pic = self.structure == 3
+        r = [1 << (self.fcode[s][0] - 1), 1 << (self.fcode[s][1] - 1)]
+        big = self.rng.random() < 0.2
+
+        def vec(scale_v=1):
+            if big:
+                return [self.rng.randint(-16 * r[0], 16 * r[0] - 1),
+                        self.rng.randint(-16 * r[1] // scale_v, 16 * r[1] // scale_v - 1)]
+            return [self.rng.randint(-7, 7), self.rng.randint(-5, 5)]
+
+        types = []
+        if frame_pic:
+            types = ['frame'] if (self.fpfd or not self.mpeg2 or 'nofield' in DEBUG) else ['frame', 'field']
+            if allow_dual and not self.fpfd:
+                types.append('dual')
+        else:
+            types = ['field', '16x8'] + (['dual'] if allow_dual else [])
+        t = self.rng.choice(types) if force is None else force
+        mv = {'type': t, 'fs': [self.rng.randint(0, 1), self.rng.randint(0, 1)],
+              'dmv': [self.rng.randint(-1, 1), self.rng.randint(-1, 1)]}
+        if t == 'frame':
+            mv['vec'] = [vec()]
+        elif t == 'field' and frame_pic:
+            mv['vec'] = [vec(2), vec(2)]
+        elif t == 'field':
+            mv['vec'] = [vec()]
+        elif t == '16x8':
+            mv['vec'] = [vec(), vec()]
+        else:
+            v = vec(2 if frame_pic else 1)
+            v = [max(-8, min(8, v[0])), max(-8, min(8, v[1]))]
+            mv['vec'] = [v]
+        return mv
+
+    def zero_motion(self):
+        if self.structure == 3:
+            return {'type': 'frame', 'vec': [[0, 0]], 'fs': [0, 0], 'dmv': [0, 0]}
+        return {'type': 'field', 'vec': [[0, 0]], 'fs': [self.parity, self.parity], 'dmv': [0, 0]}
+
+    # ------------------------------------------------------------ picture coding
+    def mb_source(self, src, col, row):
+        """Source MB in picture layout."""
+        out = []
+        for pl in range(3):
+            sh = 0 if pl == 0 else 1
+            field = -1 if self.structure == 3 else self.parity
+            get, _ = src.p[pl].view(field)
+            n = 16 >> sh
+            out.append([get((col * 16 >> sh) + i, (row * 16 >> sh) + j) for j in range(n) for i in range(n)])
+        return out
+
+    def store_mb(self, pix, col, row):
+        for pl in range(3):
+            sh = 0 if pl == 0 else 1
+            field = -1 if self.structure == 3 else self.parity
+            st = self.cur.p[pl].setter(field)
+            n = 16 >> sh
+            for j in range(n):
+                for i in range(n):
+                    st((col * 16 >> sh) + i, (row * 16 >> sh) + j, pix[pl][j * n + i])
+
+    def block_indices(self, k, dct_type):
+        """Picture-MB pixel indices of block k (plane, list of 64 indices)."""
+        if k >= 4:
+            return (k - 3, [y * 8 + x for y in range(8) for x in range(8)])
+        x0 = (k & 1) * 8
+        if dct_type:
+            rows = [2 * i + (k >> 1) for i in range(8)]
+        else:
+            rows = [(k >> 1) * 8 + i for i in range(8)]
+        return (0, [r * 16 + x0 + x for r in rows for x in range(8)])
+
+    def code_mb_residual(self, srcmb, pred, intra, qs, dct_type):
+        """Returns (cbp, list of qf per block, reconstructed MB)."""
+        recon = [list(p) if p is not None else [0] * len(s) for p, s in zip(pred or [None] * 3, srcmb)]
+        qfs = []
+        cbp = 0
+        for k in range(6):
+            pl, idx = self.block_indices(k, dct_type)
+            blk = [srcmb[pl][i] - (0 if intra else pred[pl][i]) for i in idx]
+            qf = self.quantise(fdct(blk), intra, qs)
+            coded = intra or any(qf)
+            qfs.append(qf)
+            if coded:
+                cbp |= 0x20 >> k
+                res = idct(self.dequantise(qf, intra, qs))
+            else:
+                res = [0] * 64
+            for n, i in enumerate(idx):
+                base = 0 if intra else pred[pl][i]
+                recon[pl][i] = max(0, min(255, base + res[n]))
+        return cbp, qfs, recon
+
+    def reset_dc(self):
+        self.dc_pred = [128 << self.dc_precision] * 3
+
+    def reset_pmv(self):
+        self.pmv = [[[0, 0], [0, 0]], [[0, 0], [0, 0]]]
+
+    def code_picture(self, src, ptype):
+        rng = self.rng
+        mbh = self.mbh if self.structure == 3 else self.mbh // 2
+        frame_pic = self.structure == 3
+        slices = []
+        if self.a.long_slices and not self.mpeg2:
+            # MPEG-1 slices may run over several macroblock rows
+            total = mbh * self.mbw
+            a0 = 0
+            while a0 < total:
+                a1 = min(total, a0 + rng.randint(1, 3 * self.mbw))
+                slices.append((a0, a1))
+                a0 = a1
+        else:
+            for row in range(mbh):
+                # split the row into one or two slices
+                cuts = [0]
+                if self.mbw > 2 and rng.random() < 0.3:
+                    cuts.append(rng.randint(1, self.mbw - 1))
+                cuts.append(self.mbw)
+                for si in range(len(cuts) - 1):
+                    slices.append((row * self.mbw + cuts[si], row * self.mbw + cuts[si + 1]))
+        for a0, a1 in slices:
+            row, c0 = divmod(a0, self.mbw)
+            self.bw.start(row + 1)
+            qcode = rng.randint(2, 12)
+            self.bw.put(qcode, 5)
+            if self.mpeg2 and rng.random() < 0.3:
+                self.bw.put(1, 1)
+                self.bw.put(0, 1)
+                self.bw.put(0, 7)
+            self.bw.put(0, 1)
+            self.reset_dc()
+            self.reset_pmv()
+            last_addr = row * self.mbw + c0 - 1
+            prev = None  # previous coded MB motion in this slice (B skip)
+            prev_intra = True
+            pending_skip = 0
+            for addr in range(a0, a1):
+                row, col = divmod(addr, self.mbw)
+                srcmb = self.mb_source(src, col, row)
+                if ptype == 4:
+                    self.put_increment(1 if addr != a0 else col + 1)
+                    self.bw.code('1')
+                    recon = [[0] * 256, [0] * 64, [0] * 64]
+                    for k in range(6):
+                        pl, idx = self.block_indices(k, False)
+                        qf = self.quantise(fdct([srcmb[pl][i] for i in idx]), True, 1)
+                        qf = [qf[0]] + [0] * 63
+                        self.put_dc(qf[0], 0 if k < 4 else k - 3)
+                        res = idct(self.dequantise(qf, True, 1))
+                        for n, i in enumerate(idx):
+                            recon[pl][i] = max(0, min(255, res[n]))
+                    self.bw.code('1')
+                    self.store_mb(recon, col, row)
+                    continue
+                first = addr == a0
+                last = addr == a1 - 1
+                # skipped macroblock?
+                if not first and not last and ptype != 1 and rng.random() < self.a.skip and 'noskip' not in DEBUG:
+                    if ptype == 2:
+                        motion = [self.zero_motion(), None]
+                    else:
+                        motion = None if prev_intra else prev
+                    if motion is not None:
+                        pred = self.full_prediction(col, row, motion)
+                        if pred is not None:
+                            self.store_mb(pred, col, row)
+                            pending_skip += 1
+                            if ptype == 2:
+                                self.reset_pmv()
+                            self.reset_dc()
+                            continue
+                inc = pending_skip + 1 if not first else col + 1
+                pending_skip = 0
+                # choose coding mode
+                quant = rng.random() < 0.2
+                new_q = rng.randint(1, 31) if quant else qcode
+                intra = ptype == 1 or rng.random() < 0.1
+                motion = [None, None]
+                no_mc = False
+                if not intra:
+                    for _ in range(20):
+                        if ptype == 2:
+                            if rng.random() < 0.2:
+                                motion = [self.zero_motion(), None]
+                                no_mc = True
+                            else:
+                                motion = [self.random_motion(0, self.a.dual_prime), None]
+                        else:
+                            d = rng.choice(['f', 'b', 'fb']) if 'dir' not in DEBUG else os.environ['DIR']
+                            # both directions share the macroblock's motion type
+                            f = self.random_motion(0, False) if 'f' in d else None
+                            b = self.random_motion(1, False, f['type'] if f else None) if 'b' in d else None
+                            motion = [f, b]
+                        pred = self.full_prediction(col, row, motion)
+                        if pred is not None:
+                            break
+                        no_mc = False
+                    else:
+                        # no usable reference, fall back to intra coding
+                        intra = True
+                        motion = [None, None]
+                        no_mc = False
+                if intra:
+                    pred = None
+                dct_type = (self.mpeg2 and frame_pic and not self.fpfd and rng.random() < 0.5 and 'nodct' not in DEBUG)
+                qs = self.q_scale(new_q)
+                cbp, qfs, recon = self.code_mb_residual(srcmb, pred, intra, qs, dct_type)
+                pattern = intra or cbp != 0
+                if not intra and cbp == 0:
+                    quant = False
+                    new_q = qcode
+                    if no_mc:
+                        no_mc = False  # code as forward MC with a zero vector
+                if not pattern:
+                    dct_type = False
+                # write macroblock
+                self.put_increment(inc)
+                fwd = motion[0] is not None and not no_mc
+                bwd = motion[1] is not None
+                if intra:
+                    tval = 0x11 if quant else 0x01
+                else:
+                    tval = (0x10 if quant else 0) | (0x08 if fwd else 0) | (0x04 if bwd else 0) | (0x02 if cbp else 0)
+                table = {1: MBT_I, 2: MBT_P, 3: MBT_B}[ptype]
+                self.bw.code(table[tval])
+                # motion type
+                if self.mpeg2 and (fwd or bwd):
+                    mt = (motion[0] or motion[1])['type']
+                    if frame_pic:
+                        if not self.fpfd:
+                            self.bw.put({'field': 1, 'frame': 2, 'dual': 3}[mt], 2)
+                    else:
+                        self.bw.put({'field': 1, '16x8': 2, 'dual': 3}[mt], 2)
+                if self.mpeg2 and frame_pic and not self.fpfd and pattern:
+                    self.bw.put(1 if dct_type else 0, 1)
+                if quant:
+                    self.bw.put(new_q, 5)
+                    qcode = new_q
+                if intra:
+                    if self.concealment:
+                        cm = self.zero_motion()
+                        cm['vec'] = [[rng.randint(-4, 4), rng.randint(-4, 4)]]
+                        cm['fs'] = [rng.randint(0, 1)] * 2
+                        if frame_pic:
+                            cm['type'] = 'frame'
+                        self.put_motion_vectors(0, cm)
+                        self.bw.put(1, 1)
+                    else:
+                        self.reset_pmv()
+                else:
+                    self.reset_dc()
+                    if fwd:
+                        self.put_motion_vectors(0, motion[0])
+                    elif ptype == 2:
+                        self.reset_pmv()
+                    if bwd:
+                        self.put_motion_vectors(1, motion[1])
+                if cbp and not intra:
+                    self.bw.code(CBP[cbp])
+                for k in range(6):
+                    if cbp & (0x20 >> k):
+                        self.put_block(qfs[k], intra, 0 if k < 4 else k - 3)
+                self.store_mb(recon, col, row)
+                prev = motion if not intra else None
+                prev_intra = intra
+                # B skip needs the motion in this direction set; no_mc P uses zero
+                if not intra and ptype == 3:
+                    prev = motion
+
+    def encode(self, n):
+        a = self.a
+        rng = self.rng
+        srcs = [source_frame(self.cw, self.ch, t, random.Random(a.seed * 1000 + t)) for t in range(n)]
+        # coding order
+        order = []
+        i = 0
+        anchors = list(range(0, n, a.m))
+        if anchors[-1] != n - 1:
+            anchors.append(n - 1)
+        prev_anchor = None
+        for k, an in enumerate(anchors):
+            ptype = 1 if (an % a.gop == 0) else 2
+            order.append((an, ptype))
+            if prev_anchor is not None and a.bframes:
+                for b in range(prev_anchor + 1, an):
+                    order.append((b, 3))
+            elif prev_anchor is not None:
+                pass
+            prev_anchor = an
+        if not a.bframes:
+            order = []
+            for t in range(n):
+                order.append((t, 1 if t % a.gop == 0 else 2))
+        if a.dpictures:
+            order = [(t, 4) for t in range(n)]
+        recon = {}
+        self.fwd = self.bwd = None
+        self.sequence_header()
+        for idx, (t, ptype) in enumerate(order):
+            if idx and rng.random() < 0.15:
+                self.sequence_header()
+            if ptype == 1:
+                self.gop_header()
+            self.ptype = ptype
+            self.progressive_frame = a.progressive or not self.mpeg2
+            if a.repeat and self.mpeg2 and not self.progressive_frame and rng.random() < 0.5:
+                # a progressive frame in an interlaced sequence, shown for three fields
+                self.progressive_frame = True
+            self.repeat_first_field = a.repeat and self.progressive_frame and self.mpeg2 and rng.random() < 0.5
+            use_fields = self.mpeg2 and not self.progressive_frame and rng.random() < a.fields
+            self.cur = Frame(self.cw, self.ch)
+            if ptype != 3:
+                self.fwd = self.bwd
+            # a repeated progressive frame picks the field it repeats
+            fixed = not self.mpeg2 or (self.progressive_frame and not self.repeat_first_field)
+            self.tff = False if fixed else rng.random() < 0.5
+            parities = [0, 1] if rng.random() < 0.5 else [1, 0]
+            pics = [(3, ptype)] if not use_fields else [(1 + parities[0], ptype), (1 + parities[1], ptype)]
+            if use_fields and ptype == 1 and rng.random() < 0.5:
+                pics[1] = (pics[1][0], 2)
+            for fi, (structure, pt) in enumerate(pics):
+                self.structure = structure
+                self.second_field = fi == 1
+                self.parity = 1 if structure == 2 else 0
+                self.ptype = pt
+                if pt == 2 and self.fwd is None:
+                    # only the first field of this frame is available
+                    if not self.second_field:
+                        raise SystemExit('P picture without reference')
+                    self.fwd_saved = None
+                self.fcode = [[rng.randint(1, 3), rng.randint(1, 3)], [rng.randint(1, 3), rng.randint(1, 3)]]
+                if not self.mpeg2:
+                    self.fcode = [[self.fcode[0][0]] * 2, [self.fcode[1][0]] * 2]
+                if pt == 1:
+                    self.fcode = [[15, 15], [15, 15]] if self.mpeg2 else [[1, 1], [1, 1]]
+                elif pt == 2:
+                    self.fcode[1] = [15, 15] if self.mpeg2 else [1, 1]
+                if self.mpeg2 and (pt == 1 and not a.concealment):
+                    pass
+                self.dc_precision = rng.randint(0, 3) if self.mpeg2 else 0
+                self.fpfd = True if (self.progressive_frame or not self.mpeg2) else (structure != 3 or rng.random() < 0.3)
+                if self.mpeg2 and structure != 3:
+                    self.fpfd = False
+                self.concealment = self.mpeg2 and a.concealment and rng.random() < 0.5
+                self.q_scale_type = self.mpeg2 and rng.random() < 0.5
+                self.intra_vlc = self.mpeg2 and rng.random() < 0.5
+                self.alt_scan = self.mpeg2 and rng.random() < 0.5
+                if self.concealment and pt == 1:
+                    # concealment vectors need a forward f_code
+                    self.fcode[0] = [rng.randint(1, 3), rng.randint(1, 3)]
+                self.picture_header(t % 1024, pt)
+                self.code_picture(srcs[t], pt)
+            if ptype != 3:
+                self.bwd = self.cur
+            recon[t] = self.cur
+        self.bw.start(0xB7)
+        return [recon[t] for t in range(n)]
+
+
+def main():
+    ap = argparse.ArgumentParser()
+    ap.add_argument('out')
+    ap.add_argument('recon')
+    ap.add_argument('--mpeg2', action='store_true')
+    ap.add_argument('--progressive', action='store_true')
+    ap.add_argument('--width', type=int, default=64)
+    ap.add_argument('--height', type=int, default=48)
+    ap.add_argument('--frames', type=int, default=10)
+    ap.add_argument('--m', type=int, default=3)
+    ap.add_argument('--gop', type=int, default=9)
+    ap.add_argument('--bframes', action='store_true')
+    ap.add_argument('--fields', type=float, default=0.0)
+    ap.add_argument('--dual-prime', action='store_true')
+    ap.add_argument('--concealment', action='store_true')
+    ap.add_argument('--repeat', action='store_true', help='repeat_first_field on some progressive frames')
+    ap.add_argument('--matrices', action='store_true')
+    ap.add_argument('--dpictures', action='store_true')
+    ap.add_argument('--seed', type=int, default=1)
+    ap.add_argument('--skip', type=float, default=0.2)
+    ap.add_argument('--long-slices', action='store_true')
+    ap.add_argument('--sentinel', action='store_true', help='append a picture start code after the end code')
+    a = ap.parse_args()
+    if not a.bframes:
+        a.m = 1
+    enc = Encoder(a)
+    frames = enc.encode(a.frames)
+    data = enc.bw.data()
+    if a.sentinel:
+        data += b'\x00\x00\x01\x00'
+    open(a.out, 'wb').write(data)
+    with open(a.recon, 'wb') as f:
+        for fr in frames:
+            for p in fr.p:
+                f.write(bytes(p.d))
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,147 @@
+#!/usr/bin/env python3
+"""Wrap a video elementary stream into an MPEG-1 program stream or a transport stream.
+
+usage: mux.py ps|ts in.m1v out [--chunk N]
+"""
+import sys, argparse
+
+
+def pts_bytes(prefix, ts):
+    return bytes([
+        (prefix << 4) | (((ts >> 30) & 0x07) << 1) | 1,
+        (ts >> 22) & 0xff,
+        (((ts >> 15) & 0x7f) << 1) | 1,
+        (ts >> 7) & 0xff,
+        ((ts & 0x7f) << 1) | 1,
+    ])
+
+
+def pes_packets(es, chunk):
+    """MPEG-1 syntax PES packets (stream 0xE0), PTS on every other packet."""
+    out = []
+    for n, i in enumerate(range(0, len(es), chunk)):
+        payload = es[i:i + chunk]
+        if n % 2 == 0:
+            header = pts_bytes(0x2, 3600 * n + 9000)
+        else:
+            header = b'\x0f'
+        body = header + payload
+        out.append(b'\x00\x00\x01\xe0' + len(body).to_bytes(2, 'big') + body)
+    return out
+
+
+def pack_header(scr, mux_rate=2000):
+    return (b'\x00\x00\x01\xba' + pts_bytes(0x2, scr) +
+            bytes([0x80 | (mux_rate >> 15) & 0x7f, (mux_rate >> 7) & 0xff, ((mux_rate & 0x7f) << 1) | 1]))
+
+
+def system_header(mux_rate=2000):
+    body = bytes([0x80 | (mux_rate >> 15) & 0x7f, (mux_rate >> 7) & 0xff, ((mux_rate & 0x7f) << 1) | 1,
+                  (0 << 2) | 0x03,            # audio_bound 0, fixed 0, csps 1
+                  0xe0 | 1,                   # locks, marker, video_bound 1
+                  0xff,                       # reserved
+                  0xe0, 0xe0 | 0x20, 46])     # stream 0xE0, buffer scale 1, size 46 KiB
+    return b'\x00\x00\x01\xbb' + len(body).to_bytes(2, 'big') + body
+
+
+def mux_ps(es, chunk):
+    out = bytearray()
+    for n, pes in enumerate(pes_packets(es, chunk)):
+        out += pack_header(3600 * n)
+        if n == 0:
+            out += system_header()
+        if n % 3 == 2:
+            # padding stream
+            out += b'\x00\x00\x01\xbe\x00\x04\xff\xff\xff\xff'
+        out += pes
+    out += b'\x00\x00\x01\xb9'
+    return bytes(out)
+
+
+def crc32_mpeg(data):
+    crc = 0xffffffff
+    for b in data:
+        crc ^= b << 24
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x04c11db7) if crc & 0x80000000 else (crc << 1)
+            crc &= 0xffffffff
+    return crc
+
+
+def psi_packet(pid, table_id, ext, body, cc):
+    section = bytes([table_id]) + (0xb000 | (len(body) + 9)).to_bytes(2, 'big') + \
+        ext.to_bytes(2, 'big') + b'\xc1\x00\x00' + body
+    section += crc32_mpeg(section).to_bytes(4, 'big')
+    payload = b'\x00' + section
+    head = bytes([0x47, 0x40 | (pid >> 8), pid & 0xff, 0x10 | (cc & 0x0f)])
+    return head + payload + b'\xff' * (184 - len(payload))
+
+
+def ts_packets(pid, data, cc, pcr=None):
+    """Split one PES packet into TS packets, stuffing the last one."""
+    out = []
+    first = True
+    pos = 0
+    while pos < len(data):
+        adaptation = b''
+        if first and pcr is not None:
+            base = pcr
+            adaptation = bytes([0x10]) + bytes([
+                (base >> 25) & 0xff, (base >> 17) & 0xff, (base >> 9) & 0xff,
+                (base >> 1) & 0xff, ((base & 1) << 7) | 0x7e, 0x00])
+        room = 184 - (len(adaptation) + 1 if adaptation else 0)
+        remain = len(data) - pos
+        if remain < room:
+            # stuff the adaptation field so the payload ends the packet
+            if not adaptation:
+                need = 184 - remain
+                adaptation = b'' if need == 1 else bytes([0x00]) + b'\xff' * (need - 2)
+                field = bytes([need - 1]) + adaptation
+            else:
+                field = bytes([len(adaptation) + (room - remain)]) + adaptation + b'\xff' * (room - remain)
+        else:
+            field = bytes([len(adaptation)]) + adaptation if adaptation else b''
+        take = 184 - len(field)
+        control = 0x30 if field else 0x10
+        head = bytes([0x47, (0x40 if first else 0) | (pid >> 8), pid & 0xff, control | (cc & 0x0f)])
+        pkt = head + field + data[pos:pos + take]
+        assert len(pkt) == 188, len(pkt)
+        out.append(pkt)
+        pos += take
+        cc += 1
+        first = False
+    return out, cc
+
+
+def mux_ts(es, chunk, stream_type):
+    pmt_pid, video_pid = 0x1000, 0x100
+    pat = (1).to_bytes(2, 'big') + (0xe000 | pmt_pid).to_bytes(2, 'big')
+    pmt = (0xe000 | video_pid).to_bytes(2, 'big') + b'\xf0\x00' + \
+        bytes([stream_type]) + (0xe000 | video_pid).to_bytes(2, 'big') + b'\xf0\x00'
+    out = bytearray()
+    cc = 0
+    for n, pes in enumerate(pes_packets(es, chunk)):
+        if n % 4 == 0:
+            out += psi_packet(0, 0x00, 1, pat, n // 4)
+            out += psi_packet(pmt_pid, 0x02, 1, pmt, n // 4)
+        pkts, cc = ts_packets(video_pid, pes, cc, pcr=900 * n if n % 2 == 0 else None)
+        for p in pkts:
+            out += p
+    return bytes(out)
+
+
+def main():
+    ap = argparse.ArgumentParser()
+    ap.add_argument('kind', choices=['ps', 'ts'])
+    ap.add_argument('input')
+    ap.add_argument('output')
+    ap.add_argument('--chunk', type=int, default=2000)
+    ap.add_argument('--stream-type', type=int, default=1)
+    a = ap.parse_args()
+    es = open(a.input, 'rb').read()
+    data = mux_ps(es, a.chunk) if a.kind == 'ps' else mux_ts(es, a.chunk, a.stream_type)
+    open(a.output, 'wb').write(data)
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,17 @@
+#!/bin/sh
+# Regenerates tests/data with the seeds the conformance tests were written for.
+# The .yuv files are the encoder's own reconstructions, `md5sum` over each
+# frame of them gives the MD5s listed in tests/conformance.rs.
+set -e
+gen=$(cd "$(dirname "$0")" && pwd)
+cd "${1:-$gen/../data}"
+
+python3 "$gen/mpegenc.py" mpeg1_ipb.m1v mpeg1_ipb.yuv --width 96 --height 64 --frames 8 --bframes --matrices --skip 0.3 --seed 7
+python3 "$gen/mpegenc.py" mpeg1_dpictures.m1v mpeg1_dpictures.yuv --width 48 --height 32 --frames 3 --dpictures --seed 3
+python3 "$gen/mpegenc.py" mpeg2_fields.m2v mpeg2_fields.yuv --mpeg2 --width 64 --height 64 --frames 5 --bframes --fields 0.5 --seed 11
+python3 "$gen/mpegenc.py" mpeg2_tools.m2v mpeg2_tools.yuv --mpeg2 --width 64 --height 64 --frames 7 --bframes --fields 0.3 --concealment --repeat --seed 1
+python3 "$gen/mpegenc.py" mpeg2_dual_prime.m2v mpeg2_dual_prime.yuv --mpeg2 --width 64 --height 64 --frames 6 --fields 0.5 --dual-prime --seed 1
+
+python3 "$gen/mux.py" ps mpeg1_ipb.m1v mpeg1_ipb.mpg --chunk 700
+python3 "$gen/mux.py" ts mpeg1_ipb.m1v mpeg1_ipb.ts --chunk 700
+python3 "$gen/two_programs.py" mpeg1_ipb.m1v mpeg1_dpictures.m1v two_programs.ts
@@ -0,0 +1,64 @@
+#!/usr/bin/env python3
+"""Two program transport stream: program 1 = video A (PID 0x100) + fake MPEG audio (0x101),
+program 2 = video B (PID 0x200). TS packets of the three streams are interleaved.
+usage: two_programs.py a.m1v b.m1v out.ts"""
+import os, sys
+sys.path.insert(0, os.path.dirname(os.path.abspath(__file__)))
+from mux import pes_packets, ts_packets, crc32_mpeg
+
+def section_packet(pid, table_id, ext, body, cc):
+    section = bytes([table_id]) + (0xb000 | (len(body) + 9)).to_bytes(2, 'big') + \
+        ext.to_bytes(2, 'big') + b'\xc1\x00\x00' + body
+    section += crc32_mpeg(section).to_bytes(4, 'big')
+    payload = b'\x00' + section
+    head = bytes([0x47, 0x40 | (pid >> 8), pid & 0xff, 0x10 | (cc & 0x0f)])
+    return head + payload + b'\xff' * (184 - len(payload))
+
+def es_pid(stream_type, pid, info=b''):
+    return bytes([stream_type]) + (0xe000 | pid).to_bytes(2, 'big') + (0xf000 | len(info)).to_bytes(2, 'big') + info
+
+a = open(sys.argv[1], 'rb').read()
+b = open(sys.argv[2], 'rb').read()
+# PAT: NIT entry, program 1 -> 0x1000, program 2 -> 0x1001
+pat = (0).to_bytes(2, 'big') + (0xe000 | 0x10).to_bytes(2, 'big') + \
+      (1).to_bytes(2, 'big') + (0xe000 | 0x1000).to_bytes(2, 'big') + \
+      (2).to_bytes(2, 'big') + (0xe000 | 0x1001).to_bytes(2, 'big')
+desc = b'\x0a\x04eng\x00'  # ISO 639 language descriptor
+pmt1 = (0xe000 | 0x100).to_bytes(2, 'big') + (0xf000 | len(desc)).to_bytes(2, 'big') + desc + \
+       es_pid(0x03, 0x101, desc) + es_pid(0x01, 0x100)
+pmt2 = (0xe000 | 0x200).to_bytes(2, 'big') + b'\xf0\x00' + es_pid(0x02, 0x200)
+
+def stream(es, pid, chunk):
+    out = []
+    cc = 0
+    for pes in pes_packets(es, chunk):
+        pkts, cc = ts_packets(pid, pes, cc)
+        out += pkts
+    return out
+
+va = stream(a, 0x100, 700)
+vb = stream(b, 0x200, 500)
+# audio: 24 PES packets of stream 0xC0 with 300 bytes of 0x55
+audio = []
+cc = 0
+for n in range(24):
+    body = b'\x0f' + b'\x55' * 300
+    pkts, cc = ts_packets(0x101, b'\x00\x00\x01\xc0' + len(body).to_bytes(2, 'big') + body, cc)
+    audio += pkts
+
+out = bytearray()
+n = 0
+psi = 0
+queues = [va, audio, vb]
+while any(queues):
+    if n % 20 == 0:
+        out += section_packet(0, 0x00, 1, pat, psi)
+        out += section_packet(0x1000, 0x02, 1, pmt1, psi)
+        out += section_packet(0x1001, 0x02, 2, pmt2, psi)
+        psi += 1
+    for q in queues:
+        if q:
+            out += q.pop(0)
+    n += 1
+open(sys.argv[3], 'wb').write(out)
+print(len(out) // 188, 'packets')
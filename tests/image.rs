@@ -0,0 +1,176 @@
+use ez_mpeg1::image::{ImageFormat, RgbImage};
+mod common;
+
+use common::{Picture, Sequence, PICTURE_I};
+use ez_mpeg1::video::{DecodeResult, Mpeg1Video};
+
+// every sample a different value, rows top to bottom
+fn test_image(width: u32, height: u32) -> RgbImage {
+    let data = (0..width * height * 3).map(|i| (i * 7 % 251) as u8).collect();
+    RgbImage { width, height, data }
+}
+
+fn encode(image: &RgbImage, format: ImageFormat) -> Vec<u8> {
+    let mut out = Vec::new();
+    image.write(&mut out, format).unwrap();
+    out
+}
+
+fn u32_le(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+fn u32_be(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+// bit by bit, written apart from the checksum module
+fn reference_crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn reference_adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[test]
+fn ppm_header_and_size() {
+    let image = test_image(5, 3);
+    let out = encode(&image, ImageFormat::Ppm);
+    assert!(out.starts_with(b"P6\n5 3\n255\n"));
+    assert_eq!(&out[11..], &image.data[..]);
+}
+
+#[test]
+fn bmp_rows_bottom_up_and_padded() {
+    // 5 pixels are 15 bytes, padded to 16
+    let image = test_image(5, 3);
+    let out = encode(&image, ImageFormat::Bmp);
+    assert_eq!(out.len(), 54 + 16 * 3);
+    assert_eq!(&out[..2], b"BM");
+    assert_eq!(u32_le(&out, 2), out.len() as u32);
+    assert_eq!(u32_le(&out, 10), 54);
+    assert_eq!(u32_le(&out, 14), 40);
+    assert_eq!((u32_le(&out, 18), u32_le(&out, 22)), (5, 3));
+    assert_eq!(u32_le(&out, 28) & 0xFFFF, 24);
+    assert_eq!(u32_le(&out, 34), 16 * 3);
+
+    for (line, pixels) in out[54..].chunks(16).zip(image.data.chunks(15).rev()) {
+        for (bgr, rgb) in line.chunks(3).zip(pixels.chunks(3)) {
+            assert_eq!(bgr, [rgb[2], rgb[1], rgb[0]]);
+        }
+        assert_eq!(line[15], 0);
+    }
+}
+
+// (type, data) of every chunk after the signature, checking each CRC
+fn png_chunks(out: &[u8]) -> Vec<(String, Vec<u8>)> {
+    assert_eq!(&out[..8], b"\x89PNG\r\n\x1a\n");
+    let mut chunks = Vec::new();
+    let mut pos = 8;
+    while pos < out.len() {
+        let len = u32_be(out, pos) as usize;
+        let kind = &out[pos + 4..pos + 8];
+        let data = &out[pos + 8..pos + 8 + len];
+        assert_eq!(u32_be(out, pos + 8 + len), reference_crc32(&out[pos + 4..pos + 8 + len]), "CRC of {:?}", kind);
+        chunks.push((String::from_utf8(kind.to_vec()).unwrap(), data.to_vec()));
+        pos += 12 + len;
+    }
+    chunks
+}
+
+// The data of a zlib stream of stored blocks, checking its Adler-32
+fn inflate_stored(zlib: &[u8]) -> Vec<u8> {
+    assert_eq!(&zlib[..2], [0x78, 0x01]);
+    assert_eq!(u16::from_be_bytes([zlib[0], zlib[1]]) % 31, 0);
+    let mut raw = Vec::new();
+    let mut pos = 2;
+    loop {
+        let last = zlib[pos] & 1 != 0;
+        assert_eq!(zlib[pos] >> 1, 0, "not a stored block");
+        let len = u16::from_le_bytes([zlib[pos + 1], zlib[pos + 2]]);
+        let nlen = u16::from_le_bytes([zlib[pos + 3], zlib[pos + 4]]);
+        assert_eq!(nlen, !len);
+        raw.extend_from_slice(&zlib[pos + 5..pos + 5 + len as usize]);
+        pos += 5 + len as usize;
+        if last {
+            break;
+        }
+    }
+    assert_eq!(zlib.len(), pos + 4);
+    assert_eq!(u32_be(zlib, pos), reference_adler32(&raw));
+    raw
+}
+
+fn assert_png(image: &RgbImage, blocks: usize) {
+    let out = encode(image, ImageFormat::Png);
+    let chunks = png_chunks(&out);
+    let kinds: Vec<&str> = chunks.iter().map(|(kind, _)| kind.as_str()).collect();
+    assert_eq!(kinds, ["IHDR", "IDAT", "IEND"]);
+
+    let ihdr = &chunks[0].1;
+    assert_eq!((u32_be(ihdr, 0), u32_be(ihdr, 4)), (image.width, image.height));
+    assert_eq!(&ihdr[8..], [8, 2, 0, 0, 0]);
+    assert!(chunks[2].1.is_empty());
+
+    let raw = inflate_stored(&chunks[1].1);
+    let stride = image.width as usize * 3;
+    assert_eq!(raw.len(), (stride + 1) * image.height as usize);
+    for (line, pixels) in raw.chunks(stride + 1).zip(image.data.chunks(stride)) {
+        assert_eq!(line[0], 0);
+        assert_eq!(&line[1..], pixels);
+    }
+    assert_eq!(raw.len().div_ceil(65535), blocks);
+}
+
+#[test]
+fn png_chunks_and_zlib_stream() {
+    assert_png(&test_image(5, 3), 1);
+    // 601 bytes a line are more than one stored block
+    assert_png(&test_image(200, 110), 2);
+}
+
+fn first_frame_image(aspect: Option<(u32, u32)>) -> RgbImage {
+    // the second picture puts out the first
+    let pictures = [Picture::new(PICTURE_I, 0), Picture::new(PICTURE_I, 1)];
+    let data = common::textured_stream(&Sequence::mpeg1(96, 64), &pictures);
+    let mut vcodec = Mpeg1Video::from_slice(&data);
+    assert!(matches!(vcodec.decode(), DecodeResult::GotOneFrame));
+    RgbImage::from_frame(&vcodec.frame().unwrap(), aspect)
+}
+
+#[test]
+fn aspect_correction_sizes() {
+    let sizes = |aspect| {
+        let image = first_frame_image(aspect);
+        assert_eq!(image.data.len(), (image.width * image.height * 3) as usize);
+        (image.width, image.height)
+    };
+    assert_eq!(sizes(None), (96, 64));
+    assert_eq!(sizes(Some((1, 1))), (96, 64));
+    // wide samples widen the picture, narrow ones heighten it, rounded
+    assert_eq!(sizes(Some((4, 3))), (128, 64));
+    assert_eq!(sizes(Some((10000, 9157))), (105, 64));
+    assert_eq!(sizes(Some((3, 4))), (96, 85));
+    assert_eq!(sizes(Some((200, 219))), (96, 70));
+}
+
+#[test]
+fn formats_by_extension() {
+    assert_eq!(ImageFormat::from_path("out.PNG"), Some(ImageFormat::Png));
+    assert_eq!(ImageFormat::from_path("dir.v1/out.bmp"), Some(ImageFormat::Bmp));
+    assert_eq!(ImageFormat::from_path("out.ppm"), Some(ImageFormat::Ppm));
+    assert_eq!(ImageFormat::from_path("out.jpg"), None);
+}
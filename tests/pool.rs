@@ -0,0 +1,100 @@
+mod common;
+
+use common::{Picture, Sequence, StreamWriter, BOTTOM_FIELD, PICTURE_B, PICTURE_I, PICTURE_P, TOP_FIELD};
+use ez_mpeg1::video::{DecodeResult, Mpeg1Video};
+
+// An interlaced 64x64 MPEG-2 stream of field picture pairs, I P B B P B B in
+// coding order. Every intra macroblock has its own texture, every other
+// macroblock of the P-pictures adds a coefficient to its prediction.
+fn field_stream() -> Vec<u8> {
+    let mut stream = StreamWriter::new();
+    stream.sequence_header(&Sequence::mpeg2(64, 64, false));
+    let pictures = [
+        (PICTURE_I, 0), (PICTURE_P, 3), (PICTURE_B, 1), (PICTURE_B, 2), (PICTURE_P, 6), (PICTURE_B, 4), (PICTURE_B, 5),
+    ];
+    for (index, &(coding_type, temporal_reference)) in pictures.iter().enumerate() {
+        for &structure in [TOP_FIELD, BOTTOM_FIELD].iter() {
+            stream.picture(&Picture { structure, ..Picture::new(coding_type, temporal_reference) });
+            for row in 0..2 {
+                stream.slice(row);
+                for col in 0..4 {
+                    let address = row as i32 * 4 + col;
+                    let seed = index * 16 + structure as usize * 8 + address as usize;
+                    if coding_type == PICTURE_P && col % 2 == 1 {
+                        stream.forward(address, &[&[(0, 1 + seed as i32 % 3)]]);
+                    } else {
+                        stream.textured(address, seed);
+                    }
+                }
+            }
+        }
+    }
+    stream.finish()
+}
+
+// frame() keeps returning the last output frame unchanged until the next one,
+// also once the first field of a pair went into the pool. With `hold` the
+// caller keeps it until the next call, which must not stop the second field.
+fn decode_checking_frame(data: &[u8], chunk: usize, hold: bool) -> Vec<Vec<u8>> {
+    let mut vcodec = Mpeg1Video::new();
+    let mut frames: Vec<Vec<u8>> = Vec::new();
+    let mut held = None;
+    let mut decode = |vcodec: &mut Mpeg1Video| loop {
+        let result = vcodec.decode();
+        held = None;
+        let frame = vcodec.frame();
+        let samples = frame.as_ref().map(|frame| common::samples(frame));
+        match result {
+            DecodeResult::GotOneFrame => frames.push(samples.unwrap()),
+            DecodeResult::NeedMoreData => {
+                assert_eq!(samples.as_ref(), frames.last(), "frame() changed after {} frames", frames.len());
+                if hold {
+                    held = frame;
+                }
+                break;
+            }
+            DecodeResult::InternalError => panic!("decoding failed after {} frames", frames.len()),
+        }
+    };
+    for piece in data.chunks(chunk) {
+        assert!(vcodec.push(piece).is_some(), "ring buffer full");
+        decode(&mut vcodec);
+    }
+    assert!(vcodec.end_of_stream().is_some(), "ring buffer full");
+    decode(&mut vcodec);
+    common::flush(&mut vcodec, &mut |vcodec| frames.push(common::samples(&vcodec.frame().unwrap())));
+    frames
+}
+
+#[test]
+fn frame_between_pushes() {
+    let data = field_stream();
+    let expected = common::decoded(&data);
+    assert_eq!(expected.len(), 7);
+    for &chunk in [20, 50, 333].iter() {
+        assert_eq!(decode_checking_frame(&data, chunk, false), expected, "in pieces of {}", chunk);
+        assert_eq!(decode_checking_frame(&data, chunk, true), expected, "held, in pieces of {}", chunk);
+    }
+}
+
+// Output frames held to the end keep the samples they came out with, the
+// decoder takes new buffers instead
+#[test]
+fn held_frames_stay_intact() {
+    let data = field_stream();
+    let expected = common::decoded(&data);
+    let mut vcodec = Mpeg1Video::new();
+    let mut held = Vec::new();
+    for piece in data.chunks(100) {
+        assert!(vcodec.push(piece).is_some(), "ring buffer full");
+        common::drain(&mut vcodec, &mut |vcodec| held.push(vcodec.frame().unwrap()));
+    }
+    assert!(vcodec.end_of_stream().is_some(), "ring buffer full");
+    common::drain(&mut vcodec, &mut |vcodec| held.push(vcodec.frame().unwrap()));
+    common::flush(&mut vcodec, &mut |vcodec| held.push(vcodec.frame().unwrap()));
+
+    let frames: Vec<Vec<u8>> = held.iter().map(|frame| common::samples(frame)).collect();
+    assert_eq!(frames, expected);
+    // the frames differ, a frame overwritten by a later one would show
+    assert!(frames.windows(2).all(|pair| pair[0] != pair[1]));
+}
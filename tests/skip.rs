@@ -0,0 +1,72 @@
+mod common;
+
+use common::{Picture, Sequence, StreamWriter, BOTTOM_FIELD, PICTURE_I, PICTURE_P, TOP_FIELD};
+
+// A 64x64 MPEG-1 stream, an I-picture of textured macroblocks, then a
+// P-picture in one slice that codes the macroblocks at `coded` as zero vector
+// predictions without residual and skips the rest
+fn skipped_stream(coded: &[i32]) -> Vec<u8> {
+    let mut stream = StreamWriter::new();
+    stream.sequence_header(&Sequence::mpeg1(64, 64));
+    stream.picture(&Picture::new(PICTURE_I, 0));
+    stream.slice(0);
+    for address in 0..16 {
+        stream.textured(address, address as usize);
+    }
+    stream.picture(&Picture::new(PICTURE_P, 1));
+    stream.slice(0);
+    for &address in coded.iter() {
+        stream.forward(address, &[]);
+    }
+    stream.finish()
+}
+
+#[test]
+fn skipped_macroblocks_match_zero_vector_predictions() {
+    let predicted = common::decoded(&skipped_stream(&(0..16).collect::<Vec<i32>>()));
+    assert_eq!(predicted.len(), 2);
+    // the P-picture repeats the I-picture
+    assert_eq!(predicted[0], predicted[1]);
+
+    // runs of one, across a row end, a whole row and up to the last macroblock
+    for coded in [&[0, 2, 7, 12, 15][..], &[0, 4, 8, 12, 15], &[0, 1, 2, 3, 14, 15]].iter() {
+        assert_eq!(common::decoded(&skipped_stream(coded)), predicted, "coded {:?}", coded);
+    }
+}
+
+// Only the first and last macroblock of the slice are coded
+#[test]
+fn all_skipped_picture_copies_the_reference() {
+    let frames = common::decoded(&skipped_stream(&[0, 15]));
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0], frames[1]);
+}
+
+// An interlaced 64x64 MPEG-2 frame of two I field pictures, then two P field
+// pictures coding only the first and last macroblock of each slice
+#[test]
+fn all_skipped_field_pictures_copy_the_reference() {
+    let mut stream = StreamWriter::new();
+    stream.sequence_header(&Sequence::mpeg2(64, 64, false));
+    for &structure in [TOP_FIELD, BOTTOM_FIELD].iter() {
+        stream.picture(&Picture { structure, ..Picture::new(PICTURE_I, 0) });
+        for row in 0..2 {
+            stream.slice(row);
+            for col in 0..4 {
+                let address = row as i32 * 4 + col;
+                stream.textured(address, (address + structure as i32) as usize);
+            }
+        }
+    }
+    for &structure in [TOP_FIELD, BOTTOM_FIELD].iter() {
+        stream.picture(&Picture { structure, ..Picture::new(PICTURE_P, 1) });
+        for row in 0..2 {
+            stream.slice(row);
+            stream.forward(row as i32 * 4, &[]);
+            stream.forward(row as i32 * 4 + 3, &[]);
+        }
+    }
+    let frames = common::decoded(&stream.finish());
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0], frames[1]);
+}
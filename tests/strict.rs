@@ -0,0 +1,82 @@
+mod common;
+
+use common::{Picture, Sequence, StreamWriter, FLAT, PICTURE_I, PICTURE_P};
+use ez_mpeg1::video::{Mpeg1Video, Severity, Violation};
+
+// A 32x16 I-picture of flat macroblocks and a P-picture that copies it with a
+// zero vector, its forward_f_code is `f_code`
+fn write_pictures(stream: &mut StreamWriter, f_code: u32) {
+    stream.picture(&Picture::new(PICTURE_I, 0));
+    stream.slice(0);
+    stream.intra(0, &FLAT);
+    stream.intra(1, &FLAT);
+
+    stream.picture(&Picture { f_code, ..Picture::new(PICTURE_P, 1) });
+    stream.slice(0);
+    stream.forward(0, &[]);
+    stream.forward(1, &[]);
+}
+
+// `sequences` one after the other, the pictures follow the last one
+fn stream(sequences: &[Sequence], f_code: u32) -> Vec<u8> {
+    let mut stream = StreamWriter::new();
+    for sequence in sequences.iter() {
+        stream.sequence_header(sequence);
+    }
+    write_pictures(&mut stream, f_code);
+    stream.finish()
+}
+
+fn decode(data: &[u8], strict: bool) -> Vec<Violation> {
+    let mut vcodec = Mpeg1Video::from_slice(data);
+    vcodec.set_strict(strict);
+    let mut frames = 0;
+    common::drain(&mut vcodec, &mut |_| frames += 1);
+    common::flush(&mut vcodec, &mut |_| frames += 1);
+    assert_eq!(frames, 2);
+    vcodec.take_violations()
+}
+
+fn messages(violations: &[Violation]) -> Vec<(Severity, &str)> {
+    violations.iter().map(|violation| (violation.severity, violation.message.as_str())).collect()
+}
+
+// 50x38 macroblocks at 30 Hz, 2 Mbit/s and a 480 kbit buffer
+fn unconstrained() -> Sequence {
+    Sequence { frame_rate_code: 5, bit_rate: 5000, vbv_buffer_size: 30, ..Sequence::mpeg1(800, 600) }
+}
+
+#[test]
+fn constrained_stream_has_no_violations() {
+    assert!(decode(&stream(&[Sequence::mpeg1(32, 16)], 4), true).is_empty());
+}
+
+#[test]
+fn constrained_parameters_out_of_range() {
+    // the small pictures follow their own header, the large one needs no pictures
+    let data = stream(&[unconstrained(), Sequence::mpeg1(32, 16)], 4);
+    let violations = decode(&data, true);
+    assert_eq!(messages(&violations), [
+        (Severity::Error, "constrained parameters: width 800 exceeds 768"),
+        (Severity::Error, "constrained parameters: height 600 exceeds 576"),
+        (Severity::Error, "constrained parameters: 1900 macroblocks exceed 396"),
+        (Severity::Error, "constrained parameters: 57000 macroblocks per second exceed 9900"),
+        (Severity::Error, "constrained parameters: bit rate 2000000 exceeds 1856000"),
+        (Severity::Error, "constrained parameters: vbv_buffer_size 30 exceeds 20"),
+    ]);
+    // found once the 12 byte header is read
+    assert!(violations.iter().all(|violation| violation.es_offset == 12));
+}
+
+#[test]
+fn constrained_parameters_f_code_out_of_range() {
+    let violations = decode(&stream(&[Sequence::mpeg1(32, 16)], 5), true);
+    assert_eq!(messages(&violations), [(Severity::Error, "constrained parameters: forward f_code 5 exceeds 4")]);
+}
+
+#[test]
+fn lenient_mode_collects_nothing() {
+    let data = stream(&[unconstrained(), Sequence::mpeg1(32, 16)], 5);
+    assert!(decode(&data, false).is_empty());
+    assert!(!decode(&data, true).is_empty());
+}
@@ -0,0 +1,114 @@
+mod common;
+
+use common::{Picture, Sequence, PICTURE_B, PICTURE_I, PICTURE_P};
+use ez_mpeg1::vbv::{self, VbvError, VbvParameters};
+use ez_mpeg1::video::{Mpeg1Video, PictureStats};
+
+// 100 kbit/s at 25 frames per second brings 4000 bits per frame
+const PARAMS: VbvParameters = VbvParameters {
+    bit_rate:    100_000,
+    buffer_size: 20_000,
+    frame_rate:  25.0,
+};
+
+// I-pictures of whole frames, in coding order
+fn pictures(vbv_delay: u32, bits: &[usize]) -> Vec<PictureStats> {
+    bits.iter().map(|&bits| PictureStats {
+        picture_type: 1,
+        picture_structure: 3,
+        vbv_delay,
+        fields: 2,
+        bits,
+        decoded: true,
+        ..Default::default()
+    }).collect()
+}
+
+fn assert_close(value: f64, expected: f64) {
+    assert!((value - expected).abs() < 1e-9, "{} is not {}", value, expected);
+}
+
+#[test]
+fn steady_stream_has_no_events() {
+    // decoding starts after 0.1 s with 10000 bits in the buffer
+    let events = vbv::verify(&PARAMS, &pictures(9000, &[8000, 4000, 4000, 4000, 4000, 4000]));
+    assert!(events.is_empty(), "{:?}", events);
+}
+
+#[test]
+fn large_picture_underflows() {
+    // at 0.14 s 14000 bits arrived, 8000 of them were the first picture
+    let events = vbv::verify(&PARAMS, &pictures(9000, &[8000, 30_000, 1000]));
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].error, events[0].picture, events[0].occupancy), (VbvError::Underflow, 1, 6000));
+    assert_close(events[0].time, 0.14);
+}
+
+#[test]
+fn small_pictures_overflow() {
+    // each small frame brings 3000 bits more than it takes, 10000 + 4 * 3000 don't fit
+    let bits = [&[1000; 5][..], &[4000; 6]].concat();
+    let events = vbv::verify(&PARAMS, &pictures(9000, &bits));
+    assert_eq!(events.len(), 2);
+    assert_eq!((events[0].error, events[0].picture, events[0].occupancy), (VbvError::Overflow, 4, 22_000));
+    assert_close(events[0].time, 0.26);
+    // what didn't fit is lost, the next frame overflows the full buffer again
+    assert_eq!((events[1].error, events[1].picture, events[1].occupancy), (VbvError::Overflow, 5, 23_000));
+}
+
+#[test]
+fn variable_bit_rate_never_overflows() {
+    // vbv_delay 0xFFFF starts with a full buffer that stops filling
+    let events = vbv::verify(&PARAMS, &pictures(0xFFFF, &[1000; 20]));
+    assert!(events.is_empty(), "{:?}", events);
+
+    // a picture larger than the buffer can't be in it when it is due
+    let events = vbv::verify(&PARAMS, &pictures(0xFFFF, &[1000, 30_000, 1000]));
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].error, events[0].picture), (VbvError::Underflow, 1));
+    // filled up again after the first picture
+    assert_eq!(events[0].occupancy, 20_000);
+}
+
+// A 32x16 MPEG-1 stream of I P B B P B B in coding order
+fn ipb_stream() -> Vec<u8> {
+    let pictures: Vec<Picture> = [(PICTURE_I, 0), (PICTURE_P, 3), (PICTURE_B, 1), (PICTURE_B, 2), (PICTURE_P, 6), (PICTURE_B, 4), (PICTURE_B, 5)]
+        .iter().map(|&(coding_type, temporal_reference)| Picture::new(coding_type, temporal_reference)).collect();
+    common::textured_stream(&Sequence::mpeg1(32, 16), &pictures)
+}
+
+fn picture_stats(data: &[u8]) -> Vec<PictureStats> {
+    let mut vcodec = Mpeg1Video::from_slice(data);
+    vcodec.set_stats(true);
+    common::drain(&mut vcodec, &mut |_| ());
+    common::flush(&mut vcodec, &mut |_| ());
+    vcodec.take_stats()
+}
+
+// Cut in front of the P-picture and followed by the whole stream again, the
+// pictures up to the I-picture lack reference pictures. They are skipped but
+// still have their stats.
+#[test]
+fn skipped_picture_stats() {
+    let data = ipb_stream();
+    let full = picture_stats(&data);
+    assert_eq!(full.len(), 7);
+    let pictures: Vec<usize> = (0..data.len() - 3).filter(|&at| data[at..at + 4] == [0x00, 0x00, 0x01, 0x00]).collect();
+    let header = pictures[0];
+    let cut = [&data[..header], &data[pictures[1]..], &data[header..]].concat();
+    let stats = picture_stats(&cut);
+
+    let skipped = full.len() - 1;
+    assert_eq!(stats.len(), skipped + full.len());
+    assert!(stats[..skipped].iter().all(|stats| !stats.decoded));
+    assert!(stats[skipped..].iter().all(|stats| stats.decoded));
+
+    let mut expected: Vec<_> = full[1..].iter().chain(full.iter())
+        .map(|stats| (stats.picture_type, stats.temporal_reference, stats.bits))
+        .collect();
+    expected[0].2 += header * 8;
+    // from the sequence_end_code of the first copy
+    expected[skipped].2 -= header * 8 - 32;
+    let coded: Vec<_> = stats.iter().map(|stats| (stats.picture_type, stats.temporal_reference, stats.bits)).collect();
+    assert_eq!(coded, expected);
+}
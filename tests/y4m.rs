@@ -0,0 +1,99 @@
+mod common;
+
+use common::{Picture, Sequence, BOTTOM_FIELD, PICTURE_I, PICTURE_P, TOP_FIELD};
+use ez_mpeg1::video::{CodecInfo, Mpeg1Video};
+use ez_mpeg1::y4m::{self, Y4mWriter};
+
+fn mpeg1_info(frame_rate_code: u32, aspect_ratio: u32) -> CodecInfo {
+    CodecInfo {
+        pic_width: 352,
+        pic_height: 288,
+        aspect_ratio,
+        frame_rate_code,
+        frame_rate_n: 1,
+        frame_rate_d: 1,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn frame_rates() {
+    assert_eq!(y4m::frame_rate(&mpeg1_info(1, 1)), "24000:1001");
+    assert_eq!(y4m::frame_rate(&mpeg1_info(3, 1)), "25:1");
+    assert_eq!(y4m::frame_rate(&mpeg1_info(4, 1)), "30000:1001");
+    assert_eq!(y4m::frame_rate(&mpeg1_info(8, 1)), "60:1");
+    // forbidden codes are unknown
+    assert_eq!(y4m::frame_rate(&mpeg1_info(0, 1)), "0:0");
+    assert_eq!(y4m::frame_rate(&mpeg1_info(9, 1)), "0:0");
+
+    // MPEG-2 frame_rate_extension scales the code, 30000:1001 * 2 / 1
+    let info = CodecInfo { frame_rate_n: 2, is_mpeg2: true, ..mpeg1_info(4, 1) };
+    assert_eq!(y4m::frame_rate(&info), "60000:1001");
+}
+
+#[test]
+fn pixel_aspect_ratios() {
+    // MPEG-1 pel_aspect_ratio is height : width of a sample
+    assert_eq!(y4m::pixel_aspect(&mpeg1_info(3, 1)), "1:1");
+    assert_eq!(y4m::pixel_aspect(&mpeg1_info(3, 8)), "10000:9157");
+    assert_eq!(y4m::pixel_aspect(&mpeg1_info(3, 12)), "200:219");
+    assert_eq!(y4m::pixel_aspect(&mpeg1_info(3, 0)), "0:0");
+    assert_eq!(y4m::pixel_aspect(&mpeg1_info(3, 15)), "0:0");
+
+    // MPEG-2 4:3 and 16:9 displays spread over 720x576
+    let info = CodecInfo { pic_width: 720, pic_height: 576, is_mpeg2: true, ..mpeg1_info(3, 2) };
+    assert_eq!(y4m::pixel_aspect(&info), "16:15");
+    let info = CodecInfo { aspect_ratio: 3, ..info };
+    assert_eq!(y4m::pixel_aspect(&info), "64:45");
+}
+
+fn write_y4m(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut writer = Y4mWriter::new(&mut out);
+    let mut vcodec = Mpeg1Video::from_slice(data);
+    common::drain(&mut vcodec, &mut |vcodec| writer.write_frame(vcodec).unwrap());
+    common::flush(&mut vcodec, &mut |vcodec| writer.write_frame(vcodec).unwrap());
+    writer.flush().unwrap();
+    out
+}
+
+// The stream header line and the size of every FRAME record after "FRAME\n"
+fn records(out: &[u8]) -> (String, Vec<usize>) {
+    let end = out.iter().position(|&byte| byte == b'\n').unwrap();
+    let header = String::from_utf8(out[..end].to_vec()).unwrap();
+    let mut starts = Vec::new();
+    let mut pos = end + 1;
+    while pos < out.len() {
+        assert_eq!(&out[pos..pos + 6], b"FRAME\n", "no frame at {}", pos);
+        starts.push(pos + 6);
+        pos = out[pos + 6..].windows(6).position(|window| window == b"FRAME\n").map_or(out.len(), |at| pos + 6 + at);
+    }
+    starts.push(out.len() + 6);
+    let sizes = starts.windows(2).map(|pair| pair[1] - pair[0] - 6).collect();
+    (header, sizes)
+}
+
+#[test]
+fn mpeg1_stream() {
+    let pictures = [Picture::new(PICTURE_I, 0), Picture::new(PICTURE_P, 1), Picture::new(PICTURE_P, 2)];
+    let out = write_y4m(&common::textured_stream(&Sequence::mpeg1(48, 32), &pictures));
+    let (header, sizes) = records(&out);
+    assert_eq!(header, "YUV4MPEG2 W48 H32 F25:1 Ip A1:1 C420jpeg");
+    // 48x32 luma and two 24x16 chroma planes
+    assert_eq!(sizes, [48 * 32 * 3 / 2; 3]);
+    assert_eq!(out.len(), header.len() + 1 + 3 * (6 + 2304));
+}
+
+#[test]
+fn mpeg2_interlaced_stream() {
+    let mut pictures = Vec::new();
+    for &(coding_type, temporal_reference) in [(PICTURE_I, 0), (PICTURE_P, 1)].iter() {
+        for &structure in [TOP_FIELD, BOTTOM_FIELD].iter() {
+            pictures.push(Picture { structure, ..Picture::new(coding_type, temporal_reference) });
+        }
+    }
+    let out = write_y4m(&common::textured_stream(&Sequence::mpeg2(64, 64, false), &pictures));
+    let (header, sizes) = records(&out);
+    assert_eq!(header, "YUV4MPEG2 W64 H64 F25:1 It A1:1 C420mpeg2");
+    assert_eq!(sizes, [64 * 64 * 3 / 2; 2]);
+}